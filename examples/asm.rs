@@ -11,6 +11,8 @@ pub fn main() -> Result<(), Box<dyn Error>> {
         MovVal(Register::EAX, 5),
     ])?;
 
+    builder.define_extern("callme");
+
     builder.write("tmp/asm.o", BinFormat::host())?;
 
     Ok(())
@@ -2,6 +2,8 @@
 
 use std::fmt;
 
+use crate::ir::verify::Violation;
+
 #[allow(non_snake_case)]
 
 /// An error type which can ocure during any CodeGenLib functions
@@ -10,7 +12,19 @@ use std::fmt;
 pub enum CodeGenLibError {
     VarNotExist(String),
     FuncNotExist(String),
+    LabelNotExist(String),
+    PassNotExist(String),
     UnsuportedInIntepr(String),
+    UnsuportedArg(String),
+    TypeMismatch(String),
+    /// The ssa ir isn't well formed
+    InvalidSsa(String),
+    /// Every error the verifier found in the ir
+    InvalidIr(Vec<Violation>),
+    /// The call can't be turned into a tail call
+    TailCallImpossible(String),
+    /// The object file can't be written in the binary format
+    UnsupportedFormat(String),
 }
 
 /// Result which stores T + CodeGenLibError
@@ -21,7 +35,23 @@ impl fmt::Display for CodeGenLibError {
         let msg = match self {
             CodeGenLibError::VarNotExist(x) => format!("var {x} doesn't exits"),
             CodeGenLibError::FuncNotExist(x) => format!("func {x} doesn't exits"),
+            CodeGenLibError::LabelNotExist(x) => format!("label {x} doesn't exits"),
+            CodeGenLibError::PassNotExist(x) => format!("pass {x} doesn't exits"),
             CodeGenLibError::UnsuportedInIntepr(x) => format!("{x} is unsuported in emulated jit"),
+            CodeGenLibError::UnsuportedArg(x) => format!("{x} can't be used as an argument"),
+            CodeGenLibError::TypeMismatch(x) => format!("mismatched types: {x}"),
+            CodeGenLibError::InvalidSsa(x) => format!("invalid ssa: {x}"),
+            CodeGenLibError::TailCallImpossible(x) => format!("{x} can't be tail called"),
+            CodeGenLibError::UnsupportedFormat(x) => format!("the binary format {x} is unsupported"),
+            CodeGenLibError::InvalidIr(violations) => {
+                let mut msg = format!("the ir contains {} error(s):", violations.len());
+
+                for violation in violations {
+                    msg.push_str(&format!("\n  {violation}"));
+                }
+
+                msg
+            },
         };
 
         write!(f, "{}", msg)
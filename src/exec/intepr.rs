@@ -83,10 +83,19 @@ fn intpr(ir: &Vec<AsmInstructionEnum>, funcs: &HashMap<String, (bool, Vec<AsmIns
             },
             AsmInstructionEnum::MovReg(_, _) => todo!(),
             AsmInstructionEnum::MovPtr(_, _) => todo!(),
+            AsmInstructionEnum::MovSx(_, _) => todo!(),
             AsmInstructionEnum::Store(_, _) => todo!(),
             AsmInstructionEnum::Load(_, _) => todo!(),
+            AsmInstructionEnum::Lea(_, _) => todo!(),
             AsmInstructionEnum::Call(_) => todo!(),
             AsmInstructionEnum::Jmp(_) => todo!(),
+            AsmInstructionEnum::JmpReg(_) => todo!(),
+            AsmInstructionEnum::Label(_) => {},
+            AsmInstructionEnum::Loc(_) => {},
+            AsmInstructionEnum::CmpVal(_, _) => todo!(),
+            AsmInstructionEnum::CmpReg(_, _) => todo!(),
+            AsmInstructionEnum::Je(_) | AsmInstructionEnum::Jne(_) | AsmInstructionEnum::Jl(_) |
+            AsmInstructionEnum::Jg(_) | AsmInstructionEnum::Jb(_) | AsmInstructionEnum::Ja(_) => todo!(),
             AsmInstructionEnum::Inc(_) => todo!(),
             AsmInstructionEnum::Dec(_) => todo!(),
             AsmInstructionEnum::IncMem(_) => todo!(),
@@ -97,12 +106,19 @@ fn intpr(ir: &Vec<AsmInstructionEnum>, funcs: &HashMap<String, (bool, Vec<AsmIns
             AsmInstructionEnum::SubVal(_, _) => todo!(),
             AsmInstructionEnum::SubReg(_, _) => todo!(),
             AsmInstructionEnum::SubMem(_, _) => todo!(),
+            AsmInstructionEnum::XorReg(_, _) => todo!(),
             AsmInstructionEnum::MulVal(_, _) => todo!(),
             AsmInstructionEnum::MulReg(_, _) => todo!(),
             AsmInstructionEnum::MulMem(_, _) => todo!(),
             AsmInstructionEnum::DivVal(_, _) => todo!(),
             AsmInstructionEnum::DivReg(_, _) => todo!(),
             AsmInstructionEnum::DivMem(_, _) => todo!(),
+            AsmInstructionEnum::Cqo | AsmInstructionEnum::Cdq => todo!(),
+            AsmInstructionEnum::Idiv(_) => todo!(),
+            AsmInstructionEnum::UDivVal(_, _) | AsmInstructionEnum::RemVal(_, _) | AsmInstructionEnum::URemVal(_, _) => todo!(),
+            AsmInstructionEnum::Mul(_) | AsmInstructionEnum::Imul(_) => todo!(),
+            AsmInstructionEnum::ShlVal(_, _) | AsmInstructionEnum::ShrVal(_, _) | AsmInstructionEnum::SarVal(_, _) => todo!(),
+            AsmInstructionEnum::ShlCl(_) | AsmInstructionEnum::ShrCl(_) | AsmInstructionEnum::SarCl(_) => todo!(),
             AsmInstructionEnum::Push(_) => todo!(),
             AsmInstructionEnum::PushVal(_) => todo!(),
             AsmInstructionEnum::PushLabel(_) => todo!(),
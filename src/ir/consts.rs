@@ -0,0 +1,57 @@
+//! Build time constant propagation for the variables of the `IrFunctionBuilder`
+
+/// The stack slots whose values are known while the function is built
+///
+/// Only straight-line code is tracked, every label forgets the values
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KnownValues {
+    /// (stack offset, size in bytes, value truncated to the size)
+    slots: Vec<(i64, usize, i64)>,
+
+    /// The stack ranges whose address was taken (they can be written through the pointer)
+    escaped: Vec<(i64, usize)>,
+}
+
+/// Truncates the value to `size` bytes (the rest is zero)
+pub fn truncate(value: i64, size: usize) -> i64 {
+    if size >= 8 {
+        value
+    } else {
+        value & ((1i64 << (size * 8)) - 1)
+    }
+}
+
+fn overlaps(a: (i64, usize), b: (i64, usize)) -> bool {
+    a.0 < b.0 + b.1 as i64 && b.0 < a.0 + a.1 as i64
+}
+
+impl KnownValues {
+    /// Returns the value of the `size` bytes at the stack offset (if it is known)
+    pub fn get(&self, offset: i64, size: usize) -> Option<i64> {
+        self.slots.iter()
+            .find(|slot| slot.0 == offset && slot.1 == size)
+            .map(|slot| slot.2)
+    }
+
+    /// Records a store of `size` bytes to the stack offset (`None` if the stored value isn't known)
+    pub fn set(&mut self, offset: i64, size: usize, value: Option<i64>) {
+        self.slots.retain(|slot| !overlaps((slot.0, slot.1), (offset, size)));
+
+        if let Some(value) = value {
+            if !self.escaped.iter().any(|range| overlaps(*range, (offset, size))) {
+                self.slots.push((offset, size, truncate(value, size)));
+            }
+        }
+    }
+
+    /// Records that the address of the stack range was taken, so its value is never known again
+    pub fn escape(&mut self, offset: i64, size: usize) {
+        self.set(offset, size, None);
+        self.escaped.push((offset, size));
+    }
+
+    /// Forgets every value (at join points of the control flow)
+    pub fn clear(&mut self) {
+        self.slots.clear();
+    }
+}
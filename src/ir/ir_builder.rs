@@ -1,12 +1,21 @@
 use std::error::Error;
 
-use iced_x86::Register;
+use iced_x86::{MemoryOperand, Register};
 
 use crate::{
-    target::{Abi, Target}, error::CodeGenLibError, Builder
+    target::{Abi, Target}, error::CodeGenLibError, opt::{frame_escapes, tail::copy_stack_args, OptLevel, PassManager}, x86::attrs::FuncAttrs, Builder,
+    ssa::{isel::jump, CmpOp},
 };
 
-pub use super::{Type, AsmInstructionEnum::{self, *}};
+pub use super::{SourceLoc, Type, AsmInstructionEnum::{self, *}};
+
+use super::consts::{truncate, KnownValues};
+
+/// The name of the hidden argument which points to the memory for large return values
+const SRET: &str = ".sret";
+
+/// The name of the copies of arguments which are passed by reference
+const TEMP: &str = ".temp";
 
 /// A struct which builds a function's ir
 #[derive(Debug, Clone)]
@@ -16,11 +25,23 @@ pub struct IrFunctionBuilder {
     args: Vec<((String, u64, Option<Register>, Type), u64)>,
     vars: Vec<(String, i64, Type)>, // i64 -> stack offset
     funcs: Vec<(String, Vec<Type>)>,
+    ret: Option<Type>,
     public: bool,
+    attrs: FuncAttrs,
+
+    /// The source location of the instructions which are built next
+    loc: Option<SourceLoc>,
+
+    /// The variables whose values are known at build time
+    known: KnownValues,
 
     /// for label names
     parsed_label_args: usize,
 
+    /// The size of the variables and of the temporaries of the current call (below them)
+    frame: i64,
+    temps: i64,
+
     abi: Abi,
 
     pub builder: Builder,
@@ -37,89 +58,139 @@ impl IrFunctionBuilder {
             args: vec![],
             vars: vec![],
             funcs: vec![],
+            ret: None,
 
             public: false,
+            attrs: FuncAttrs::default(),
+            loc: None,
+            known: KnownValues::default(),
 
             builder: builder.to_owned(),
 
             abi: abi.to_owned(),
 
             parsed_label_args: 0,
+
+            frame: 0,
+            temps: 0,
         }
     }
 
     /// The input tuple values: `(String, u64)` represent:
     ///  * `String` -> The argument name
     ///  * `u64` -> The argument size in bytes
+    /// 
+    /// Which argument is passed in which register or stack slot is decided by the abi
     pub fn args(&mut self, args: Vec<(&str, Type)>) {
         let mut mod_args: Vec<((String, u64, Option<Register>, Type), u64)> = vec![];
 
-        let mut reg_pasted_args = 0;
+        let mut args = args;
 
-        let mut prev_size = 0;
+        if self.sret() { // the pointer to the return memory is passed as the first argument
+            args.insert(0, (SRET, Type::Ptr(Box::from( Type::u64(0) ))));
+        }
 
-        for arg in args {
-            let reg: Option<Register> = {
-                if reg_pasted_args < self.abi.reg_args() && arg.1.in_reg() {
-                    reg_pasted_args += 1;
+        let types: Vec<Type> = args.iter().map(|arg| arg.1.to_owned()).collect();
+        let regs = self.abi.arg_regs(&types);
 
-                    if arg.1.size() == 8 { // u64/i64/str
-                        Some(self.abi.arg64(reg_pasted_args - 1))
-                    } else {
-                        Some(self.abi.arg32(reg_pasted_args - 1))
-                    }
+        let mut stack_args = 0;
 
-                } else {
-                    None
+        for (arg, nr) in args.into_iter().zip(regs) {
+            let reg: Option<Register> = nr.map(|nr| {
+                if matches!(arg.1, Type::u32(_) | Type::i32(_)) {
+                    self.abi.arg32(nr)
+                } else { // u64/i64/str, aggregates which are passed by value or a pointer to them
+                    self.abi.arg64(nr)
                 }
-            };
+            });
 
-            mod_args.push(((arg.0.into(), arg.1.size(), reg, arg.1.clone()), prev_size));
+            let stack_pos = match reg {
+                Some(_) => 0,
+                None => {
+                    stack_args += 1;
+                    self.abi.stack_arg(stack_args - 1)
+                },
+            };
 
-            prev_size += arg.1.size();
+            mod_args.push(((arg.0.into(), arg.1.size(), reg, arg.1.clone()), stack_pos as u64));
         }
 
         self.args = mod_args;
     }
 
+    /// Sets the type of the return value (default: 8 byte int)
+    /// 
+    /// !Needs to be called before vars !
+    /// 
+    /// Values which don't fit into the return registers get written into memory
+    /// the caller passes a pointer to as a hidden first argument
+    pub fn set_ret(&mut self, typ: Type) {
+        self.ret = Some(typ);
+
+        let args: Vec<(String, Type)> = self.args.iter()
+            .filter(|arg| arg.0.0 != SRET)
+            .map(|arg| (arg.0.0.to_owned(), arg.0.3.to_owned()))
+            .collect();
+
+        self.args(args.iter().map(|arg| (arg.0.as_str(), arg.1.to_owned())).collect());
+    }
+
+    /// Returns if the return value is returned via the hidden struct return pointer
+    fn sret(&self) -> bool {
+        match &self.ret {
+            Some(typ) => self.abi.ret_regs(typ.size()) == 0,
+            None => false,
+        }
+    }
+
     /// !Needs to be called after setuped args !
     /// The input tuple values: `(String, u64)` represent:
     ///  * `String` -> The argument name
     ///  * `u64` -> The var size in bytes
+    /// 
+    /// Register arguments get stored in their own stack slot, stack arguments
+    /// are used from the callers frame (`[rbp + 16 + shadow space + n * 8]`)
+    /// 
+    /// The slot of arguments which are passed by reference holds the pointer,
+    /// it gets dereferenced on every access
     pub fn vars(&mut self, vars: Vec<(&str, Type)>) {
         let mut mod_vars: Vec<(String, i64, Type)> = vec![];
-        let mut stack_args: Vec<(String, u64, Type)> = vec![];
 
-        let mut stack_offset: i64 = 8;
+        let mut stack_offset: i64 = 0;
+
+        let mut alloc = |size: i64| {
+            let align = size.clamp(1, 8);
+
+            stack_offset = (stack_offset + size + align - 1) / align * align;
+            -stack_offset
+        };
 
         for arg in self.args.iter() {
             let name = &arg.0.0;
-            let size = arg.0.1;
 
-            if arg.0.2.is_some() {
-                self.generated
-                   .push(Store(arg.0.2.unwrap(), self.abi.stack(-stack_offset)));
+            match arg.0.2 {
+                Some(reg) => {
+                    let size = if self.abi.by_ref(&arg.0.3) { 8 } else { arg.0.3.slot_size().max(reg.size() as u64) };
+                    let offset = alloc(size as i64);
 
-                mod_vars.push((name.into(), -stack_offset, arg.0.3.clone()));
-            } else {
-               stack_args.push((name.into(), size, arg.0.3.clone()));
-               mod_vars.push((name.to_string(), stack_offset, arg.0.3.clone()));
-            }
+                    self.generated
+                       .push(Store(reg, self.abi.stack(offset)));
 
-            stack_offset += size as i64;
-        }
-
-        for var in stack_args {
-            mod_vars.push((var.0, var.1 as i64, var.2));
+                    mod_vars.push((name.into(), offset, arg.0.3.clone()));
+                },
+                None => mod_vars.push((name.into(), arg.1 as i64, arg.0.3.clone())),
+            }
         }
 
         for var in vars {
-            mod_vars.push((var.0.into(), -stack_offset, var.1.clone()));
+            let offset = alloc(var.1.slot_size() as i64);
 
-            stack_offset += var.1.size() as i64;
+            mod_vars.push((var.0.into(), offset, var.1.clone()));
         }
 
         self.vars = mod_vars;
+        self.frame = stack_offset;
+        self.known = KnownValues::default();
     }
 
     /// Sets the extern functions
@@ -180,29 +251,155 @@ impl IrFunctionBuilder {
         var1: &str,
         var2: &str,
         result_var: &str,
+    ) -> Result<(), CodeGenLibError> {
+        self.gen_binary(var1, var2, result_var, i64::wrapping_add, AddMem)
+    }
+
+    /// Builds a sub which does:
+    /// 
+    /// ```
+    /// result_var = var1 - var2
+    /// ```
+    pub fn build_sub(&mut self, var1: &str, var2: &str, result_var: &str) -> Result<(), CodeGenLibError> {
+        self.gen_binary(var1, var2, result_var, i64::wrapping_sub, SubMem)
+    }
+
+    /// Builds a mul which does:
+    /// 
+    /// ```
+    /// result_var = var1 * var2
+    /// ```
+    pub fn build_mul(&mut self, var1: &str, var2: &str, result_var: &str) -> Result<(), CodeGenLibError> {
+        self.gen_binary(var1, var2, result_var, i64::wrapping_mul, MulMem)
+    }
+
+    /// Generates the operation with the size of the result variable
+    /// (it is folded if the values of both variables are known)
+    fn gen_binary(
+        &mut self,
+        var1: &str,
+        var2: &str,
+        result_var: &str,
+        fold: fn(i64, i64) -> i64,
+        instr: fn(Register, MemoryOperand) -> AsmInstructionEnum,
     ) -> Result<(), CodeGenLibError> {
         let var1 = self.get_var(var1.into())?;
         let var2 = self.get_var(var2.into())?;
         let ret = self.get_var(result_var.into())?;
 
+        let size = ret.2.size() as usize;
+        let reg = if size == 4 { Register::EAX } else { Register::RAX };
+
+        let known = |var: &(String, i64, Type)| if var.2.size() as usize == size { self.known.get(var.1, size) } else { None };
+
+        if let (Some(a), Some(b)) = (known(&var1), known(&var2)) {
+            let value = truncate(fold(a, b), size);
+
+            self.generated.push(MovVal(reg, value));
+            self.generated.push(Store(reg, self.abi.stack(ret.1)));
+            self.known.set(ret.1, size, Some(value));
+
+            return Ok(());
+        }
 
         self.generated
-            .push(Load(Register::RAX, self.abi.stack(var1.1)));
+            .push(Load(reg, self.abi.stack(var1.1)));
         self.generated
-            .push(AddMem(Register::RAX, self.abi.stack(var2.1) ));
+            .push(instr(reg, self.abi.stack(var2.1)));
 
         self.generated
-            .push(Store(Register::RAX, self.abi.stack(ret.1)));
+            .push(Store(reg, self.abi.stack(ret.1)));
+        self.known.set(ret.1, size, None);
+
+        Ok(())
+    }
+
+    /// Jumps to the label `label` if the comparison `var1 op var2` is true
+    /// (it is decided at build time if the values of both variables are known)
+    pub fn build_branch(&mut self, var1: &str, op: CmpOp, var2: &str, label: &str) -> Result<(), CodeGenLibError> {
+        let var1 = self.get_var(var1.into())?;
+        let var2 = self.get_var(var2.into())?;
+
+        let size = var1.2.size() as usize;
+
+        if var2.2.size() as usize != size || !(size == 4 || size == 8) {
+            return Err(CodeGenLibError::TypeMismatch(format!("can't compare {} with {}", var1.0, var2.0)));
+        }
+
+        if let (Some(a), Some(b)) = (self.known.get(var1.1, size), self.known.get(var2.1, size)) {
+            // the values are compared as 64 bit
+            let (a, b) = if size == 4 { (a as i32 as i64, b as i32 as i64) } else { (a, b) };
+
+            if compare(op, a, b) {
+                self.build_jmp(label);
+            }
+
+            return Ok(());
+        }
+
+        let (a, b) = if size == 4 { (Register::EAX, Register::R11D) } else { (Register::RAX, Register::R11) };
+        let (jcc, swap) = jump(op);
+
+        self.generated.push(Load(a, self.abi.stack(var1.1)));
+        self.generated.push(Load(b, self.abi.stack(var2.1)));
+        self.generated.push(CmpReg(a, b));
+
+        if swap { // the jump is taken if the comparison is false
+            let skip = self.new_label();
+
+            self.generated.push(jcc(skip.to_owned()));
+            self.generated.push(Jmp(label.into()));
+            self.generated.push(Label(skip));
+        } else {
+            self.generated.push(jcc(label.into()));
+        }
 
         Ok(())
     }
 
     /// Returns the variable with the name `var_name`
+    /// 
+    /// Values with up to 16 bytes are returned in registers (if the abi allows it),
+    /// larger ones get copied into the memory of the hidden struct return pointer
     pub fn build_return_var(&mut self, var_name: &str) -> Result<(), CodeGenLibError> {
         let var = self.get_var(var_name.into())?;
 
-        self.generated
-           .push(Load(self.abi.ret_reg(), self.abi.stack(var.1)));
+        let size = var.2.size();
+
+        match self.abi.ret_regs(size) {
+            1 => {
+                let reg = if size == 4 { Register::EAX } else { self.abi.ret_reg() };
+
+                match self.known.get(var.1, reg.size()) {
+                    Some(value) => self.generated.push(MovVal(reg, value)),
+                    None => self.generated.push(Load(reg, self.abi.stack(var.1))),
+                }
+            },
+            2 => {
+                self.generated
+                   .push(Load(self.abi.ret_reg(), self.abi.stack(var.1)));
+                self.generated
+                   .push(Load(self.abi.return_reg2.unwrap(), self.abi.stack(var.1 + 8)));
+            },
+            _ => {
+                let sret = self.get_var(SRET.into())?;
+
+                self.generated
+                   .push(Load(self.abi.ret_reg(), self.abi.stack(sret.1)));
+
+                let (base, displ) = self.var_base(&var, Register::R10);
+
+                // the pointer to the memory is also returned
+                for (offset, size) in parts(size as usize) {
+                    let offset = offset as i64;
+
+                    self.generated
+                       .push(Load(sized(Register::R11, size), self.abi.ptr(base, displ + offset)));
+                    self.generated
+                       .push(Store(sized(Register::R11, size), self.abi.ptr(self.abi.ret_reg(), offset)));
+                }
+            },
+        }
 
         self.generated.push( Ret );
 
@@ -219,156 +416,241 @@ impl IrFunctionBuilder {
         Ok(())
     }
 
-    pub fn gen_x_arg_for_func(&mut self, name: &str, index: usize, ref original_arg: Type, prev_args: &Vec<Type>) -> Result<(), CodeGenLibError> {
-        // prepare func
-        let mut func: (String, Vec<Type>) = (String::new(), vec![]);
+    /// Returns the label name for the next constant which gets stored in the data section
+    fn new_label(&mut self) -> String {
+        let label_name = format!("{}.{}", self.name, self.parsed_label_args);
 
-        for _func in self.funcs.iter() {
-            if _func.0 == name {
-                func = _func.to_owned();
-            }
-        }
-        if func.0 == String::new() { // Still uninitalised
-            return Err(CodeGenLibError::FuncNotExist(name.into()));
-        }
+        self.parsed_label_args += 1;
 
-        let mut used_regs = 0;
+        label_name
+    }
+
+    /// Moves the address of the variable `name` into `reg`
+    /// 
+    /// Its value can be changed through the pointer, so it is never known again
+    fn gen_address_of(&mut self, name: String, reg: Register) -> Result<(), CodeGenLibError> {
+        let var = self.get_var(name)?;
+        self.known.escape(var.1, var.2.slot_size() as usize);
 
-        let mut arg = original_arg.clone();
+        if self.is_ref(&var) {
+            self.generated.push(Load(reg, self.abi.stack(var.1)));
+        } else {
+            self.generated.push(Lea(reg, self.abi.stack(var.1)));
+        }
 
-        for mut _arg in prev_args.to_owned() {
+        Ok(())
+    }
 
-            if _arg.empty() == Type::InVar(String::new()) {      
-                let var = self.get_var(
-                    match arg {
-                        Type::InVar(x) => x.to_owned(),
-                        _ => "".into(),
-                    }
-                )?;
+    /// Returns if the variable is an argument which is passed by reference
+    fn is_ref(&self, var: &(String, i64, Type)) -> bool {
+        self.args.iter().any(|arg| arg.0.0 == var.0 && self.abi.by_ref(&arg.0.3))
+    }
 
-                _arg = var.2.clone();
-                arg = var.2;
-            }
+    /// Returns the base register and the displacement of the memory of the variable
+    /// (the pointer of arguments which are passed by reference gets loaded into `reg`)
+    fn var_base(&mut self, var: &(String, i64, Type), reg: Register) -> (Register, i64) {
+        if self.is_ref(var) {
+            self.generated.push(Load(reg, self.abi.stack(var.1)));
 
-            if _arg.in_reg() {
-                used_regs += 1;
-            }
+            (reg, 0)
+        } else {
+            (Register::RBP, self.abi.stack(var.1).displacement)
         }
+    }
 
-        if arg.empty() == Type::InVar(String::new()) {
-            println!("{:?}", original_arg);
-            let var = self.get_var(
-                match original_arg {
-                    Type::InVar(x) => x.to_owned(),
-                    _ => "".into(),
-                }
-            )?;
-
-            match var.2 {
-                Type::u64(_) => { 
-                    if arg.in_reg() && used_regs <= self.abi.reg_args() {
-                        self.generated.push(Store(self.abi.arg64(used_regs), self.abi.stack(var.1)));
-                    } else {
-                        self.generated.push(Load(Register::RAX, self.abi.stack(var.1)));
-                        self.generated.push(Push(Register::RAX));
-                    }
-                },
-                Type::u32(_) => { 
-                    if arg.in_reg() && used_regs <= self.abi.reg_args() {
-                        self.generated.push(Store(self.abi.arg32(used_regs), self.abi.stack(var.1)));
-                    } else {
-                        self.generated.push(Load(Register::RAX, self.abi.stack(var.1)));
-                        self.generated.push(Push(Register::RAX));
-                    }
-                },
-                Type::i64(_) => { 
-                    if arg.in_reg() && used_regs <= self.abi.reg_args() {
-                        self.generated.push(Store(self.abi.arg64(used_regs), self.abi.stack(var.1)));
-                    } else {
-                        self.generated.push(Load(Register::RAX, self.abi.stack(var.1)));
-                        self.generated.push(Push(Register::RAX));
-                    }
-                },
-                Type::i32(_) => { 
-                    if arg.in_reg() && used_regs <= self.abi.reg_args() {
-                        self.generated.push(Store(self.abi.arg32(used_regs), self.abi.stack(var.1)));
-                    } else {
-                        self.generated.push(Load(Register::RAX, self.abi.stack(var.1)));
-                        self.generated.push(Push(Register::RAX));
-                    }
-                },
-                Type::Bytes(_) => todo!(),
-                Type::Str(_) => todo!(),
-                Type::Ptr(_) => todo!(),
-                _ => {},
-            }
-        } else if used_regs <= self.abi.reg_args() && arg.in_reg() {
-            match arg {
-                Type::u32(val) =>   {self.generated.push(MovVal(self.abi.arg32(used_regs), val as i64)); },
-                Type::i32(val) =>   {self.generated.push(MovVal(self.abi.arg32(used_regs), val as i64)); },
-                Type::u64(val) =>   {self.generated.push(MovVal(self.abi.arg64(used_regs), val as i64)); },
-                Type::i64(val) =>   {self.generated.push(MovVal(self.abi.arg64(used_regs), val as i64)); },
-                Type::Str(content) => {
-                    let label_name = format!("{}.{}", self.name, self.parsed_label_args);
+    /// Returns a temporary with the type `typ` below the variables, which lives until the end of the call
+    fn alloc_temp(&mut self, typ: Type) -> (String, i64, Type) {
+        self.temps += typ.slot_size() as i64;
 
-                    self.parsed_label_args += 1;
+        let temp = (TEMP.to_owned(), -((self.frame + 7) / 8 * 8 + self.temps), typ);
 
-                    self.builder.define_label(&label_name, false, content);
+        if !self.vars.iter().any(|var| var.1 == temp.1 && var.2.size() >= temp.2.size()) {
+            self.vars.push(temp.clone());
+        }
 
-                    self.generated.push(MovPtr(self.abi.arg64(index), label_name));
-                },
-                Type::Ptr(content) => {
-                    let label_name = format!("{}.{}", self.name, self.parsed_label_args);
+        temp
+    }
 
-                    self.parsed_label_args += 1;
+    /// Replaces `Type::InVar` with the type of the variable
+    fn arg_type(&self, arg: &Type) -> Result<Type, CodeGenLibError> {
+        match arg {
+            Type::InVar(name) => Ok(self.get_var(name.into())?.2),
+            arg => Ok(arg.to_owned()),
+        }
+    }
 
+    /// Moves the argument `arg` into the argument register with the number `nr`
+    fn gen_reg_arg(&mut self, nr: usize, arg: Type) -> Result<(), CodeGenLibError> {
+        match arg {
+            Type::u32(val) => self.generated.push(MovVal(self.abi.arg32(nr), val as i64)),
+            Type::i32(val) => self.generated.push(MovVal(self.abi.arg32(nr), val as i64)),
+            Type::u64(val) => self.generated.push(MovVal(self.abi.arg64(nr), val as i64)),
+            Type::i64(val) => self.generated.push(MovVal(self.abi.arg64(nr), val)),
+            Type::Str(content) => {
+                let label_name = self.new_label();
+                self.builder.define_label(&label_name, false, content);
+
+                self.generated.push(MovPtr(self.abi.arg64(nr), label_name));
+            },
+            Type::Ptr(content) => {
+                if let Type::InVar(target) = *content { // address of the variable
+                    self.gen_address_of(target, self.abi.arg64(nr))?;
+                } else {
+                    let label_name = self.new_label();
                     self.builder.define_label(&label_name, false, content.bytes());
 
-                    self.generated.push(MovPtr(self.abi.arg64(index), label_name));
-                },
-                _ => {},
-            };
+                    self.generated.push(MovPtr(self.abi.arg64(nr), label_name));
+                }
+            },
+            Type::Bytes(content) => self.gen_aggregate_arg(Type::Bytes(content), self.abi.arg64(nr))?,
+            Type::InVar(name) => {
+                let var = self.get_var(name)?;
 
-        } else {
-            match arg {
-                Type::Str(content) => {
-                    let label_name = format!("{}.{}",self.name, self.parsed_label_args);
+                if let Type::Bytes(_) = var.2 {
+                    return self.gen_aggregate_arg(Type::InVar(var.0), self.abi.arg64(nr));
+                }
 
-                    self.parsed_label_args += 1;
+                let reg = match var.2.size() {
+                    4 => self.abi.arg32(nr),
+                    8 => self.abi.arg64(nr),
+                    _ => return Err(CodeGenLibError::UnsuportedArg(var.0)),
+                };
 
-                    self.builder.define_label(&label_name, false, content);
+                match self.known.get(var.1, reg.size()) {
+                    Some(value) => self.generated.push(MovVal(reg, value)),
+                    None => self.generated.push(Load(reg, self.abi.stack(var.1))),
+                }
+            },
+            arg => return Err(CodeGenLibError::UnsuportedArg(format!("{:?}", arg))),
+        };
 
-                    self.generated.push(PushPtr(label_name));
-                },
-                Type::Ptr(content) => {
-                    let label_name = format!("{}.{}", self.name, self.parsed_label_args);
-                    
-                    self.parsed_label_args += 1;
+        Ok(())
+    }
 
+    /// Pushes the argument `arg` as an 8 byte stack slot
+    fn gen_stack_arg(&mut self, arg: Type) -> Result<(), CodeGenLibError> {
+        match arg {
+            Type::u32(val) => {
+                if val <= i32::MAX as u32 {
+                    self.generated.push(PushVal(val as i64));
+                } else {
+                    self.generated.push(MovVal(Register::RAX, val as i64));
+                    self.generated.push(Push(Register::RAX));
+                }
+            },
+            Type::i32(val) => self.generated.push(PushVal(val as i64)),
+            Type::u64(val) => {
+                if val <= i32::MAX as u64 {
+                    self.generated.push(PushVal(val as i64));
+                } else {
+                    self.generated.push(MovVal(Register::RAX, val as i64));
+                    self.generated.push(Push(Register::RAX));
+                }
+            },
+            Type::i64(val) => {
+                if val >= i32::MIN as i64 && val <= i32::MAX as i64 {
+                    self.generated.push(PushVal(val));
+                } else {
+                    self.generated.push(MovVal(Register::RAX, val));
+                    self.generated.push(Push(Register::RAX));
+                }
+            },
+            Type::Str(content) => {
+                let label_name = self.new_label();
+                self.builder.define_label(&label_name, false, content);
+
+                self.generated.push(MovPtr(Register::RAX, label_name));
+                self.generated.push(Push(Register::RAX));
+            },
+            Type::Ptr(content) => {
+                if let Type::InVar(target) = *content { // address of the variable
+                    self.gen_address_of(target, Register::RAX)?;
+                } else {
+                    let label_name = self.new_label();
                     self.builder.define_label(&label_name, false, content.bytes());
 
-                    self.generated.push(PushPtr(label_name));
-                },
+                    self.generated.push(MovPtr(Register::RAX, label_name));
+                }
+
+                self.generated.push(Push(Register::RAX));
+            },
+            Type::InVar(name) => {
+                let var = self.get_var(name)?;
+
+                if self.abi.positional_args && matches!(var.2, Type::Bytes(_)) {
+                    self.gen_aggregate_arg(Type::InVar(var.0), Register::RAX)?;
+                    self.generated.push(Push(Register::RAX));
 
-                Type::u64(val) => { self.generated.push(PushVal(val as i64)) },
-                Type::i64(val) => { self.generated.push(PushVal(val as i64)) },
-                Type::u32(val) => { self.generated.push(PushVal(val as i64)) },
-                Type::i32(val) => { self.generated.push(PushVal(val as i64)) },
-
-                arg => {
-                    let label_name = format!("{}.{}",self.name, self.parsed_label_args);
-                    
-                    self.parsed_label_args += 1;
-
-                    self.builder.define_label(&label_name, false, arg.bytes());
-        
-                    self.generated.push(PushLabel(
-                        label_name
-                    ));
+                    return Ok(());
                 }
+
+                if ![4, 8].contains(&var.2.size()) {
+                    return Err(CodeGenLibError::UnsuportedArg(var.0));
+                }
+
+                // 4 byte values are zero extended into rax
+                match self.known.get(var.1, var.2.size() as usize) {
+                    Some(value) if value >= i32::MIN as i64 && value <= i32::MAX as i64 => {
+                        self.generated.push(PushVal(value));
+                        return Ok(());
+                    },
+                    Some(value) => self.generated.push(MovVal(Register::RAX, value)),
+                    None if var.2.size() == 4 => self.generated.push(Load(Register::EAX, self.abi.stack(var.1))),
+                    None => self.generated.push(Load(Register::RAX, self.abi.stack(var.1))),
+                }
+
+                self.generated.push(Push(Register::RAX));
+            },
+            Type::Bytes(content) if self.abi.positional_args => {
+                self.gen_aggregate_arg(Type::Bytes(content), Register::RAX)?;
+                self.generated.push(Push(Register::RAX));
+            },
+            // they would need to be copied onto the stack by value
+            Type::Bytes(_) => return Err(CodeGenLibError::UnsuportedArg("Bytes".into())),
+            Type::Unlim(_) => return Err(CodeGenLibError::UnsuportedArg("Unlim".into())),
+        };
+
+        Ok(())
+    }
+
+    /// Moves the aggregate `arg` (`Type::Bytes` or a variable of it) into `reg`
+    /// 
+    /// Aggregates with 1, 2, 4 or 8 bytes are passed by value, else `reg` gets
+    /// the address of a copy of it (which the callee can change)
+    fn gen_aggregate_arg(&mut self, arg: Type, reg: Register) -> Result<(), CodeGenLibError> {
+        let typ = self.arg_type(&arg)?;
+
+        if !self.abi.by_ref(&typ) {
+            match arg {
+                Type::Bytes(content) => {
+                    let mut value = [0u8; 8];
+                    value[..content.len()].copy_from_slice(&content);
+
+                    self.generated.push(MovVal(reg, i64::from_le_bytes(value)));
+                },
+                // the slot of the variable has 8 bytes
+                Type::InVar(name) => self.generated.push(Load(reg, self.abi.stack(self.get_var(name)?.1))),
+                arg => return Err(CodeGenLibError::UnsuportedArg(format!("{:?}", arg))),
             }
+
+            return Ok(());
+        }
+
+        let temp = self.alloc_temp(Type::Bytes(vec![0; typ.size() as usize]));
+
+        match arg {
+            Type::Bytes(content) => self.gen_copy_bytes(&content, &temp),
+            Type::InVar(name) => {
+                let var = self.get_var(name)?;
+
+                self.gen_copy_var(&var, &temp)?;
+            },
+            arg => return Err(CodeGenLibError::UnsuportedArg(format!("{:?}", arg))),
         }
 
+        self.known.escape(temp.1, temp.2.slot_size() as usize);
+        self.generated.push(Lea(reg, self.abi.stack(temp.1)));
+
         Ok(())
     }
 
@@ -376,30 +658,167 @@ impl IrFunctionBuilder {
     /// 
     /// **!** func needs to be definied via the efuncs-function else there will be sus errors
     /// 
+    /// The call follows the calling convention of the abi:
+    ///  * stack arguments get pushed from right to left and get removed after the call
+    ///  * the shadow space gets reserved below the stack arguments (windows)
+    ///  * rsp is 16 byte aligned at the call
+    ///  * for variadic functions (`Type::Unlim` in the signature) al holds the count of the used vector registers
+    /// 
     /// Example:
     /// ```
     /// func.build_call("printf", vec![Type::Str(b"Hello World!".into())])?;
     /// ```
     pub fn build_call(&mut self, func: &str, args: Vec<Type>) -> Result<(), Box<dyn Error>> {
-        let mut index = 0;
+        self.gen_call(func, args, None, false)
+    }
+
+    /// Calls function with name `func` and args `args` and stores the return value in `result_var`
+    /// 
+    /// The type of `result_var` decides how the value is returned:
+    ///  * values which fit into the return registers are stored from them
+    ///  * for larger values the address of `result_var` is passed as a hidden first argument
+    pub fn build_call_ret(&mut self, func: &str, args: Vec<Type>, result_var: &str) -> Result<(), Box<dyn Error>> {
+        let var = self.get_var(result_var.into())?;
 
-        let mut prev_args = vec![];
+        let size = var.2.size();
+        let regs = self.abi.ret_regs(size);
 
-        for arg in args {
-            self.gen_x_arg_for_func(func, index, arg.clone(), &prev_args)?;
+        self.gen_call(func, args, if regs == 0 { Some(var.to_owned()) } else { None }, false)?;
 
-            prev_args.push( arg );
+        match regs {
+            1 => {
+                let reg = if size == 4 { Register::EAX } else { self.abi.ret_reg() };
 
-            println!("{:?}", prev_args);
+                self.generated.push(Store(reg, self.abi.stack(var.1)));
+            },
+            2 => {
+                self.generated.push(Store(self.abi.ret_reg(), self.abi.stack(var.1)));
+                self.generated.push(Store(self.abi.return_reg2.unwrap(), self.abi.stack(var.1 + 8)));
+            },
+            _ => {}, // the callee wrote the value into the variable
+        }
+
+        self.known.set(var.1, var.2.slot_size() as usize, None);
+
+        Ok(())
+    }
+
+    /// Returns the result of calling `func` with the args `args` through a guaranteed tail call
+    ///
+    /// The frame of this function gets removed before jumping to `func`, so it fails if:
+    ///  * the return value is written into memory (the callee doesn't know about it)
+    ///  * the address of a variable is taken or an argument is passed by reference
+    ///  * `func` gets more arguments passed on the stack than this function
+    pub fn build_tail_call(&mut self, func: &str, args: Vec<Type>) -> Result<(), Box<dyn Error>> {
+        let escapes = args.iter().any(|arg| matches!(arg, Type::Ptr(content) if matches!(**content, Type::InVar(_))));
+        // the copies of arguments which are passed by reference are in this frame
+        let by_ref = args.iter().any(|arg| self.arg_type(arg).is_ok_and(|typ| self.abi.by_ref(&typ)));
+
+        if self.sret() || escapes || by_ref || frame_escapes(&self.generated) {
+            return Err(Box::from(CodeGenLibError::TailCallImpossible(func.into())));
+        }
+
+        self.gen_call(func, args, None, true)
+    }
+
+    /// Generates the call, `sret` is the variable for the return value
+    /// if it is returned via the hidden struct return pointer
+    ///
+    /// A `tail` call moves the stack arguments into the incoming argument area and jumps to `func`
+    fn gen_call(&mut self, func: &str, args: Vec<Type>, sret: Option<(String, i64, Type)>, tail: bool) -> Result<(), Box<dyn Error>> {
+        let signature = match self.funcs.iter().find(|f| f.0 == func) {
+            Some(f) => f.1.to_owned(),
+            None => return Err(Box::from(CodeGenLibError::FuncNotExist(func.into()))),
+        };
+
+        let variadic = signature.iter().any(|typ| matches!(typ, Type::Unlim(_)));
+
+        self.temps = 0;
+
+        let mut types = vec![];
+
+        if sret.is_some() {
+            types.push(Type::Ptr(Box::from( Type::u64(0) )));
+        }
+
+        for arg in args.iter() {
+            types.push(self.arg_type(arg)?);
+        }
+
+        let mut regs = self.abi.arg_regs(&types);
+
+        let sret_reg = match sret {
+            Some(_) => regs.remove(0),
+            None => None,
+        };
+
+        let stack_args: Vec<Type> = args.iter().zip(regs.iter())
+            .filter(|(_, reg)| reg.is_none())
+            .map(|(arg, _)| arg.to_owned())
+            .collect();
 
-            index += 1;
-        } 
+        let incoming = self.args.iter().filter(|arg| arg.0.2.is_none()).count();
+        let outgoing = stack_args.len();
+
+        if tail && outgoing > incoming {
+            return Err(Box::from(CodeGenLibError::TailCallImpossible(func.into())));
+        }
+
+        let stack_size = stack_args.len() as i64 * 8 + self.abi.shadow_space;
+        let padding = (self.abi.stack_align - stack_size % self.abi.stack_align) % self.abi.stack_align;
+
+        if padding != 0 {
+            self.generated.push(SubVal(Register::RSP, padding));
+        }
+
+        for arg in stack_args.into_iter().rev() {
+            self.gen_stack_arg(arg)?;
+        }
+
+        if self.abi.shadow_space != 0 { // the stack arguments are above the shadow space
+            self.generated.push(SubVal(Register::RSP, self.abi.shadow_space));
+        }
+
+        for (arg, reg) in args.into_iter().zip(regs) {
+            if let Some(nr) = reg {
+                self.gen_reg_arg(nr, arg)?;
+            }
+        }
+
+        if let (Some(var), Some(nr)) = (sret, sret_reg) {
+            // an argument which is passed by reference already is the pointer
+            if let (Register::RBP, displ) = self.var_base(&var, self.abi.arg64(nr)) {
+                self.generated.push(Lea(self.abi.arg64(nr), self.abi.ptr(Register::RBP, displ)));
+            }
+        }
+
+        if variadic && self.abi.varargs_al {
+            self.generated.push(MovVal(Register::AL, 0)); // no vector registers are used
+        }
+
+        if tail {
+            self.generated.extend(copy_stack_args(outgoing, &self.abi));
+        } else {
+            self.generated.push(Call(func.into()));
+        }
+
+        if stack_size + padding != 0 {
+            self.generated.push(AddVal(Register::RSP, stack_size + padding));
+        }
 
-        self.generated.push(Call(func.into()));
+        if tail {
+            self.generated.push(Jmp(func.into()));
+        }
 
         Ok(())
     }
 
+    /// Sets the variable `name` to `content`
+    /// 
+    ///  * `Type::Str`/`Type::Ptr` store the address of the content (which is put into the data section)
+    ///  * `Type::Ptr(Type::InVar)` stores the address of the variable
+    ///  * `Type::Bytes` get copied into the variable
+    ///  * `Type::InVar` copies the variable into it
     pub fn build_set(&mut self, name: &str, content: Type) -> Result<(), Box<dyn Error>> {
 
         let var = self.get_var(name.into())?;
@@ -408,34 +827,320 @@ impl IrFunctionBuilder {
             Type::u64(val) => { 
                 self.generated.push(MovVal(Register::RAX, val as i64));
                 self.generated.push(Store(Register::RAX, self.abi.stack(var.1)));
+                self.known.set(var.1, 8, Some(val as i64));
             },
             Type::u32(val) => { 
                 self.generated.push(MovVal(Register::EAX, val as i64));
                 self.generated.push(Store(Register::EAX, self.abi.stack(var.1)));
+                self.known.set(var.1, 4, Some(val as i64));
             },
             Type::i64(val) => { 
                 self.generated.push(MovVal(Register::RAX, val as i64));
                 self.generated.push(Store(Register::RAX, self.abi.stack(var.1)));
+                self.known.set(var.1, 8, Some(val));
             },
             Type::i32(val) => { 
                 self.generated.push(MovVal(Register::EAX, val as i64));
                 self.generated.push(Store(Register::EAX, self.abi.stack(var.1)));
+                self.known.set(var.1, 4, Some(val as i64));
+            },
+            Type::Bytes(content) => {
+                // the memory of arguments which are passed by reference has no padding
+                let size = if self.is_ref(&var) { var.2.size() } else { var.2.slot_size() };
+
+                if content.len() as u64 > size {
+                    return Err(Box::from(CodeGenLibError::TypeMismatch(format!("{} bytes don't fit into {}", content.len(), var.0))));
+                }
+
+                self.gen_copy_bytes(&content, &var);
+            },
+            Type::Str(content) => {
+                let label_name = self.new_label();
+                self.builder.define_label(&label_name, false, content);
+
+                self.generated.push(MovPtr(Register::RAX, label_name));
+                self.generated.push(Store(Register::RAX, self.abi.stack(var.1)));
+                self.known.set(var.1, 8, None);
+            },
+            Type::Ptr(target) => {
+                if let Type::InVar(target) = *target { // address of the variable
+                    self.gen_address_of(target, Register::RAX)?;
+                } else {
+                    let label_name = self.new_label();
+                    self.builder.define_label(&label_name, false, target.bytes());
+
+                    self.generated.push(MovPtr(Register::RAX, label_name));
+                }
+
+                self.generated.push(Store(Register::RAX, self.abi.stack(var.1)));
+                self.known.set(var.1, 8, None);
+            },
+            Type::InVar(src) => {
+                let src = self.get_var(src)?;
+
+                self.gen_copy_var(&src, &var)?;
             },
-            Type::Bytes(_) => {},
-            Type::Str(_) => {},
-            Type::Ptr(_adr) => {},
-            Type::InVar(_) => {},
-            Type::Unlim(_) => {},
+            Type::Unlim(_) => return Err(Box::from(CodeGenLibError::TypeMismatch(format!("{} can't be set to varargs", var.0)))),
+        }
+
+        Ok(())
+    }
+
+    /// Stores the bytes into the variable `var` (in 8, 4, 2 and 1 byte parts)
+    fn gen_copy_bytes(&mut self, content: &[u8], var: &(String, i64, Type)) {
+        let (base, displ) = self.var_base(var, Register::R11);
+
+        for (offset, size) in parts(content.len()) {
+            let reg = sized(Register::RAX, size);
+
+            let mut value = [0u8; 8];
+            value[..size].copy_from_slice(&content[offset..offset + size]);
+
+            self.generated.push(MovVal(reg, i64::from_le_bytes(value)));
+            self.generated.push(Store(reg, self.abi.ptr(base, displ + offset as i64)));
+
+            if base == Register::RBP {
+                self.known.set(var.1 + offset as i64, size, Some(i64::from_le_bytes(value)));
+            }
         }
+    }
+
+    /// Copies the variable `src` into the variable `target`
+    /// 
+    /// Ints get sign or zero extended (depending on the type of `src`) or truncated
+    fn gen_copy_var(&mut self, src: &(String, i64, Type), target: &(String, i64, Type)) -> Result<(), CodeGenLibError> {
+        let is_int = |typ: &Type| matches!(typ, Type::u64(_) | Type::u32(_) | Type::i64(_) | Type::i32(_) | Type::Str(_) | Type::Ptr(_));
+
+        if is_int(&src.2) && is_int(&target.2) {
+            let signed = matches!(src.2, Type::i32(_) | Type::i64(_));
+            let reg = if target.2.size() == 4 { Register::EAX } else { Register::RAX };
+
+            if let Some(value) = self.known.get(src.1, src.2.size() as usize) {
+                let value = if signed && src.2.size() == 4 { value as i32 as i64 } else { value };
+
+                self.generated.push(MovVal(reg, truncate(value, reg.size())));
+                self.generated.push(Store(reg, self.abi.stack(target.1)));
+                self.known.set(target.1, reg.size(), Some(value));
 
+                return Ok(());
+            }
+
+            match (src.2.size(), target.2.size()) {
+                (4, 8) if signed => {
+                    self.generated.push(Load(Register::EAX, self.abi.stack(src.1)));
+                    self.generated.push(MovSx(Register::RAX, Register::EAX));
+                },
+                (4, _) => self.generated.push(Load(Register::EAX, self.abi.stack(src.1))), // zero extends into rax
+                _ => self.generated.push(Load(Register::RAX, self.abi.stack(src.1))),
+            }
+
+            self.generated.push(Store(reg, self.abi.stack(target.1)));
+            self.known.set(target.1, reg.size(), None);
+        } else if let (Type::Bytes(_), Type::Bytes(_)) = (&src.2, &target.2) {
+            let (src_base, src_displ) = self.var_base(src, Register::R10);
+            let (target_base, target_displ) = self.var_base(target, Register::R11);
+
+            for (offset, size) in parts(src.2.size().min(target.2.size()) as usize) {
+                let reg = sized(Register::RAX, size);
+                let offset = offset as i64;
+
+                self.generated.push(Load(reg, self.abi.ptr(src_base, src_displ + offset)));
+                self.generated.push(Store(reg, self.abi.ptr(target_base, target_displ + offset)));
+
+                if target_base == Register::RBP {
+                    self.known.set(target.1 + offset, size, None);
+                }
+            }
+        } else {
+            return Err(CodeGenLibError::TypeMismatch(format!("{} can't be copied into {}", src.0, target.0)));
+        }
 
         Ok(())
     }
     
+    /// Defines the label `name` at the current position, which can be used as a jump target
+    /// 
+    /// The values of the variables aren't known after it (it can be reached from anywhere)
+    pub fn build_label(&mut self, name: &str) {
+        self.generated.push(Label(name.into()));
+        self.known.clear();
+    }
+
+    /// Jumps to the label `name`
+    pub fn build_jmp(&mut self, name: &str) {
+        self.generated.push(Jmp(name.into()));
+        self.known.clear();
+    }
+
+    /// Jumps to the label of the case which matches the value of the variable `var`
+    /// (or to the label `default` if no case matches)
+    /// 
+    /// The input tuple values: `(i64, &str)` represent:
+    ///  * `i64` -> The case value
+    ///  * `&str` -> The label which gets jumped to
+    /// 
+    /// Dense case sets get lowered to a bounds checked jump table, sparse ones to a compare tree
+    pub fn build_switch(&mut self, var: &str, cases: Vec<(i64, &str)>, default: &str) -> Result<(), Box<dyn Error>> {
+        let var = self.get_var(var.into())?;
+
+        let signed = match var.2 {
+            Type::i64(_) | Type::i32(_) => true,
+            Type::u64(_) | Type::u32(_) => false,
+            _ => return Err(Box::from(CodeGenLibError::TypeMismatch(format!("can't switch over {}", var.0)))),
+        };
+
+        let mut cases: Vec<(i64, String)> = cases.iter().map(|case| (case.0, case.1.to_string())).collect();
+        if signed {
+            cases.sort_by_key(|case| case.0);
+        } else {
+            cases.sort_by_key(|case| case.0 as u64);
+        }
+
+        if let Some(case) = cases.windows(2).find(|cases| cases[0].0 == cases[1].0) {
+            return Err(Box::from(CodeGenLibError::TypeMismatch(format!("the case {} is used twice", case[0].0))));
+        }
+
+        // every path jumps away, so nothing is known after the switch
+        let known = self.known.get(var.1, var.2.size() as usize);
+        self.known.clear();
+
+        if let Some(value) = known { // the case is decided at build time
+            let value = if let Type::i32(_) = var.2 { value as i32 as i64 } else { value };
+
+            let target = match cases.iter().find(|case| case.0 == value) {
+                Some(case) => case.1.to_owned(),
+                None => default.into(),
+            };
+
+            self.generated.push(Jmp(target));
+
+            return Ok(());
+        }
+
+        // the value gets compared as 64 bit
+        match var.2 {
+            Type::i32(_) => {
+                self.generated.push(Load(Register::EAX, self.abi.stack(var.1)));
+                self.generated.push(MovSx(Register::RAX, Register::EAX));
+            },
+            Type::u32(_) => self.generated.push(Load(Register::EAX, self.abi.stack(var.1))),
+            _ => self.generated.push(Load(Register::RAX, self.abi.stack(var.1))),
+        }
+
+        if cases.is_empty() {
+            self.generated.push(Jmp(default.into()));
+        } else if Self::is_dense(&cases) {
+            self.gen_jump_table(&cases, default);
+        } else {
+            self.gen_compare_tree(&cases, default, signed);
+        }
+
+        Ok(())
+    }
+
+    /// Returns if the (sorted) cases are dense enough for a jump table
+    fn is_dense(cases: &[(i64, String)]) -> bool {
+        let span = cases[cases.len() - 1].0.wrapping_sub(cases[0].0) as u64;
+
+        cases.len() >= 4 && span < cases.len() as u64 * 3
+    }
+
+    /// Compares rax with the value (values which don't fit into 32 bits go through r11)
+    fn gen_cmp(&mut self, value: i64) {
+        if value >= i32::MIN as i64 && value <= i32::MAX as i64 {
+            self.generated.push(CmpVal(Register::RAX, value));
+        } else {
+            self.generated.push(MovVal(Register::R11, value));
+            self.generated.push(CmpReg(Register::RAX, Register::R11));
+        }
+    }
+
+    /// Jumps over the jump table to the case of the value in rax
+    fn gen_jump_table(&mut self, cases: &[(i64, String)], default: &str) {
+        let min = cases[0].0;
+        let span = cases[cases.len() - 1].0.wrapping_sub(min);
+
+        // rax = value - min (values below min wrap around and fail the unsigned bounds check)
+        if min >= i32::MIN as i64 && min <= i32::MAX as i64 {
+            if min != 0 {
+                self.generated.push(SubVal(Register::RAX, min));
+            }
+        } else {
+            self.generated.push(MovVal(Register::R11, min));
+            self.generated.push(SubReg(Register::RAX, Register::R11));
+        }
+
+        self.generated.push(CmpVal(Register::RAX, span));
+        self.generated.push(Ja(default.into()));
+
+        let mut targets = vec![];
+
+        for offset in 0..=span {
+            let target = match cases.iter().find(|case| case.0 == min.wrapping_add(offset)) {
+                Some(case) => case.1.to_owned(),
+                None => default.into(),
+            };
+
+            targets.push(target);
+        }
+
+        let table = self.new_label();
+        let name = self.name.to_owned();
+        self.builder.define_jump_table(&table, &name, targets);
+
+        // the entries are signed offsets from the table (so the address of the function isn't needed)
+        self.generated.push(MovPtr(Register::R11, table));
+        self.generated.push(Load(Register::EAX, MemoryOperand::new(Register::R11, Register::RAX, 4, 0, 0, false, Register::None)));
+        self.generated.push(MovSx(Register::RAX, Register::EAX));
+        self.generated.push(AddReg(Register::RAX, Register::R11));
+        self.generated.push(JmpReg(Register::RAX));
+    }
+
+    /// Binary searches the case of the value in rax
+    fn gen_compare_tree(&mut self, cases: &[(i64, String)], default: &str, signed: bool) {
+        if cases.len() <= 3 {
+            for case in cases {
+                self.gen_cmp(case.0);
+                self.generated.push(Je(case.1.to_owned()));
+            }
+
+            self.generated.push(Jmp(default.into()));
+
+            return;
+        }
+
+        let mid = cases.len() / 2;
+        let lower = self.new_label();
+
+        self.gen_cmp(cases[mid].0);
+        self.generated.push(Je(cases[mid].1.to_owned()));
+        self.generated.push(if signed { Jl(lower.to_owned()) } else { Jb(lower.to_owned()) });
+
+        self.gen_compare_tree(&cases[mid + 1..], default, signed);
+
+        self.generated.push(Label(lower));
+        self.gen_compare_tree(&cases[..mid], default, signed);
+    }
+
     /// Sets the function public
     pub fn set_public(&mut self) {
         self.public = true;
     }
+
+    /// Sets the attributes of the function (naked, noreturn, alignment, ...)
+    pub fn set_attrs(&mut self, attrs: FuncAttrs) {
+        self.attrs = attrs;
+    }
+
+    /// Sets the source location of the instructions which are built next
+    /// 
+    /// The file id comes from `IrBuilder::define_file`
+    pub fn set_loc(&mut self, loc: SourceLoc) {
+        if self.loc != Some(loc) {
+            self.generated.push(Loc(loc));
+            self.loc = Some(loc);
+        }
+    }
 }
 
 /// Builder which handels `IrFunctionBuilders`
@@ -449,9 +1154,12 @@ pub struct IrBuilder {
 impl IrBuilder {
     /// Returns new `IrFunctionBuilder` with the `target` Abi
     pub fn new(target: Target) -> Self {
+        let mut build = Builder::new();
+        build.abi = target.abi.to_owned();
+
         Self { 
             functs: vec![], 
-            build: Builder::new(),
+            build,
             abi: target,
         }
     }
@@ -465,23 +1173,101 @@ impl IrBuilder {
         self.functs.last_mut().unwrap()
     }
 
+    /// Sets the optimization passes to the preset of the level
+    pub fn set_opt_level(&mut self, level: OptLevel) {
+        self.build.set_opt_level(level);
+    }
+
+    /// Sets the optimization passes which run over every function
+    pub fn set_passes(&mut self, passes: PassManager) {
+        self.build.passes = passes;
+    }
+
+    /// Adds the source file and returns its file id (for `SourceLoc`)
+    /// 
+    /// The files need to be defined before the functions which use them are added
+    pub fn define_file(&mut self, name: &str) -> u32 {
+        self.build.define_file(name)
+    }
+
     /// Writes all functions/data etc. into outfile with path `outpath`
     pub fn write(&mut self, outpath: &str) -> Result<(), Box<dyn std::error::Error>> {
         for func in self.functs.iter() {
             let func = func.to_owned();
 
-            let mut code = func.generated;
+            let mut code = func.generated.to_owned();
 
-            if code.last() != Some(&Ret) {
+            if !matches!(code.last(), Some(Ret | Jmp(_))) && !func.attrs.naked && !func.attrs.noreturn {
                 code.push( MovVal(self.abi.abi.ret_reg(), 0) ); // return 0;
                 code.push( Ret );
             }
 
             self.build.sync(&func.builder);
 
-            self.build.define(&func.name, func.public, code)?;
+            for efunc in func.funcs.iter() {
+                self.build.define_extern(&efunc.0);
+            }
+
+            // the slot of arguments which are passed by reference holds the pointer
+            let vars = func.vars.iter().map(|var| {
+                let typ = if func.is_ref(var) { Type::Ptr(Box::from(var.2.to_owned())) } else { var.2.to_owned() };
+
+                (var.0.to_owned(), func.abi.stack(var.1).displacement, typ)
+            }).collect();
+
+            self.build.define_vars(&func.name, vars);
+            self.build.define_with_attrs(&func.name, func.public, code, func.attrs)?;
         }
 
         self.build.write(outpath, self.abi.bin)
     }
 }
+
+/// Returns the result of the comparison
+/// Splits `size` bytes into parts of 8, 4, 2 and 1 bytes (offset and size of the part)
+fn parts(size: usize) -> Vec<(usize, usize)> {
+    let mut parts = vec![];
+    let mut offset = 0;
+
+    while offset < size {
+        let part = match size - offset {
+            8.. => 8,
+            4.. => 4,
+            2.. => 2,
+            _ => 1,
+        };
+
+        parts.push((offset, part));
+        offset += part;
+    }
+
+    parts
+}
+
+/// Returns the part of rax or r11 with the size
+fn sized(reg: Register, size: usize) -> Register {
+    match (reg, size) {
+        (Register::RAX, 4) => Register::EAX,
+        (Register::RAX, 2) => Register::AX,
+        (Register::RAX, 1) => Register::AL,
+        (Register::R11, 4) => Register::R11D,
+        (Register::R11, 2) => Register::R11W,
+        (Register::R11, 1) => Register::R11L,
+        (reg, _) => reg,
+    }
+}
+
+fn compare(op: CmpOp, a: i64, b: i64) -> bool {
+    match op {
+        CmpOp::Eq => a == b,
+        CmpOp::Ne => a != b,
+        CmpOp::Lt => a < b,
+        CmpOp::Le => a <= b,
+        CmpOp::Gt => a > b,
+        CmpOp::Ge => a >= b,
+        CmpOp::ULt => (a as u64) < b as u64,
+        CmpOp::ULe => a as u64 <= b as u64,
+        CmpOp::UGt => a as u64 > b as u64,
+        CmpOp::UGe => a as u64 >= b as u64,
+    }
+}
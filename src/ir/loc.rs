@@ -0,0 +1,56 @@
+//! Source locations of the ir
+//!
+//! A location is attached with a `Loc` marker, which applies to every following instruction
+//! (until the next marker). The markers don't generate code
+
+use std::fmt;
+
+use super::AsmInstructionEnum;
+
+/// A position in the source code of the frontend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SourceLoc {
+    /// The index of the file (see `Builder::define_file`)
+    pub file: u32,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl SourceLoc {
+    /// Creates a new source location
+    pub fn new(file: u32, line: u32, column: u32) -> Self {
+        Self { file, line, column }
+    }
+}
+
+impl fmt::Display for SourceLoc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// Returns the source location of the instruction at `index` (the last `Loc` marker before it)
+pub fn loc_of(code: &[AsmInstructionEnum], index: usize) -> Option<SourceLoc> {
+    code.iter().take(index + 1).rev().find_map(|instr| match instr {
+        AsmInstructionEnum::Loc(loc) => Some(*loc),
+        _ => None,
+    })
+}
+
+/// Turns the instructions with their source locations into code with `Loc` markers
+/// (a marker is only added if the location changes)
+pub fn with_locs(code: Vec<(SourceLoc, AsmInstructionEnum)>) -> Vec<AsmInstructionEnum> {
+    let mut located = vec![];
+    let mut current = None;
+
+    for (loc, instr) in code {
+        if current != Some(loc) {
+            located.push(AsmInstructionEnum::Loc(loc));
+            current = Some(loc);
+        }
+
+        located.push(instr);
+    }
+
+    located
+}
@@ -2,13 +2,19 @@
 
 use iced_x86::{MemoryOperand, Register};
 
+pub mod consts;
 pub mod ir_builder;
+pub mod loc;
 pub mod typ;
+pub mod virt;
 pub mod resolve;
+pub mod verify;
 
 pub use ir_builder::IrBuilder;
 pub use ir_builder::IrFunctionBuilder;
+pub use loc::SourceLoc;
 pub use typ::Type;
+pub use virt::{VInstr, VReg};
 
 /// The enum of the IR
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -20,12 +26,37 @@ pub enum AsmInstructionEnum {
     MovVal(Register, i64),
     MovReg(Register, Register),
     MovPtr(Register, String),
+    MovSx(Register, Register),
 
     Store(Register, MemoryOperand),
     Load(Register, MemoryOperand),
+    Lea(Register, MemoryOperand),
 
     Call(String),
     Jmp(String),
+    JmpReg(Register),
+
+    /// A jump target inside of the function (doesn't generate any code)
+    Label(String),
+
+    /// The following instructions were generated for this source location (doesn't generate any code)
+    Loc(SourceLoc),
+
+    CmpVal(Register, i64),
+    CmpReg(Register, Register),
+
+    /// Jumps to the label if equal
+    Je(String),
+    /// Jumps to the label if not equal
+    Jne(String),
+    /// Jumps to the label if less (signed)
+    Jl(String),
+    /// Jumps to the label if greater (signed)
+    Jg(String),
+    /// Jumps to the label if below (unsigned)
+    Jb(String),
+    /// Jumps to the label if above (unsigned)
+    Ja(String),
 
     Inc(Register),
     Dec(Register),
@@ -41,14 +72,50 @@ pub enum AsmInstructionEnum {
     SubReg(Register, Register),
     SubMem(Register, MemoryOperand),
 
+    XorReg(Register, Register),
+
     MulVal(Register, i64),
     MulReg(Register, Register),
     MulMem(Register, MemoryOperand),
 
+    /// Signed division of the register by the constant (overwrites rax, rdx and r11)
     DivVal(Register, i64),
     DivReg(Register, Register),
     DivMem(Register, MemoryOperand),
 
+    /// Unsigned division of the register by the constant (overwrites rax, rdx and r11)
+    UDivVal(Register, i64),
+    /// Signed remainder of the register divided by the constant (overwrites rax, rdx and r11)
+    RemVal(Register, i64),
+    /// Unsigned remainder of the register divided by the constant (overwrites rax, rdx and r11)
+    URemVal(Register, i64),
+
+    /// Sign extends rax into rdx:rax
+    Cqo,
+    /// Sign extends eax into edx:eax
+    Cdq,
+    /// Signed division of rdx:rax (quotient in rax, remainder in rdx)
+    Idiv(Register),
+
+    /// Unsigned multiplication of rax with the register (the product is in rdx:rax)
+    Mul(Register),
+    /// Signed multiplication of rax with the register (the product is in rdx:rax)
+    Imul(Register),
+
+    /// Shifts the register left by the constant
+    ShlVal(Register, i64),
+    /// Shifts the register right by the constant (logical)
+    ShrVal(Register, i64),
+    /// Shifts the register right by the constant (arithmetic)
+    SarVal(Register, i64),
+
+    /// Shifts the register left by cl
+    ShlCl(Register),
+    /// Shifts the register right by cl (logical)
+    ShrCl(Register),
+    /// Shifts the register right by cl (arithmetic)
+    SarCl(Register),
+
     Push(Register),
     PushVal(i64),
     PushLabel(String),
@@ -5,21 +5,148 @@ use formatic::{Decl, Link, Scope};
 use iced_x86::{MemoryOperand, Register};
 use iced_x86::{BlockEncoder, BlockEncoderOptions, Code, Instruction, InstructionBlock};
 
-use crate::target::Abi;
-use super::AsmInstructionEnum;
+use crate::error::CodeGenLibError;
+use super::{AsmInstructionEnum, SourceLoc};
+
+/// Returns the machine code of a jump to a label inside of the function
+/// (the rel32 gets filled in after every instruction is encoded)
+fn local_jmp(opcode: &[u8], target: String, fixups: &mut Vec<(usize, String)>, at: usize) -> Instruction {
+    fixups.push((at + opcode.len(), target));
+
+    if opcode.len() == 1 {
+        Instruction::with_declare_byte_5(opcode[0], 0, 0, 0, 0)
+    } else {
+        Instruction::with_declare_byte_6(opcode[0], opcode[1], 0, 0, 0, 0)
+    }
+}
+
+/// Returns the machine code of the division by a constant with `div`/`idiv`
+/// (the strength reduction pass replaces it with cheaper code)
+fn div_const(reg: Register, value: i64, signed: bool, rem: bool) -> Result<Vec<Instruction>, Box<dyn Error>> {
+    let wide = reg.size() == 8;
+    let (rax, rdx, r11) = if wide { (Register::RAX, Register::RDX, Register::R11) } else { (Register::EAX, Register::EDX, Register::R11D) };
+    let mov = if wide { Code::Mov_r64_rm64 } else { Code::Mov_r32_rm32 };
+
+    Ok(vec![
+        Instruction::with2(mov, rax, reg)?,
+        match (signed, wide) {
+            (true, true) => Instruction::with(Code::Cqo),
+            (true, false) => Instruction::with(Code::Cdq),
+            (false, _) => Instruction::with2(Code::Xor_r32_rm32, Register::EDX, Register::EDX)?,
+        },
+        if wide { Instruction::with2(Code::Mov_r64_imm64, r11, value)? } else { Instruction::with2(Code::Mov_r32_imm32, r11, value)? },
+        match (signed, wide) {
+            (true, true) => Instruction::with1(Code::Idiv_rm64, r11)?,
+            (true, false) => Instruction::with1(Code::Idiv_rm32, r11)?,
+            (false, true) => Instruction::with1(Code::Div_rm64, r11)?,
+            (false, false) => Instruction::with1(Code::Div_rm32, r11)?,
+        },
+        Instruction::with2(mov, reg, if rem { rdx } else { rax })?,
+    ])
+}
+
+/// The machine code of a function and the offsets into it
+#[derive(Debug, Clone)]
+pub struct Resolved {
+    pub code: Vec<u8>,
+    /// The relocations to other functions
+    pub links: Vec<Link>,
+    /// The declarations of the called functions
+    pub decls: HashMap<String, Decl>,
+    /// The byte offset of every `Label` in the code
+    pub labels: HashMap<String, usize>,
+    /// The byte offsets at which the code of a source location starts
+    pub locs: Vec<(usize, SourceLoc)>,
+    /// The byte offset of every instruction
+    pub offsets: Vec<usize>,
+}
 
 /// Turns the IR into machine code
 pub fn resolve(
     funcs: Vec<String>,
     labels: Vec<String>,
     code: &Vec<AsmInstructionEnum>,
-) -> Result<(Vec<u8>, Vec<Link>, HashMap<String, Decl>), Box<dyn Error>> {
+) -> Result<Resolved, Box<dyn Error>> {
     let mut decls: HashMap<String, Decl> = HashMap::new();
     let mut links = vec![];
     let mut generated = vec![];
 
+    let local_labels: Vec<&String> = code.iter().filter_map(|instr| match instr {
+        AsmInstructionEnum::Label(name) => Some(name),
+        _ => None,
+    }).collect();
+
+    let mut offsets: HashMap<String, usize> = HashMap::new();
+    let mut fixups: Vec<(usize, String)> = vec![];
+    let mut locs: Vec<(usize, SourceLoc)> = vec![];
+    let mut instr_offsets: Vec<usize> = vec![];
+
     for instruction in code {
+        instr_offsets.push(generated.len());
+
         let instr: Vec<Instruction> = match instruction.to_owned() {
+            AsmInstructionEnum::Label(name) => {
+                offsets.insert(name, generated.len());
+                continue;
+            }
+
+            AsmInstructionEnum::Loc(loc) => {
+                // only the last location before an instruction is used
+                if locs.last().map(|last| last.0) == Some(generated.len()) {
+                    locs.pop();
+                }
+
+                locs.push((generated.len(), loc));
+                continue;
+            }
+
+            AsmInstructionEnum::Jmp(target) if local_labels.contains(&&target) => {
+                vec![local_jmp(&[0xE9], target, &mut fixups, generated.len())]
+            }
+
+            AsmInstructionEnum::Je(target) => vec![local_jmp(&[0x0F, 0x84], target, &mut fixups, generated.len())],
+            AsmInstructionEnum::Jne(target) => vec![local_jmp(&[0x0F, 0x85], target, &mut fixups, generated.len())],
+            AsmInstructionEnum::Jl(target) => vec![local_jmp(&[0x0F, 0x8C], target, &mut fixups, generated.len())],
+            AsmInstructionEnum::Jg(target) => vec![local_jmp(&[0x0F, 0x8F], target, &mut fixups, generated.len())],
+            AsmInstructionEnum::Jb(target) => vec![local_jmp(&[0x0F, 0x82], target, &mut fixups, generated.len())],
+            AsmInstructionEnum::Ja(target) => vec![local_jmp(&[0x0F, 0x87], target, &mut fixups, generated.len())],
+
+            AsmInstructionEnum::JmpReg(reg) => {
+                if reg.size() == 8 {
+                    vec![Instruction::with1(Code::Jmp_rm64, reg)?]
+                } else {
+                    vec![Instruction::with(Code::Nopd)]
+                }
+            }
+
+            AsmInstructionEnum::CmpVal(reg, value) => {
+                if reg.size() == 8 {
+                    vec![Instruction::with2(Code::Cmp_rm64_imm32, reg, value)?]
+                } else if reg.size() == 4 {
+                    vec![Instruction::with2(Code::Cmp_rm32_imm32, reg, value)?]
+                } else if reg.size() == 2 {
+                    vec![Instruction::with2(Code::Cmp_rm16_imm16, reg, value)?]
+                } else if reg.size() == 1 {
+                    vec![Instruction::with2(Code::Cmp_rm8_imm8, reg, value)?]
+                } else {
+                    vec![Instruction::with(Code::Nopd)]
+                }
+            }
+
+            AsmInstructionEnum::CmpReg(reg1, reg2) => {
+                if (reg1.size() == 8) && (reg2.size() == 8) {
+                    vec![Instruction::with2(Code::Cmp_r64_rm64, reg1, reg2)?]
+                } else if (reg1.size() == 4) && (reg2.size() == 4) {
+                    vec![Instruction::with2(Code::Cmp_r32_rm32, reg1, reg2)?]
+                } else if (reg1.size() == 2) && (reg2.size() == 2) {
+                    vec![Instruction::with2(Code::Cmp_r16_rm16, reg1, reg2)?]
+                } else if (reg1.size() == 1) && (reg2.size() == 1) {
+                    vec![Instruction::with2(Code::Cmp_r8_rm8, reg1, reg2)?]
+                } else {
+                    vec![Instruction::with(Code::Nopd)]
+                }
+            }
+
             AsmInstructionEnum::Ret => vec![Instruction::with(Code::Retnq)],
 
             AsmInstructionEnum::Endbr64 => vec![Instruction::with(Code::Endbr64)],
@@ -110,10 +237,22 @@ pub fn resolve(
                 }
             }
 
+            AsmInstructionEnum::MovSx(target, src) => {
+                match (target.size(), src.size()) {
+                    (8, 4) => vec![Instruction::with2(Code::Movsxd_r64_rm32, target, src)?],
+                    (8, 2) => vec![Instruction::with2(Code::Movsx_r64_rm16, target, src)?],
+                    (8, 1) => vec![Instruction::with2(Code::Movsx_r64_rm8, target, src)?],
+                    (4, 2) => vec![Instruction::with2(Code::Movsx_r32_rm16, target, src)?],
+                    (4, 1) => vec![Instruction::with2(Code::Movsx_r32_rm8, target, src)?],
+                    (2, 1) => vec![Instruction::with2(Code::Movsx_r16_rm8, target, src)?],
+                    _ => vec![Instruction::with(Code::Nopd)],
+                }
+            }
+
             AsmInstructionEnum::MovPtr(src, target) => {
                 let target = target.to_string();
 
-                if !decls.contains_key(&target) && !funcs.contains(&target) {
+                if !decls.contains_key(&target) && !labels.contains(&target) && !funcs.contains(&target) {
                     decls.insert(target.clone(), Decl::Data(Scope::Import));
                 };
 
@@ -124,9 +263,9 @@ pub fn resolve(
                 });
 
                 if src.size() == 8 {
-                    vec![Instruction::with2(Code::Lea_r64_m, Register::RAX, MemoryOperand::new(
+                    vec![Instruction::with2(Code::Lea_r64_m, src, MemoryOperand::new(
                         Register::RIP, Register::None, 1, 7, 1, false, Register::None
-                    ))?, Instruction::with2(Code::Mov_r64_rm64, src, Register::RAX)?]
+                    ))?]
                 } else if src.size() == 4 {
                     vec![Instruction::with2(Code::Lea_r32_m, Register::EAX, MemoryOperand::new(
                         Register::RIP, Register::None, 1, 7, 1, false, Register::None
@@ -138,7 +277,9 @@ pub fn resolve(
             }
 
             AsmInstructionEnum::Load(reg, mem) => {
-                if reg.size() == 8 {
+                if reg.is_xmm() {
+                    vec![Instruction::with2(Code::Movdqu_xmm_xmmm128, reg, mem)?]
+                } else if reg.size() == 8 {
                     vec![Instruction::with2(Code::Mov_r64_rm64, reg, mem)?]
                 } else if reg.size() == 4 {
                     vec![Instruction::with2(Code::Mov_r32_rm32, reg, mem)?]
@@ -152,7 +293,9 @@ pub fn resolve(
             }
 
             AsmInstructionEnum::Store(reg, mem) => {
-                if reg.size() == 8 {
+                if reg.is_xmm() {
+                    vec![Instruction::with2(Code::Movdqu_xmmm128_xmm, mem, reg)?]
+                } else if reg.size() == 8 {
                     vec![Instruction::with2(Code::Mov_rm64_r64, mem, reg)?]
                 } else if reg.size() == 4 {
                     vec![Instruction::with2(Code::Mov_rm32_r32, mem, reg)?]
@@ -165,6 +308,14 @@ pub fn resolve(
                 }
             }
 
+            AsmInstructionEnum::Lea(reg, mem) => {
+                if reg.size() == 8 {
+                    vec![Instruction::with2(Code::Lea_r64_m, reg, mem)?]
+                } else {
+                    vec![Instruction::with(Code::Nopd)]
+                }
+            }
+
             AsmInstructionEnum::Inc(reg) => {
                 if reg.size() == 8 {
                     vec![Instruction::with1(Code::Inc_rm64, reg)?]
@@ -249,6 +400,20 @@ pub fn resolve(
                 }
             }
 
+            AsmInstructionEnum::XorReg(src, target) => {
+                if (src.size() == 8) && (target.size() == 8) {
+                    vec![Instruction::with2(Code::Xor_r64_rm64, src, target)?]
+                } else if (src.size() == 4) && (target.size() == 4) {
+                    vec![Instruction::with2(Code::Xor_r32_rm32, src, target)?]
+                } else if (src.size() == 2) && (target.size() == 2) {
+                    vec![Instruction::with2(Code::Xor_r16_rm16, src, target)?]
+                } else if (src.size() == 1) && (target.size() == 1) {
+                    vec![Instruction::with2(Code::Xor_r8_rm8, src, target)?]
+                } else {
+                    vec![Instruction::with(Code::Nopd)]
+                }
+            }
+
             AsmInstructionEnum::AddMem(reg, mem) => {
                 if reg.size() == 8 {
                     vec![Instruction::with2(Code::Add_r64_rm64, reg, mem)?]
@@ -307,11 +472,11 @@ pub fn resolve(
 
             AsmInstructionEnum::MulVal(reg, value) => {
                 if reg.size() == 8 {
-                    vec![Instruction::with2(Code::Imul_r64_rm64_imm32, reg, value)?]
+                    vec![Instruction::with3(Code::Imul_r64_rm64_imm32, reg, reg, value as i32)?]
                 } else if reg.size() == 4 {
-                    vec![Instruction::with2(Code::Imul_r32_rm32_imm32, reg, value)?]
+                    vec![Instruction::with3(Code::Imul_r32_rm32_imm32, reg, reg, value as i32)?]
                 } else if reg.size() == 2 {
-                    vec![Instruction::with2(Code::Imul_r16_rm16_imm16, reg, value)?]
+                    vec![Instruction::with3(Code::Imul_r16_rm16_imm16, reg, reg, value as i32)?]
                 } else {
                     vec![Instruction::with(Code::Nopd)]
                 }
@@ -356,15 +521,109 @@ pub fn resolve(
                 }
             }
 
-            AsmInstructionEnum::DivVal(_, _) => {
-                vec![Instruction::with(Code::Nopd)]
-            }
+            AsmInstructionEnum::DivVal(reg, value) => div_const(reg, value, true, false)?,
+            AsmInstructionEnum::UDivVal(reg, value) => div_const(reg, value, false, false)?,
+            AsmInstructionEnum::RemVal(reg, value) => div_const(reg, value, true, true)?,
+            AsmInstructionEnum::URemVal(reg, value) => div_const(reg, value, false, true)?,
             AsmInstructionEnum::DivReg(_, _) => {
                 vec![Instruction::with(Code::Nopd)]
             }
             AsmInstructionEnum::DivMem(_, _) => {
                 vec![Instruction::with(Code::Nopd)]
             }
+
+            AsmInstructionEnum::Cqo => vec![Instruction::with(Code::Cqo)],
+            AsmInstructionEnum::Cdq => vec![Instruction::with(Code::Cdq)],
+
+            AsmInstructionEnum::Idiv(reg) => {
+                if reg.size() == 8 {
+                    vec![Instruction::with1(Code::Idiv_rm64, reg)?]
+                } else if reg.size() == 4 {
+                    vec![Instruction::with1(Code::Idiv_rm32, reg)?]
+                } else {
+                    vec![Instruction::with(Code::Nopd)]
+                }
+            }
+
+            AsmInstructionEnum::Mul(reg) => {
+                if reg.size() == 8 {
+                    vec![Instruction::with1(Code::Mul_rm64, reg)?]
+                } else if reg.size() == 4 {
+                    vec![Instruction::with1(Code::Mul_rm32, reg)?]
+                } else {
+                    vec![Instruction::with(Code::Nopd)]
+                }
+            }
+
+            AsmInstructionEnum::Imul(reg) => {
+                if reg.size() == 8 {
+                    vec![Instruction::with1(Code::Imul_rm64, reg)?]
+                } else if reg.size() == 4 {
+                    vec![Instruction::with1(Code::Imul_rm32, reg)?]
+                } else {
+                    vec![Instruction::with(Code::Nopd)]
+                }
+            }
+
+            AsmInstructionEnum::ShlVal(reg, value) => {
+                if reg.size() == 8 {
+                    vec![Instruction::with2(Code::Shl_rm64_imm8, reg, value as u32)?]
+                } else if reg.size() == 4 {
+                    vec![Instruction::with2(Code::Shl_rm32_imm8, reg, value as u32)?]
+                } else {
+                    vec![Instruction::with(Code::Nopd)]
+                }
+            }
+
+            AsmInstructionEnum::ShrVal(reg, value) => {
+                if reg.size() == 8 {
+                    vec![Instruction::with2(Code::Shr_rm64_imm8, reg, value as u32)?]
+                } else if reg.size() == 4 {
+                    vec![Instruction::with2(Code::Shr_rm32_imm8, reg, value as u32)?]
+                } else {
+                    vec![Instruction::with(Code::Nopd)]
+                }
+            }
+
+            AsmInstructionEnum::SarVal(reg, value) => {
+                if reg.size() == 8 {
+                    vec![Instruction::with2(Code::Sar_rm64_imm8, reg, value as u32)?]
+                } else if reg.size() == 4 {
+                    vec![Instruction::with2(Code::Sar_rm32_imm8, reg, value as u32)?]
+                } else {
+                    vec![Instruction::with(Code::Nopd)]
+                }
+            }
+
+            AsmInstructionEnum::ShlCl(reg) => {
+                if reg.size() == 8 {
+                    vec![Instruction::with2(Code::Shl_rm64_CL, reg, Register::CL)?]
+                } else if reg.size() == 4 {
+                    vec![Instruction::with2(Code::Shl_rm32_CL, reg, Register::CL)?]
+                } else {
+                    vec![Instruction::with(Code::Nopd)]
+                }
+            }
+
+            AsmInstructionEnum::ShrCl(reg) => {
+                if reg.size() == 8 {
+                    vec![Instruction::with2(Code::Shr_rm64_CL, reg, Register::CL)?]
+                } else if reg.size() == 4 {
+                    vec![Instruction::with2(Code::Shr_rm32_CL, reg, Register::CL)?]
+                } else {
+                    vec![Instruction::with(Code::Nopd)]
+                }
+            }
+
+            AsmInstructionEnum::SarCl(reg) => {
+                if reg.size() == 8 {
+                    vec![Instruction::with2(Code::Sar_rm64_CL, reg, Register::CL)?]
+                } else if reg.size() == 4 {
+                    vec![Instruction::with2(Code::Sar_rm32_CL, reg, Register::CL)?]
+                } else {
+                    vec![Instruction::with(Code::Nopd)]
+                }
+            }
             
             AsmInstructionEnum::PushLabel(name) => {
                 let name = name.to_string();
@@ -376,10 +635,13 @@ pub fn resolve(
                 links.push(Link {
                     from: String::new(),
                     to: name,
-                    at: generated.len() + 1,
+                    at: generated.len() + 2,
                 });
 
-                vec![Instruction::with1(Code::Push_rm64, Abi::host().mem(0))?]
+                // push qword [rip + disp32]
+                vec![Instruction::with1(Code::Push_rm64, MemoryOperand::new(
+                    Register::RIP, Register::None, 1, 6, 1, false, Register::None
+                ))?]
             }
 
             AsmInstructionEnum::PushPtr(target) => {
@@ -408,5 +670,23 @@ pub fn resolve(
         }
     }
 
-    Ok((generated, links, decls))
+    for (at, target) in fixups {
+        let target_offset = match offsets.get(&target) {
+            Some(offset) => *offset as i64,
+            None => return Err(Box::from(CodeGenLibError::LabelNotExist(target))),
+        };
+
+        let rel = (target_offset - (at as i64 + 4)) as i32;
+
+        generated[at..at + 4].copy_from_slice(&rel.to_le_bytes());
+    }
+
+    Ok(Resolved {
+        code: generated,
+        links,
+        decls,
+        labels: offsets,
+        locs,
+        offsets: instr_offsets,
+    })
 }
\ No newline at end of file
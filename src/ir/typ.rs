@@ -40,7 +40,7 @@ impl Type {
             Type::u32(_) => 4,
             Type::i64(_) => 8,
             Type::i32(_) => 4,
-            Type::Bytes(vec) => (vec.len() - 1) as u64,
+            Type::Bytes(vec) => vec.len() as u64,
             Type::Str(_) => 8,
             Type::Ptr(_) => 8,
 
@@ -49,13 +49,24 @@ impl Type {
         }
     }
 
+    /// Returns the size of the stack slot for the type in bytes
+    /// (bytes get a multiple of 8 so they can be copied in 8 byte parts)
+    pub fn slot_size(&self) -> u64 {
+        match self {
+            Type::Bytes(vec) => (vec.len() as u64).div_ceil(8) * 8,
+            typ => typ.size(),
+        }
+    }
+
     /// Returns the contents of the type as `Vec<u8>`  (empty for undetermined)
+    /// 
+    /// Ints are little endian like they are stored in memory
     pub fn bytes(&self) -> Vec<u8> {
         match self {
-            Type::u64(val) => val.to_be_bytes().into(),
-            Type::u32(val) => val.to_be_bytes().into(),
-            Type::i64(val) => val.to_be_bytes().into(),
-            Type::i32(val) => val.to_be_bytes().into(),
+            Type::u64(val) => val.to_le_bytes().into(),
+            Type::u32(val) => val.to_le_bytes().into(),
+            Type::i64(val) => val.to_le_bytes().into(),
+            Type::i32(val) => val.to_le_bytes().into(),
             Type::Bytes(b) => b.to_vec(),
             Type::Str(b) => b.to_vec(),
             Type::Ptr(target) => (*target).bytes(),
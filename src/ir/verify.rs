@@ -0,0 +1,407 @@
+//! Checks the ir for errors before it gets turned into machine code
+
+use std::fmt;
+
+use iced_x86::{MemoryOperand, Register};
+
+use crate::{x86::attrs::FuncAttrs, Builder};
+use super::{loc::loc_of, AsmInstructionEnum::{self, *}, SourceLoc, Type};
+
+/// A single error found by the verifier
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// The name of the function which contains the error
+    pub func: String,
+    /// The index of the instruction in the function
+    pub index: usize,
+    /// The source location of the instruction (if the function has `Loc` markers)
+    pub loc: Option<SourceLoc>,
+    pub msg: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.loc {
+            Some(loc) => write!(f, "{} [{}] at {}: {}", self.func, self.index, loc, self.msg),
+            None => write!(f, "{} [{}]: {}", self.func, self.index, self.msg),
+        }
+    }
+}
+
+/// Returns the size of the memory access done with the operand
+/// (the ir uses the scale for the size of `IncMem`/`DecMem`)
+fn mem_width(mem: &MemoryOperand) -> u64 {
+    mem.scale as u64
+}
+
+struct Verifier<'a> {
+    func: String,
+    funcs: &'a [String],
+    labels: &'a [String],
+    externs: &'a [String],
+    vars: Option<&'a Vec<(String, i64, Type)>>,
+
+    /// The labels which are defined inside of the function
+    locals: Vec<String>,
+
+    attrs: &'a FuncAttrs,
+
+    violations: Vec<Violation>,
+}
+
+impl<'a> Verifier<'a> {
+    fn error(&mut self, index: usize, msg: String) {
+        self.violations.push(Violation {
+            func: self.func.to_owned(),
+            index,
+            loc: None,
+            msg,
+        });
+    }
+
+    fn reg(&mut self, index: usize, reg: Register, sizes: &[usize]) {
+        if reg == Register::None {
+            self.error(index, "missing register".into());
+        } else if !sizes.contains(&reg.size()) {
+            self.error(index, format!("register {:?} with {} bytes isn't supported here", reg, reg.size()));
+        }
+    }
+
+    fn regs(&mut self, index: usize, reg1: Register, reg2: Register, sizes: &[usize]) {
+        self.reg(index, reg1, sizes);
+        self.reg(index, reg2, sizes);
+
+        if reg1.size() != reg2.size() {
+            self.error(index, format!("register {:?} and {:?} have different sizes", reg1, reg2));
+        }
+    }
+
+    fn imm32(&mut self, index: usize, value: i64) {
+        if value < i32::MIN as i64 || value > i32::MAX as i64 {
+            self.error(index, format!("immediate {value} doesn't fit into 32 bits"));
+        }
+    }
+
+    fn mem(&mut self, index: usize, mem: &MemoryOperand, width: u64) {
+        if mem.base != Register::RBP || mem.index != Register::None {
+            return;
+        }
+
+        let vars = match self.vars {
+            Some(vars) => vars,
+            None => return, // no variable layout known (raw `Builder::define`)
+        };
+
+        let start = mem.displacement;
+        let end = start + width as i64;
+
+        // every stack argument occupies a whole 8 byte slot of the incoming argument area
+        let declared = vars.iter().any(|var| {
+            let size = if var.1 > 0 { var.2.slot_size().max(8) } else { var.2.slot_size() };
+
+            start >= var.1 && end <= var.1 + size as i64
+        });
+
+        if !declared {
+            self.error(index, format!("access to undeclared variable at [rbp{:+}]", start));
+        }
+    }
+
+    fn symbol(&mut self, index: usize, name: &String, data: bool) {
+        let known = self.funcs.contains(name)
+            || self.externs.contains(name)
+            || (data && self.labels.contains(name));
+
+        if !known {
+            self.error(index, format!("undefined symbol {name}"));
+        }
+    }
+
+    fn local(&mut self, index: usize, label: &String) {
+        if !self.locals.contains(label) {
+            self.error(index, format!("undefined label {label}"));
+        }
+    }
+
+    fn instr(&mut self, index: usize, instr: &AsmInstructionEnum) {
+        match instr {
+            Ret if self.attrs.noreturn => self.error(index, "return in a noreturn function".into()),
+            Ret | Nop | Endbr64 | Label(_) | Loc(_) | Cqo | Cdq => {},
+            PushVal(value) => self.imm32(index, *value), // sign extended to 64 bits
+
+            MovVal(reg, value) => {
+                self.reg(index, *reg, &[1, 2, 4, 8]);
+
+                if reg.size() == 4 && (*value < i32::MIN as i64 || *value > u32::MAX as i64) {
+                    self.error(index, format!("immediate {value} doesn't fit into 32 bits"));
+                }
+            },
+            MovReg(reg1, reg2) | AddReg(reg1, reg2) | SubReg(reg1, reg2) | XorReg(reg1, reg2) => self.regs(index, *reg1, *reg2, &[1, 2, 4, 8]),
+            MulReg(reg1, reg2) => self.regs(index, *reg1, *reg2, &[2, 4, 8]),
+            MovSx(reg1, reg2) => {
+                self.reg(index, *reg1, &[2, 4, 8]);
+                self.reg(index, *reg2, &[1, 2, 4]);
+
+                if reg1.size() <= reg2.size() {
+                    self.error(index, format!("{:?} is too small to sign extend {:?}", reg1, reg2));
+                }
+            },
+            MovPtr(reg, label) => {
+                self.reg(index, *reg, &[4, 8]);
+                self.symbol(index, label, true);
+            },
+
+            Load(reg, mem) | Store(reg, mem) => {
+                self.reg(index, *reg, &[1, 2, 4, 8, 16]);
+                self.mem(index, mem, reg.size() as u64);
+            },
+            AddMem(reg, mem) | SubMem(reg, mem) => {
+                self.reg(index, *reg, &[1, 2, 4, 8]);
+                self.mem(index, mem, reg.size() as u64);
+            },
+            Lea(reg, mem) => {
+                self.reg(index, *reg, &[8]);
+                self.mem(index, mem, 1);
+            },
+            MulMem(reg, mem) => {
+                self.reg(index, *reg, &[2, 4, 8]);
+                self.mem(index, mem, reg.size() as u64);
+            },
+
+            Call(target) => self.symbol(index, target, false),
+            Jmp(target) => {
+                if !self.locals.contains(target) {
+                    self.symbol(index, target, false);
+                }
+            },
+            Je(label) | Jne(label) | Jl(label) | Jg(label) | Jb(label) | Ja(label) => self.local(index, label),
+            JmpReg(reg) => self.reg(index, *reg, &[8]),
+
+            CmpVal(reg, value) => {
+                self.reg(index, *reg, &[1, 2, 4, 8]);
+                self.imm32(index, *value);
+            },
+            CmpReg(reg1, reg2) => self.regs(index, *reg1, *reg2, &[1, 2, 4, 8]),
+
+            Inc(reg) | Dec(reg) => self.reg(index, *reg, &[1, 2, 4, 8]),
+            IncMem(mem) | DecMem(mem) => {
+                if ![1, 2, 4, 8].contains(&mem.scale) {
+                    self.error(index, format!("memory operand with {} bytes isn't supported", mem.scale));
+                }
+
+                self.mem(index, mem, mem_width(mem));
+            },
+
+            AddVal(reg, value) | SubVal(reg, value) => {
+                self.reg(index, *reg, &[1, 2, 4, 8]);
+                self.imm32(index, *value);
+            },
+            MulVal(reg, value) => {
+                self.reg(index, *reg, &[2, 4, 8]);
+                self.imm32(index, *value);
+            },
+
+            DivReg(..) | DivMem(..) => {
+                self.error(index, "division isn't supported by the encoder".into());
+            },
+            Idiv(reg) | Mul(reg) | Imul(reg) | ShlCl(reg) | ShrCl(reg) | SarCl(reg) => self.reg(index, *reg, &[4, 8]),
+
+            DivVal(reg, value) | UDivVal(reg, value) | RemVal(reg, value) | URemVal(reg, value) => {
+                self.reg(index, *reg, &[4, 8]);
+
+                let signed = matches!(instr, DivVal(..) | RemVal(..));
+
+                if *value == 0 {
+                    self.error(index, "division by zero".into());
+                } else if reg.size() == 4 {
+                    let fits = if signed { *value >= i32::MIN as i64 && *value <= i32::MAX as i64 } else { *value >= 0 && *value <= u32::MAX as i64 };
+
+                    if !fits {
+                        self.error(index, format!("divisor {value} doesn't fit into 32 bits"));
+                    }
+                }
+            },
+            ShlVal(reg, value) | ShrVal(reg, value) | SarVal(reg, value) => {
+                self.reg(index, *reg, &[4, 8]);
+
+                if *value < 0 || *value >= reg.size() as i64 * 8 {
+                    self.error(index, format!("can't shift {} bits", value));
+                }
+            },
+
+            Push(reg) | Pop(reg) => self.reg(index, *reg, &[2, 8]),
+            PushLabel(label) | PushPtr(label) => self.symbol(index, label, true),
+        }
+    }
+
+    /// Checks that nothing follows an unconditional `Ret`/`Jmp` (except a label)
+    /// 
+    /// Every unreachable run of instructions (until the next label) is reported once
+    fn reachability(&mut self, code: &[AsmInstructionEnum]) {
+        let mut reachable = true;
+        let mut reported = false;
+
+        for (index, instr) in code.iter().enumerate() {
+            if let Label(_) = instr {
+                reachable = true;
+                reported = false;
+            } else if let Loc(_) = instr {
+                continue;
+            } else if !reachable {
+                if !reported {
+                    self.error(index, format!("unreachable code after return ({:?})", instr));
+                    reported = true;
+                }
+
+                continue;
+            }
+
+            if matches!(instr, Ret | Jmp(_) | JmpReg(_)) {
+                reachable = false;
+            }
+        }
+    }
+
+    /// Checks that every label is only defined once
+    fn unique_labels(&mut self, code: &[AsmInstructionEnum]) {
+        let mut defined: Vec<&String> = vec![];
+
+        for (index, instr) in code.iter().enumerate() {
+            if let Label(name) = instr {
+                if defined.contains(&name) {
+                    self.error(index, format!("label {name} is defined twice"));
+                }
+
+                defined.push(name);
+            }
+        }
+    }
+
+    /// Checks that every push gets poped before the function returns
+    fn stack_balance(&mut self, code: &[AsmInstructionEnum]) {
+        let mut depth: i64 = 0;
+        let mut falls_through = true;
+
+        for (index, instr) in code.iter().enumerate() {
+            match instr {
+                Push(reg) => depth += reg.size() as i64,
+                PushVal(_) | PushLabel(_) | PushPtr(_) => depth += 8,
+                Pop(reg) => depth -= reg.size() as i64,
+                SubVal(Register::RSP, value) => depth += value,
+                AddVal(Register::RSP, value) => depth -= value,
+                Jmp(target) if self.locals.contains(target) => {},
+                Label(_) => falls_through = true,
+                Ret | Jmp(_) => {
+                    if depth != 0 {
+                        self.error(index, format!("unbalanced stack at return ({depth} bytes pushed)"));
+                    }
+
+                    falls_through = false;
+                },
+                _ => {},
+            }
+
+            if depth < 0 {
+                self.error(index, "pop without a matching push".into());
+                depth = 0;
+            }
+        }
+
+        if depth != 0 && falls_through {
+            self.error(code.len(), format!("unbalanced stack at end of function ({depth} bytes pushed)"));
+        }
+    }
+}
+
+/// Verifies the ir of a single function and returns every found error
+///
+/// `vars` is the variable layout of the function as `(name, rbp offset, type)`
+/// (if it is `None` stack accesses aren't checked)
+/// 
+/// The stack of naked functions isn't checked (they are often trampolines)
+pub fn verify_function<'a>(
+    name: &str,
+    code: &[AsmInstructionEnum],
+    funcs: &'a [String],
+    labels: &'a [String],
+    externs: &'a [String],
+    vars: Option<&'a Vec<(String, i64, Type)>>,
+    attrs: &'a FuncAttrs,
+) -> Vec<Violation> {
+    let mut verifier = Verifier {
+        func: name.into(),
+        funcs,
+        labels,
+        externs,
+        vars,
+        locals: code.iter().filter_map(|instr| match instr {
+            Label(name) => Some(name.to_owned()),
+            _ => None,
+        }).collect(),
+        attrs,
+        violations: vec![],
+    };
+
+    for (index, instr) in code.iter().enumerate() {
+        verifier.instr(index, instr);
+    }
+
+    verifier.reachability(code);
+    verifier.unique_labels(code);
+    if !attrs.naked {
+        verifier.stack_balance(code);
+    }
+
+    for violation in verifier.violations.iter_mut() {
+        violation.loc = loc_of(code, violation.index);
+    }
+
+    verifier.violations
+}
+
+/// Verifies every function of the builder
+pub fn verify_module(builder: &Builder) -> Vec<Violation> {
+    let mut violations = vec![];
+    let mut verified: Vec<&String> = vec![];
+
+    let mut externs = builder.externs.to_owned();
+
+    // the called functions which aren't defined are externals (unless `Builder::strict_externs` is set)
+    if !builder.strict_externs {
+        for code in builder.funcs.values() {
+            for instr in code.1.iter() {
+                if let Call(target) | Jmp(target) = instr {
+                    let local = code.1.contains(&Label(target.to_owned()));
+
+                    if !local && !externs.contains(target) {
+                        externs.push(target.to_owned());
+                    }
+                }
+            }
+        }
+    }
+
+    for name in builder.func_names.iter() {
+        if verified.contains(&name) {
+            continue;
+        }
+        verified.push(name);
+
+        let code = match builder.funcs.get(name) {
+            Some(func) => &func.1,
+            None => continue,
+        };
+
+        violations.append(&mut verify_function(
+            name,
+            code,
+            &builder.func_names,
+            &builder.label_names,
+            &externs,
+            builder.vars.get(name),
+            &builder.attrs(name),
+        ));
+    }
+
+    violations
+}
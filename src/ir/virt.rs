@@ -0,0 +1,101 @@
+//! Virtual registers and the instructions which use them
+//!
+//! The instructions are lowered to `AsmInstructionEnum` by the register allocator (see `regalloc`)
+
+use iced_x86::MemoryOperand;
+
+use super::AsmInstructionEnum;
+
+/// A virtual register with the size of its value in bytes (4 or 8)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VReg {
+    pub id: u32,
+    pub size: usize,
+}
+
+impl VReg {
+    /// Creates a new virtual register
+    pub fn new(id: u32, size: usize) -> Self {
+        Self { id, size }
+    }
+}
+
+/// An instruction on virtual registers
+///
+/// The arithmetic instructions are two address instructions (`dst = dst op src`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VInstr {
+    MovVal(VReg, i64),
+    Mov(VReg, VReg),
+    MovPtr(VReg, String),
+
+    Load(VReg, MemoryOperand),
+    Store(VReg, MemoryOperand),
+    Lea(VReg, MemoryOperand),
+
+    AddVal(VReg, i64),
+    Add(VReg, VReg),
+    SubVal(VReg, i64),
+    Sub(VReg, VReg),
+    MulVal(VReg, i64),
+    Mul(VReg, VReg),
+
+    /// Signed division (uses rax and rdx)
+    Div(VReg, VReg),
+    /// Signed remainder (uses rax and rdx)
+    Rem(VReg, VReg),
+
+    /// Shifts left by the second register (uses cl)
+    Shl(VReg, VReg),
+    /// Shifts right by the second register (logical, uses cl)
+    Shr(VReg, VReg),
+    /// Shifts right by the second register (arithmetic, uses cl)
+    Sar(VReg, VReg),
+
+    CmpVal(VReg, i64),
+    Cmp(VReg, VReg),
+
+    /// Reads the argument which is passed in the `nr`th argument register
+    /// (needs to be at the start of the function)
+    Arg(VReg, usize),
+
+    /// Calls the function with the arguments in the argument registers
+    /// and moves the return value into the register
+    Call(String, Vec<VReg>, Option<VReg>),
+
+    /// Returns the value of the register (if any)
+    Ret(Option<VReg>),
+
+    /// A machine instruction (labels, jumps, ...) which is kept as it is
+    Asm(AsmInstructionEnum),
+}
+
+impl VInstr {
+    /// Returns the virtual registers the instruction reads
+    pub fn uses(&self) -> Vec<VReg> {
+        use VInstr::*;
+
+        match self {
+            Store(reg, _) | AddVal(reg, _) | SubVal(reg, _) | MulVal(reg, _) | CmpVal(reg, _) => vec![*reg],
+            Mov(_, src) => vec![*src],
+            Add(dst, src) | Sub(dst, src) | Mul(dst, src) | Div(dst, src) | Rem(dst, src) |
+            Shl(dst, src) | Shr(dst, src) | Sar(dst, src) | Cmp(dst, src) => vec![*dst, *src],
+            Call(_, args, _) => args.to_owned(),
+            Ret(Some(reg)) => vec![*reg],
+            _ => vec![],
+        }
+    }
+
+    /// Returns the virtual register the instruction writes
+    pub fn def(&self) -> Option<VReg> {
+        use VInstr::*;
+
+        match self {
+            MovVal(reg, _) | Mov(reg, _) | MovPtr(reg, _) | Load(reg, _) | Lea(reg, _) |
+            AddVal(reg, _) | Add(reg, _) | SubVal(reg, _) | Sub(reg, _) | MulVal(reg, _) | Mul(reg, _) |
+            Div(reg, _) | Rem(reg, _) | Shl(reg, _) | Shr(reg, _) | Sar(reg, _) | Arg(reg, _) => Some(*reg),
+            Call(_, _, ret) => *ret,
+            _ => None,
+        }
+    }
+}
@@ -38,11 +38,13 @@
 pub mod error;
 pub mod ir;
 pub mod opt;
+pub mod regalloc;
+pub mod ssa;
 pub mod x86;
 pub mod target;
 pub mod exec;
 
-pub use ir::resolve::resolve;
+pub use ir::resolve::{resolve, Resolved};
 pub use x86::builder::Builder;
 pub use x86::*;
 
@@ -53,7 +55,7 @@ pub mod IR {
     pub use iced_x86::Register;
 }
 
-pub use opt::Optimize;
+pub use opt::{optimize, Optimize};
 
 /// Most used structs for high level interface exported
 pub mod prelude {
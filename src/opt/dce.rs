@@ -0,0 +1,263 @@
+//! Dead code elimination: unreachable code, dead stores and unused symbols
+
+use std::{collections::{BTreeSet, HashMap, HashSet}, error::Error};
+
+use iced_x86::{MemoryOperand, Register};
+
+use crate::{ir::AsmInstructionEnum::{self, *}, Builder};
+
+use super::{frame_escapes, PassContext};
+
+/// Returns the ranges of the basic blocks (they start at labels and end after jumps and returns)
+fn blocks(code: &[AsmInstructionEnum]) -> Vec<(usize, usize)> {
+    let mut blocks = vec![];
+    let mut start = 0;
+
+    for (index, instr) in code.iter().enumerate() {
+        if let Label(_) = instr {
+            if index != start {
+                blocks.push((start, index));
+                start = index;
+            }
+        }
+
+        if matches!(instr, Ret | Jmp(_) | JmpReg(_) | Je(_) | Jne(_) | Jl(_) | Jg(_) | Jb(_) | Ja(_)) {
+            blocks.push((start, index + 1));
+            start = index + 1;
+        }
+    }
+
+    if start != code.len() {
+        blocks.push((start, code.len()));
+    }
+
+    blocks
+}
+
+/// Returns the symbol the instruction refers to
+pub(crate) fn referenced(instr: &AsmInstructionEnum) -> Option<&String> {
+    match instr {
+        Call(target) | Jmp(target) | Je(target) | Jne(target) | Jl(target) | Jg(target) | Jb(target) | Ja(target) |
+        MovPtr(_, target) | PushLabel(target) | PushPtr(target) => Some(target),
+        _ => None,
+    }
+}
+
+/// Returns the successors of every block (`None` if they aren't known because of an indirect jump)
+fn successors(code: &[AsmInstructionEnum], blocks: &[(usize, usize)]) -> Vec<Option<Vec<usize>>> {
+    let block_of = |label: &String| blocks.iter().position(|block| code[block.0] == Label(label.to_owned()));
+
+    blocks.iter().enumerate().map(|(nr, block)| {
+        let instrs = &code[block.0..block.1];
+        let mut succs = vec![];
+
+        // labels whose address is taken could be jumped to from anywhere
+        for instr in instrs {
+            if let Some(block) = referenced(instr).and_then(block_of) {
+                succs.push(block);
+            }
+        }
+
+        match instrs.last() {
+            Some(Ret) | Some(Jmp(_)) => {},
+            Some(JmpReg(_)) => return None,
+            _ if nr + 1 < blocks.len() => succs.push(nr + 1),
+            _ => {},
+        }
+
+        Some(succs)
+    }).collect()
+}
+
+/// Removes the instructions which can't be reached from the start of the function
+pub fn dce(code: Vec<AsmInstructionEnum>, ctx: &PassContext) -> Result<Vec<AsmInstructionEnum>, Box<dyn Error>> {
+    let blocks = blocks(&code);
+    let succs = successors(&code, &blocks);
+
+    // an indirect jump can reach every label
+    if succs.iter().any(|succs| succs.is_none()) {
+        return Ok(code);
+    }
+
+    let mut reachable = vec![false; blocks.len()];
+    let mut stack = vec![0];
+
+    while let Some(block) = stack.pop() {
+        if block >= blocks.len() || reachable[block] {
+            continue;
+        }
+
+        reachable[block] = true;
+        stack.extend(succs[block].iter().flatten());
+    }
+
+    let opt: Vec<AsmInstructionEnum> = blocks.iter().zip(reachable)
+        .filter(|(_, reachable)| *reachable)
+        .flat_map(|(block, _)| code[block.0..block.1].to_owned())
+        .collect();
+
+    if opt.len() != code.len() {
+        ctx.remark(format!("removed {} unreachable instructions", code.len() - opt.len()));
+    }
+
+    Ok(opt)
+}
+
+/// The bytes of the stack frame whose values are read later
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+struct Live {
+    /// Every byte could be read
+    all: bool,
+    bytes: BTreeSet<i64>,
+}
+
+impl Live {
+    fn everything() -> Self {
+        Self { all: true, bytes: BTreeSet::new() }
+    }
+
+    fn union(&mut self, other: &Live) {
+        self.all |= other.all;
+        self.bytes.extend(other.bytes.iter());
+    }
+
+    fn any(&self, displ: i64, size: usize) -> bool {
+        self.all || (displ..displ + size as i64).any(|byte| self.bytes.contains(&byte))
+    }
+}
+
+/// Returns the displacement if the operand is a fixed slot of the stack frame
+fn slot(mem: &MemoryOperand) -> Option<i64> {
+    (mem.base == Register::RBP && mem.index == Register::None).then_some(mem.displacement)
+}
+
+/// Returns the memory operand the instruction reads and the size of the read
+fn read_mem(instr: &AsmInstructionEnum) -> Option<(&MemoryOperand, usize)> {
+    match instr {
+        Load(reg, mem) | AddMem(reg, mem) | SubMem(reg, mem) | MulMem(reg, mem) | DivMem(reg, mem) => Some((mem, reg.size())),
+        IncMem(mem) | DecMem(mem) => Some((mem, mem.scale as usize)),
+        _ => None,
+    }
+}
+
+/// Updates the liveness from after the instruction to before it and returns if it is a dead store
+fn transfer(instr: &AsmInstructionEnum, live: &mut Live) -> bool {
+    match instr {
+        Ret => *live = Live::default(),
+        Store(reg, mem) => {
+            if let Some(displ) = slot(mem) {
+                if !live.any(displ, reg.size()) {
+                    return true;
+                }
+
+                for byte in displ..displ + reg.size() as i64 {
+                    live.bytes.remove(&byte);
+                }
+            }
+        },
+        instr => if let Some((mem, size)) = read_mem(instr) {
+            match slot(mem) {
+                Some(displ) => live.bytes.extend(displ..displ + size as i64),
+                None if [Register::RBP, Register::RSP].contains(&mem.base) => live.all = true,
+                None => {},
+            }
+        },
+    }
+
+    false
+}
+
+/// Removes the stores into the stack frame which are never read
+///
+/// Functions which take the address of the stack or change rbp are kept as they are
+pub fn dse(code: Vec<AsmInstructionEnum>, ctx: &PassContext) -> Result<Vec<AsmInstructionEnum>, Box<dyn Error>> {
+    if frame_escapes(&code) {
+        return Ok(code);
+    }
+
+    let blocks = blocks(&code);
+    let succs = successors(&code, &blocks);
+
+    let labels: HashSet<&String> = code.iter().filter_map(|instr| match instr {
+        Label(label) => Some(label),
+        _ => None,
+    }).collect();
+
+    // the callee of a tail call can read the stack arguments which were stored into the frame of the caller
+    let tail_call = |block: usize| matches!(&code[blocks[block].1 - 1], Jmp(target) if !labels.contains(target));
+
+    let live_out = |live_in: &Vec<Live>, block: usize| match &succs[block] {
+        _ if tail_call(block) => Live::everything(),
+        Some(succs) => {
+            let mut live = Live::default();
+            for succ in succs {
+                live.union(&live_in[*succ]);
+            }
+            live
+        },
+        None => Live::everything(),
+    };
+
+    let mut live_in = vec![Live::default(); blocks.len()];
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+
+        for (nr, block) in blocks.iter().enumerate().rev() {
+            let mut live = live_out(&live_in, nr);
+
+            for instr in code[block.0..block.1].iter().rev() {
+                transfer(instr, &mut live);
+            }
+
+            if live != live_in[nr] {
+                live_in[nr] = live;
+                changed = true;
+            }
+        }
+    }
+
+    let mut dead = HashSet::new();
+
+    for (nr, block) in blocks.iter().enumerate() {
+        let mut live = live_out(&live_in, nr);
+
+        for index in (block.0..block.1).rev() {
+            if transfer(&code[index], &mut live) {
+                dead.insert(index);
+            }
+        }
+    }
+
+    if !dead.is_empty() {
+        ctx.remark(format!("eliminated {} dead stores", dead.len()));
+    }
+
+    Ok(code.into_iter().enumerate()
+        .filter(|(index, _)| !dead.contains(index))
+        .map(|(_, instr)| instr)
+        .collect())
+}
+
+/// Returns the functions and labels of the builder which are public or used by a used function
+///
+/// `funcs` is the code of the functions (after inlining, see `inline_module`)
+pub fn used_symbols(builder: &Builder, funcs: &HashMap<String, Vec<AsmInstructionEnum>>) -> HashSet<String> {
+    let mut used = HashSet::new();
+
+    let mut stack: Vec<String> = builder.funcs.iter().filter(|func| func.1.0).map(|func| func.0.to_owned()).collect();
+    stack.extend(builder.labels.iter().filter(|label| label.1.0).map(|label| label.0.to_owned()));
+
+    while let Some(name) = stack.pop() {
+        if !used.insert(name.to_owned()) {
+            continue;
+        }
+
+        if let Some(code) = funcs.get(&name) {
+            stack.extend(code.iter().filter_map(referenced).cloned());
+        }
+    }
+
+    used
+}
@@ -0,0 +1,226 @@
+//! Inlining of small private functions into their callers
+//!
+//! The stack slots of the inlined function are placed below the frame of the caller,
+//! its labels get renamed and every return becomes a jump behind the inlined code
+
+use std::collections::HashMap;
+
+use iced_x86::Register;
+
+use crate::{ir::AsmInstructionEnum::{self, *}, target::Abi, Builder};
+
+use super::{frame_size, mem_access, mem_access_mut, remark::Remark, used_callee_saved, written_reg};
+
+/// Returns the labels the code defines
+fn labels(code: &[AsmInstructionEnum]) -> Vec<String> {
+    code.iter().filter_map(|instr| match instr {
+        Label(label) => Some(label.to_owned()),
+        _ => None,
+    }).collect()
+}
+
+/// Renames the symbol the instruction refers to (or defines)
+fn rename(instr: AsmInstructionEnum, name: impl Fn(String) -> String) -> AsmInstructionEnum {
+    match instr {
+        Label(label) => Label(name(label)),
+        Call(target) => Call(name(target)),
+        Jmp(target) => Jmp(name(target)),
+        Je(target) => Je(name(target)),
+        Jne(target) => Jne(name(target)),
+        Jl(target) => Jl(name(target)),
+        Jg(target) => Jg(name(target)),
+        Jb(target) => Jb(name(target)),
+        Ja(target) => Ja(name(target)),
+        MovPtr(reg, target) => MovPtr(reg, name(target)),
+        PushLabel(target) => PushLabel(name(target)),
+        PushPtr(target) => PushPtr(name(target)),
+        instr => instr,
+    }
+}
+
+/// Returns if everything the code pushes (or allocates) on the stack is removed before it returns
+fn rsp_balanced(code: &[AsmInstructionEnum]) -> bool {
+    let mut depth: i64 = 0;
+
+    for instr in code {
+        match instr {
+            Push(_) | PushVal(_) | PushLabel(_) | PushPtr(_) => depth += 8,
+            Pop(_) => depth -= 8,
+            SubVal(reg, value) if reg.full_register() == Register::RSP => depth += value,
+            AddVal(reg, value) if reg.full_register() == Register::RSP => depth -= value,
+            Ret if depth != 0 => return false,
+            _ => {},
+        }
+
+        if depth < 0 {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Returns if the code can be placed into another function
+///
+/// It may only use its own stack slots (no stack arguments), mustn't change rbp or
+/// the callee saved registers, needs to remove what it pushes and may only jump to its own labels
+pub fn inlinable(code: &[AsmInstructionEnum], abi: &Abi) -> bool {
+    let frame = |reg: &Register| [Register::RBP, Register::RSP].contains(&reg.full_register());
+    let labels = labels(code);
+
+    if !used_callee_saved(code, abi).is_empty() || !rsp_balanced(code) {
+        return false;
+    }
+
+    code.iter().all(|instr| {
+        if let Some((mem, _)) = mem_access(instr) {
+            let own_slot = mem.base == Register::RBP && mem.displacement < 0;
+
+            if frame(&mem.index) || (frame(&mem.base) && !own_slot) {
+                return false;
+            }
+        }
+
+        match instr {
+            JmpReg(_) => false,
+            Jmp(target) | Je(target) | Jne(target) | Jl(target) | Jg(target) | Jb(target) | Ja(target) => labels.contains(target),
+            MovReg(_, src) | MovSx(_, src) | AddReg(_, src) | SubReg(_, src) | XorReg(_, src) | CmpReg(_, src) | Push(src) => !frame(src),
+            AddVal(reg, _) | SubVal(reg, _) => reg.full_register() != Register::RBP,
+            instr => !written_reg(instr).is_some_and(|reg| frame(&reg)),
+        }
+    })
+}
+
+/// Returns the code of the callee for its `nr`th inlined call in a function whose frame has the size `frame`
+pub fn inline_body(callee: &str, code: &[AsmInstructionEnum], nr: usize, frame: i64) -> Vec<AsmInstructionEnum> {
+    let labels = labels(code);
+    let end = format!(".{callee}.{nr}");
+
+    let last = code.iter().rposition(|instr| !matches!(instr, Loc(_)));
+    let mut body = vec![];
+    let mut returns = false;
+
+    for (index, instr) in code.iter().enumerate() {
+        let mut instr = rename(instr.to_owned(), |label| {
+            if labels.contains(&label) { format!("{end}.{label}") } else { label }
+        });
+
+        if let Some(mem) = mem_access_mut(&mut instr) {
+            if mem.base == Register::RBP {
+                mem.displacement -= frame;
+            }
+        }
+
+        match instr {
+            Ret if Some(index) == last => {},
+            Ret => {
+                body.push(Jmp(end.to_owned()));
+                returns = true;
+            },
+            instr => body.push(instr),
+        }
+    }
+
+    if returns {
+        body.push(Label(end));
+    }
+
+    body
+}
+
+/// Inlines the calls to other functions of the builder into every function
+///
+/// Private functions with at most `threshold` instructions (without `Loc` markers) get inlined,
+/// `FuncAttrs::always_inline` ignores the size (and also inlines public functions) and `FuncAttrs::noinline` prevents it
+///
+/// Returns the code of every function and a remark for every inlined call
+pub fn inline_module(builder: &Builder, threshold: usize) -> (HashMap<String, Vec<AsmInstructionEnum>>, Vec<Remark>) {
+    let mut inliner = Inliner {
+        builder,
+        threshold,
+        done: HashMap::new(),
+        active: vec![],
+        remarks: vec![],
+    };
+
+    for name in builder.funcs.keys() {
+        inliner.func(name);
+    }
+
+    (inliner.done, inliner.remarks)
+}
+
+/// Inlines the callees before their callers (so calls of calls get inlined too)
+struct Inliner<'a> {
+    builder: &'a Builder,
+    threshold: usize,
+
+    /// The functions whose calls are already inlined
+    done: HashMap<String, Vec<AsmInstructionEnum>>,
+
+    /// The functions whose calls are currently inlined (recursive calls of them stay calls)
+    active: Vec<String>,
+
+    remarks: Vec<Remark>,
+}
+
+impl Inliner<'_> {
+    /// Returns the code of the function with the calls inlined
+    fn func(&mut self, name: &str) -> Vec<AsmInstructionEnum> {
+        if let Some(code) = self.done.get(name) {
+            return code.to_owned();
+        }
+
+        let code = self.builder.funcs[name].1.to_owned();
+
+        if self.builder.attrs(name).naked {
+            self.done.insert(name.into(), code.to_owned());
+            return code;
+        }
+
+        self.active.push(name.into());
+
+        let frame = frame_size(&code, self.builder.vars.get(name), &self.builder.abi);
+        let mut inlined = vec![];
+        let mut calls = 0;
+
+        for instr in code {
+            if let Call(callee) = &instr {
+                if let Some(body) = self.callee(callee) {
+                    inlined.extend(inline_body(callee, &body, calls, frame));
+                    calls += 1;
+
+                    self.remarks.push(Remark::new("inline", name, format!("inlined {callee} into {name}")));
+                    continue;
+                }
+            }
+
+            inlined.push(instr);
+        }
+
+        self.active.pop();
+        self.done.insert(name.into(), inlined.to_owned());
+
+        inlined
+    }
+
+    /// Returns the code of the callee if its calls get inlined
+    fn callee(&mut self, name: &str) -> Option<Vec<AsmInstructionEnum>> {
+        let public = self.builder.funcs.get(name)?.0;
+        let attrs = self.builder.attrs(name);
+
+        if attrs.noinline || attrs.naked || (public && !attrs.always_inline) || self.active.iter().any(|func| func == name) {
+            return None;
+        }
+
+        // the entries of jump tables are relative to the start of the function
+        if self.builder.jump_tables.values().any(|table| table.0 == name) {
+            return None;
+        }
+
+        let code = self.func(name);
+        let size = code.iter().filter(|instr| !matches!(instr, Loc(_))).count();
+
+        ((attrs.always_inline || size <= self.threshold) && inlinable(&code, &self.builder.abi)).then_some(code)
+    }
+}
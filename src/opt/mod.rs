@@ -0,0 +1,328 @@
+//! Safety time!
+
+use crate::ir::{AsmInstructionEnum::{self, *}, Type};
+use crate::{target::Abi, x86::attrs::FuncAttrs};
+use remark::Remark;
+use iced_x86::{MemoryOperand, Register};
+use std::{collections::VecDeque, error::Error};
+
+pub mod dce;
+pub mod inline;
+pub mod pass;
+pub mod peephole;
+pub mod remark;
+pub mod strength;
+pub mod tail;
+
+pub use pass::{OptLevel, Pass, PassContext, PassManager};
+
+/*macro_rules! instr {
+    ($instr_var:ident => $instr:ident; $last_instr_var:ident => $instr2:ident => $($arg:tt)*) => {
+        matches!($instr_var, $instr($($arg)*) if $last_instr_var == $instr2( $($arg)* ))
+    };
+}*/
+
+/// Returns the memory operand and the access size of the instruction
+fn mem_access(instr: &AsmInstructionEnum) -> Option<(&MemoryOperand, i64)> {
+    match instr {
+        Load(reg, mem) | Store(reg, mem) | AddMem(reg, mem) | SubMem(reg, mem) |
+        MulMem(reg, mem) | DivMem(reg, mem) => Some((mem, reg.size() as i64)),
+        Lea(_, mem) => Some((mem, 1)),
+        IncMem(mem) | DecMem(mem) => Some((mem, mem.scale as i64)),
+        _ => None,
+    }
+}
+
+/// Returns the memory operand of the instruction (mutable)
+fn mem_access_mut(instr: &mut AsmInstructionEnum) -> Option<&mut MemoryOperand> {
+    match instr {
+        Load(_, mem) | Store(_, mem) | AddMem(_, mem) | SubMem(_, mem) |
+        MulMem(_, mem) | DivMem(_, mem) | Lea(_, mem) | IncMem(mem) | DecMem(mem) => Some(mem),
+        _ => None,
+    }
+}
+
+/// Returns the size of the stack frame (without the saved rbp) which the function needs
+///
+/// It is big enough for every variable of the layout `vars` (`(name, rbp displacement, type)`)
+/// and every stack access of the code and is aligned to the abi stack alignment
+pub fn frame_size(code: &[AsmInstructionEnum], vars: Option<&Vec<(String, i64, Type)>>, abi: &Abi) -> i64 {
+    let mut size = 0;
+
+    for instr in code {
+        if let Some((mem, _)) = mem_access(instr) {
+            if mem.base == Register::RBP && mem.displacement < 0 {
+                size = size.max(-mem.displacement);
+            }
+        }
+    }
+
+    if let Some(vars) = vars {
+        for var in vars {
+            if var.1 < 0 {
+                size = size.max(-var.1);
+            }
+        }
+    }
+
+    (size + abi.stack_align - 1) / abi.stack_align * abi.stack_align
+}
+
+/// Returns the register the instruction writes
+pub(crate) fn written_reg(instr: &AsmInstructionEnum) -> Option<Register> {
+    match instr {
+        MovVal(reg, _) | MovReg(reg, _) | MovSx(reg, _) | MovPtr(reg, _) | Load(reg, _) | Lea(reg, _) |
+        Inc(reg) | Dec(reg) | Pop(reg) |
+        AddVal(reg, _) | AddReg(reg, _) | AddMem(reg, _) |
+        SubVal(reg, _) | SubReg(reg, _) | SubMem(reg, _) | XorReg(reg, _) |
+        MulVal(reg, _) | MulReg(reg, _) | MulMem(reg, _) |
+        DivVal(reg, _) | DivReg(reg, _) | DivMem(reg, _) |
+        UDivVal(reg, _) | RemVal(reg, _) | URemVal(reg, _) |
+        ShlVal(reg, _) | ShrVal(reg, _) | SarVal(reg, _) |
+        ShlCl(reg) | ShrCl(reg) | SarCl(reg) => Some(*reg),
+        _ => None,
+    }
+}
+
+/// Returns if the code takes the address of the stack frame (or changes rbp)
+pub(crate) fn frame_escapes(code: &[AsmInstructionEnum]) -> bool {
+    let frame = |reg: &Register| [Register::RBP, Register::RSP].contains(&reg.full_register());
+
+    code.iter().any(|instr| match instr {
+        Lea(_, mem) => frame(&mem.base),
+        MovReg(_, src) | AddReg(_, src) | SubReg(_, src) | XorReg(_, src) | Push(src) => frame(src),
+        instr => written_reg(instr).map(|reg| reg.full_register()) == Some(Register::RBP),
+    })
+}
+
+/// Returns the callee saved registers of the abi which the code writes
+pub fn used_callee_saved(code: &[AsmInstructionEnum], abi: &Abi) -> Vec<Register> {
+    let mut written = vec![];
+
+    for instr in code {
+        if let Some(reg) = written_reg(instr) {
+            let reg = if reg.is_xmm() { reg } else { reg.full_register() };
+
+            if !written.contains(&reg) {
+                written.push(reg);
+            }
+        }
+    }
+
+    abi.callee_saved.iter()
+        .filter(|reg| written.contains(reg))
+        .map(|reg| reg.to_owned())
+        .collect()
+}
+
+/// Optimizes and makes the incoming ir safe (for the host abi, see `optimize`)
+///
+/// The stack frame gets its size from the slots the code uses and the function gets the default attributes
+pub fn Optimize(code: Vec<AsmInstructionEnum>) -> Result<Vec<AsmInstructionEnum>, Box<dyn Error>> {
+    let abi = Abi::host();
+    let frame = frame_size(&code, None, &abi);
+
+    optimize(code, &abi, frame, &FuncAttrs::default())
+}
+
+/// Optimizes and makes the incoming ir safe
+///
+/// Runs the default passes (see `PassManager`) and adds the stack frame (see `lower_frame`)
+pub fn optimize(code: Vec<AsmInstructionEnum>, abi: &Abi, frame: i64, attrs: &FuncAttrs) -> Result<Vec<AsmInstructionEnum>, Box<dyn Error>> {
+    let code = PassManager::default().run(code, &PassContext { name: "", abi, attrs, stack_args: 0, remarks: Default::default() })?;
+
+    lower_frame(code, abi, frame, attrs)
+}
+
+/// Returns if the function can address its stack slots with rsp instead of rbp:
+/// it doesn't call other functions, doesn't change rsp and only uses rbp as the base of memory operands
+fn frameless(code: &[AsmInstructionEnum]) -> bool {
+    let frame = |reg: &Register| [Register::RBP, Register::RSP].contains(&reg.full_register());
+
+    code.iter().all(|instr| {
+        if let Some((mem, _)) = mem_access(instr) {
+            if frame(&mem.index) || mem.base.full_register() == Register::RSP {
+                return false;
+            }
+        }
+
+        match instr {
+            Call(_) | Push(_) | Pop(_) | PushVal(_) | PushLabel(_) | PushPtr(_) => false,
+            MovReg(_, src) | MovSx(_, src) | AddReg(_, src) | SubReg(_, src) | XorReg(_, src) | CmpReg(_, src) | MulReg(_, src) => !frame(src),
+            instr => !written_reg(instr).is_some_and(|reg| frame(&reg)),
+        }
+    })
+}
+
+/// The label of the epilogue the returns jump to (see `FuncAttrs::shared_epilogue`)
+const EPILOGUE: &str = ".epilogue";
+
+/// Adds the prologue and an epilogue for every return and every tail jump to another function
+///
+/// `frame` is the size of the stack frame for the variables (see `frame_size`),
+/// the callee saved registers the code writes get saved below it
+///
+/// Leaf functions (see `frameless`) don't set up rbp, their slots are addressed with rsp
+/// and are placed into the red zone of the abi if they fit (unless `FuncAttrs::keep_frame_pointer` is set)
+///
+/// With `Abi::frame_after_alloc` rbp gets set after the allocation (for frames above 240 bytes it points below the pushed rbp
+/// and the rbp displacements get adjusted)
+/// 
+/// Naked functions are returned unchanged, noreturn functions don't get an epilogue
+/// and with `FuncAttrs::shared_epilogue` the returns jump to a single epilogue at the end
+pub fn lower_frame(code: Vec<AsmInstructionEnum>, abi: &Abi, frame: i64, attrs: &FuncAttrs) -> Result<Vec<AsmInstructionEnum>, Box<dyn Error>> {
+    Ok(lower_frame_remarks(code, abi, frame, attrs, "")?.0)
+}
+
+/// Like `lower_frame`, but also returns remarks for the function `name`
+/// if it got a leaf frame or a shared epilogue
+pub fn lower_frame_remarks(
+    code: Vec<AsmInstructionEnum>,
+    abi: &Abi,
+    frame: i64,
+    attrs: &FuncAttrs,
+    name: &str,
+) -> Result<(Vec<AsmInstructionEnum>, Vec<Remark>), Box<dyn Error>> {
+    if attrs.naked {
+        return Ok((code, vec![]));
+    }
+
+    let mut remarks = vec![];
+
+    // a noreturn function never needs to restore the registers
+    let saved = if attrs.noreturn { vec![] } else { used_callee_saved(&code, abi) };
+
+    let mut saves = vec![];
+    let mut save_offset = frame;
+
+    for reg in saved {
+        save_offset += reg.size() as i64;
+        saves.push((reg, abi.stack(-save_offset)));
+    }
+
+    let leaf = !attrs.keep_frame_pointer && save_offset + 8 <= abi.page_size && frameless(&code);
+
+    // without the pushed rbp the slots end 8 bytes below the return address
+    let frame = match leaf {
+        false => (save_offset + abi.stack_align - 1) / abi.stack_align * abi.stack_align,
+        true if save_offset == 0 || save_offset + 8 <= abi.red_zone => 0,
+        true => save_offset + 8,
+    };
+
+    if leaf && frame == 0 && save_offset != 0 {
+        remarks.push(Remark::new("lower-frame", name, format!("omitted the frame pointer and placed the {save_offset} byte frame into the red zone")));
+    } else if leaf {
+        remarks.push(Remark::new("lower-frame", name, "omitted the frame pointer of the leaf function".into()));
+    }
+
+    // Restores the registers and the stack (gets inserted before every return)
+    let mut epilogue = vec![];
+
+    for save in saves.iter() {
+        epilogue.push(Load(save.0, save.1));
+    }
+
+    if frame != 0 {
+        epilogue.push(AddVal(Register::RSP, frame));
+    }
+    if !leaf {
+        epilogue.push(AsmInstructionEnum::Pop(Register::RBP)); // for stack safty
+    }
+
+    let labels: Vec<String> = code.iter().filter_map(|instr| match instr {
+        Label(label) => Some(label.to_owned()),
+        _ => None,
+    }).collect();
+
+    // the returns jump to a single copy of the epilogue at the end of the function
+    let returns = code.iter().filter(|instr| **instr == Ret).count();
+    let shared = attrs.shared_epilogue && !epilogue.is_empty() && returns > 1;
+
+    if shared {
+        remarks.push(Remark::new("lower-frame", name, format!("the {returns} returns jump to a shared epilogue")));
+    }
+
+    let mut opt: VecDeque<AsmInstructionEnum> = VecDeque::new();
+
+    for instr in code {
+        match instr {
+            Ret if shared => opt.push_back(Jmp(EPILOGUE.into())),
+            Ret => {
+                opt.extend(epilogue.iter().cloned());
+                opt.push_back(Ret);
+            },
+            Nop => { /* CHILL */ },
+            // a tail jump to another function leaves the frame like a return
+            Jmp(target) if !labels.contains(&target) => {
+                opt.extend(epilogue.iter().cloned());
+                opt.push_back(Jmp(target));
+            },
+            instr => opt.push_back(instr),
+        }
+    }
+
+    if shared {
+        if opt.back() == Some(&Jmp(EPILOGUE.into())) {
+            opt.pop_back();
+        }
+
+        opt.push_back(Label(EPILOGUE.into()));
+        opt.extend(epilogue.iter().cloned());
+        opt.push_back(Ret);
+    } else if !matches!(opt.back(), Some(Ret | Jmp(_))) && !attrs.noreturn {
+        opt.extend(epilogue.iter().cloned());
+        opt.push_back(Ret);
+    }
+
+    // Setup the stack and add
+    for save in saves.iter().rev() {
+        opt.push_front(Store(save.0, save.1));
+    }
+
+    // rbp points at most 240 bytes (the largest frame offset of the windows unwind info) above the allocation
+    let frame_offset = if abi.frame_after_alloc && !leaf { frame.min(240) } else { 0 };
+
+    if frame_offset != 0 {
+        opt.push_front(Lea(Register::RBP, abi.ptr(Register::RSP, frame_offset)));
+    }
+
+    if frame > abi.page_size && abi.stack_probe.is_some() { // every page needs to be touched in order
+        opt.push_front(SubReg(Register::RSP, Register::RAX));
+        opt.push_front(Call(abi.stack_probe.to_owned().unwrap()));
+        opt.push_front(MovVal(Register::RAX, frame));
+    } else if frame != 0 {
+        opt.push_front(SubVal(Register::RSP, frame));
+    }
+
+    if leaf { // rbp would be 8 bytes below the return address
+        for instr in opt.iter_mut() {
+            if let Some(mem) = mem_access_mut(instr) {
+                if mem.base == Register::RBP {
+                    mem.base = Register::RSP;
+                    mem.displacement += frame - 8;
+                }
+            }
+        }
+
+        return Ok((opt.into(), remarks));
+    }
+
+    if frame_offset != 0 && frame_offset != frame { // rbp is below the pushed rbp
+        for instr in opt.iter_mut() {
+            if let Some(mem) = mem_access_mut(instr) {
+                if mem.base == Register::RBP {
+                    mem.displacement += frame - frame_offset;
+                }
+            }
+        }
+    }
+
+    if frame_offset == 0 {
+        opt.push_front(MovReg(Register::RBP, Register::RSP));
+    }
+
+    opt.push_front(Push(Register::RBP));
+    //opt.push_front(Endbr64);
+
+    Ok((opt.into(), remarks))
+}
@@ -0,0 +1,226 @@
+//! The pass manager which runs the optimization passes over the ir of a function
+//!
+//! The passes run before the stack frame is added (see `lower_frame`)
+
+use std::{cell::RefCell, error::Error, fmt, sync::Arc};
+
+use crate::{error::CodeGenLibError, ir::AsmInstructionEnum::{self, *}, target::Abi, x86::attrs::FuncAttrs};
+
+use super::{dce::{dce, dse}, peephole::peephole, remark::Remark, strength::{strength_reduction, strength_reduction_small}, tail::tail_calls};
+
+/// What the passes know about the function they run on
+#[derive(Debug, Clone)]
+pub struct PassContext<'a> {
+    pub name: &'a str,
+    pub abi: &'a Abi,
+    pub attrs: &'a FuncAttrs,
+
+    /// How many arguments the function gets passed on the stack
+    pub stack_args: usize,
+
+    /// The remarks of the passes which ran (see `PassContext::remark`)
+    pub remarks: RefCell<Vec<Remark>>,
+}
+
+impl PassContext<'_> {
+    /// Records why the running pass changed the function
+    pub fn remark(&self, message: String) {
+        self.remarks.borrow_mut().push(Remark::new("", self.name, message));
+    }
+}
+
+/// The function of a pass
+pub type PassFn = dyn Fn(Vec<AsmInstructionEnum>, &PassContext) -> Result<Vec<AsmInstructionEnum>, Box<dyn Error>> + Send + Sync;
+
+/// A named rewrite of the ir of a function
+#[derive(Clone)]
+pub struct Pass {
+    pub name: String,
+    func: Arc<PassFn>,
+}
+
+impl Pass {
+    /// Creates a new pass
+    pub fn new<F>(name: &str, func: F) -> Self
+    where
+        F: Fn(Vec<AsmInstructionEnum>, &PassContext) -> Result<Vec<AsmInstructionEnum>, Box<dyn Error>> + Send + Sync + 'static,
+    {
+        Self {
+            name: name.into(),
+            func: Arc::new(func),
+        }
+    }
+
+    /// Runs the pass over the code
+    pub fn run(&self, code: Vec<AsmInstructionEnum>, ctx: &PassContext) -> Result<Vec<AsmInstructionEnum>, Box<dyn Error>> {
+        (self.func)(code, ctx)
+    }
+}
+
+impl fmt::Debug for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Pass({})", self.name)
+    }
+}
+
+/// The optimization presets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptLevel {
+    /// No optimizations
+    O0,
+    /// Cheap optimizations (the default)
+    O1,
+    /// Every optimization, the passes run until the code doesn't change anymore
+    O2,
+    /// Optimizations for small code
+    Os,
+}
+
+/// Runs the passes in order
+#[derive(Debug, Clone)]
+pub struct PassManager {
+    pub passes: Vec<Pass>,
+
+    /// If the builder leaves out the private functions and labels which aren't used
+    pub remove_unused: bool,
+
+    /// The size (in instructions) up to which private functions get inlined by the builder (see `inline_module`)
+    pub inline_threshold: usize,
+
+    /// If every function of the builder sets up rbp (see `FuncAttrs::keep_frame_pointer`)
+    pub keep_frame_pointers: bool,
+
+    /// If every function of the builder has a single epilogue (see `FuncAttrs::shared_epilogue`)
+    pub shared_epilogues: bool,
+
+    /// How often the passes run at most (they stop early once the code doesn't change)
+    pub iterations: usize,
+}
+
+impl Default for PassManager {
+    fn default() -> Self {
+        Self::new(OptLevel::O1)
+    }
+}
+
+impl PassManager {
+    /// Creates the pipeline of the optimization level
+    pub fn new(level: OptLevel) -> Self {
+        let passes = match level {
+            OptLevel::O0 => vec![],
+            OptLevel::O1 | OptLevel::O2 => vec![
+                Pass::new("dce", dce),
+                Pass::new("strength-reduction", strength_reduction),
+                Pass::new("peephole", peephole),
+                Pass::new("dse", dse),
+                Pass::new("remove-nops", remove_nops),
+                Pass::new("tail-calls", tail_calls),
+            ],
+            OptLevel::Os => vec![
+                Pass::new("dce", dce),
+                Pass::new("strength-reduction", strength_reduction_small),
+                Pass::new("peephole", peephole),
+                Pass::new("dse", dse),
+                Pass::new("remove-nops", remove_nops),
+                Pass::new("tail-calls", tail_calls),
+            ],
+        };
+
+        Self {
+            passes,
+            remove_unused: level != OptLevel::O0,
+            inline_threshold: match level {
+                OptLevel::O0 => 0,
+                OptLevel::O1 => 12,
+                OptLevel::O2 => 32,
+                OptLevel::Os => 6,
+            },
+            keep_frame_pointers: level == OptLevel::O0,
+            shared_epilogues: level == OptLevel::Os,
+            iterations: if level == OptLevel::O2 { 4 } else { 1 },
+        }
+    }
+
+    /// Returns the names of the passes in the order they run
+    pub fn names(&self) -> Vec<&str> {
+        self.passes.iter().map(|pass| pass.name.as_str()).collect()
+    }
+
+    fn position(&self, name: &str) -> Result<usize, CodeGenLibError> {
+        match self.passes.iter().position(|pass| pass.name == name) {
+            Some(index) => Ok(index),
+            None => Err(CodeGenLibError::PassNotExist(name.into())),
+        }
+    }
+
+    /// Adds the pass at the end of the pipeline
+    pub fn add(&mut self, pass: Pass) {
+        self.passes.push(pass);
+    }
+
+    /// Adds the pass before the pass `before`
+    pub fn insert_before(&mut self, before: &str, pass: Pass) -> Result<(), CodeGenLibError> {
+        let index = self.position(before)?;
+        self.passes.insert(index, pass);
+
+        Ok(())
+    }
+
+    /// Adds the pass after the pass `after`
+    pub fn insert_after(&mut self, after: &str, pass: Pass) -> Result<(), CodeGenLibError> {
+        let index = self.position(after)?;
+        self.passes.insert(index + 1, pass);
+
+        Ok(())
+    }
+
+    /// Removes the pass `name` from the pipeline
+    pub fn remove(&mut self, name: &str) -> Result<(), CodeGenLibError> {
+        let index = self.position(name)?;
+        self.passes.remove(index);
+
+        Ok(())
+    }
+
+    /// Runs every pass over the code (naked functions are kept verbatim)
+    /// 
+    /// The pipeline is repeated up to `iterations` times, since a pass can enable changes of the ones before it
+    pub fn run(&self, mut code: Vec<AsmInstructionEnum>, ctx: &PassContext) -> Result<Vec<AsmInstructionEnum>, Box<dyn Error>> {
+        if ctx.attrs.naked {
+            return Ok(code);
+        }
+
+        for iteration in 0..self.iterations {
+            let before = if iteration + 1 < self.iterations { Some(code.to_owned()) } else { None };
+
+            for pass in self.passes.iter() {
+                let start = ctx.remarks.borrow().len();
+
+                code = pass.run(code, ctx)?;
+
+                for remark in ctx.remarks.borrow_mut()[start..].iter_mut() {
+                    remark.pass = pass.name.to_owned();
+                }
+            }
+
+            // the last iteration or nothing changed
+            match before {
+                Some(before) if before != code => {},
+                _ => break,
+            }
+        }
+
+        Ok(code)
+    }
+}
+
+/// Removes every `nop`
+pub fn remove_nops(code: Vec<AsmInstructionEnum>, ctx: &PassContext) -> Result<Vec<AsmInstructionEnum>, Box<dyn Error>> {
+    let nops = code.iter().filter(|instr| **instr == Nop).count();
+
+    if nops != 0 {
+        ctx.remark(format!("removed {nops} nops"));
+    }
+
+    Ok(code.into_iter().filter(|instr| *instr != Nop).collect())
+}
@@ -0,0 +1,227 @@
+//! The peephole optimizer: rewrites short windows of consecutive instructions
+//!
+//! `Loc` markers don't break a window, labels do
+
+use std::error::Error;
+
+use crate::ir::AsmInstructionEnum::{self, *};
+
+use super::PassContext;
+
+/// A rewrite of a few consecutive instructions
+#[derive(Debug, Clone, Copy)]
+pub struct Rule {
+    pub name: &'static str,
+
+    /// The number of instructions the rule looks at
+    pub window: usize,
+
+    /// Returns the replacement of the window (at most as long as the window) if the rule matches
+    ///
+    /// The second argument is the code which follows the window
+    pub rewrite: fn(&[AsmInstructionEnum], &[AsmInstructionEnum]) -> Option<Vec<AsmInstructionEnum>>,
+}
+
+/// The rules of the `peephole` pass
+pub const RULES: &[Rule] = &[
+    Rule { name: "store-load", window: 2, rewrite: store_load },
+    Rule { name: "load-store", window: 2, rewrite: load_store },
+    Rule { name: "self-move", window: 1, rewrite: self_move },
+    Rule { name: "move-back", window: 2, rewrite: move_back },
+    Rule { name: "identity", window: 1, rewrite: identity },
+    Rule { name: "xor-zero", window: 1, rewrite: xor_zero },
+    Rule { name: "inc-dec", window: 1, rewrite: inc_dec },
+];
+
+/// The pass which applies `RULES`
+pub fn peephole(code: Vec<AsmInstructionEnum>, ctx: &PassContext) -> Result<Vec<AsmInstructionEnum>, Box<dyn Error>> {
+    let (code, counts) = rewrite(code, RULES);
+
+    for (rule, count) in RULES.iter().zip(counts) {
+        if count != 0 {
+            ctx.remark(format!("applied {} {count} times", rule.name));
+        }
+    }
+
+    Ok(code)
+}
+
+/// Applies the rules until none of them matches anymore
+pub fn apply(code: Vec<AsmInstructionEnum>, rules: &[Rule]) -> Vec<AsmInstructionEnum> {
+    rewrite(code, rules).0
+}
+
+/// Applies the rules and returns how often each of them matched
+fn rewrite(mut code: Vec<AsmInstructionEnum>, rules: &[Rule]) -> (Vec<AsmInstructionEnum>, Vec<usize>) {
+    let mut counts = vec![0; rules.len()];
+    let longest = rules.iter().map(|rule| rule.window).max().unwrap_or(1);
+    let mut index = 0;
+
+    while index < code.len() {
+        if let Loc(_) = code[index] {
+            index += 1;
+            continue;
+        }
+
+        let mut matched = false;
+
+        for (nr, rule) in rules.iter().enumerate() {
+            let positions: Vec<usize> = (index..code.len())
+                .filter(|pos| !matches!(code[*pos], Loc(_)))
+                .take(rule.window)
+                .collect();
+
+            if positions.len() < rule.window {
+                continue;
+            }
+
+            let window: Vec<AsmInstructionEnum> = positions.iter().map(|pos| code[*pos].to_owned()).collect();
+            let last = positions[positions.len() - 1];
+
+            if let Some(replacement) = (rule.rewrite)(&window, &code[last + 1..]) {
+                for pos in positions.iter().skip(replacement.len()).rev() {
+                    code.remove(*pos);
+                }
+
+                for (pos, instr) in positions.iter().zip(replacement) {
+                    code[*pos] = instr;
+                }
+
+                counts[nr] += 1;
+                matched = true;
+                break;
+            }
+        }
+
+        if !matched {
+            index += 1;
+            continue;
+        }
+
+        // the rewrite can complete a window which starts before it
+        let mut back = longest - 1;
+        while back > 0 && index > 0 {
+            index -= 1;
+
+            if !matches!(code[index], Loc(_)) {
+                back -= 1;
+            }
+        }
+    }
+
+    (code, counts)
+}
+
+/// Returns if the code reads the flags (decided by `reads`) before it overwrites them
+fn live(code: &[AsmInstructionEnum], reads: fn(&AsmInstructionEnum) -> bool) -> bool {
+    for instr in code {
+        if reads(instr) {
+            return true;
+        }
+
+        match instr {
+            CmpVal(..) | CmpReg(..) |
+            AddVal(..) | AddReg(..) | AddMem(..) |
+            SubVal(..) | SubReg(..) | SubMem(..) | XorReg(..) |
+            MulVal(..) | MulReg(..) | MulMem(..) |
+            DivVal(..) | DivReg(..) | DivMem(..) | Idiv(_) | Mul(_) | Imul(_) |
+            UDivVal(..) | RemVal(..) | URemVal(..) => return false,
+
+            // the flags aren't preserved across calls
+            Call(_) | Ret => return false,
+
+            // the flags could be read after a jump or by the code which jumps to the label
+            Je(_) | Jne(_) | Jl(_) | Jg(_) | Jb(_) | Ja(_) | Jmp(_) | JmpReg(_) | Label(_) => return true,
+
+            _ => {},
+        }
+    }
+
+    false
+}
+
+/// Returns if the code reads any flag before it overwrites them
+pub fn flags_live(code: &[AsmInstructionEnum]) -> bool {
+    live(code, |instr| matches!(instr, Je(_) | Jne(_) | Jl(_) | Jg(_) | Jb(_) | Ja(_)))
+}
+
+/// Returns if the code reads the carry flag before it overwrites it
+pub fn carry_live(code: &[AsmInstructionEnum]) -> bool {
+    live(code, |instr| matches!(instr, Jb(_) | Ja(_)))
+}
+
+/// `mov [m], r; mov s, [m]` -> `mov [m], r; mov s, r`
+fn store_load(window: &[AsmInstructionEnum], _: &[AsmInstructionEnum]) -> Option<Vec<AsmInstructionEnum>> {
+    match window {
+        [Store(src, mem), Load(dst, other)] if mem == other => {
+            if src == dst {
+                Some(vec![window[0].to_owned()])
+            } else if src.is_gpr() && dst.is_gpr() && src.size() == dst.size() {
+                Some(vec![window[0].to_owned(), MovReg(*dst, *src)])
+            } else {
+                None
+            }
+        },
+        _ => None,
+    }
+}
+
+/// `mov r, [m]; mov [m], r` -> `mov r, [m]` (not if `r` is part of the address, the load changes `m`)
+fn load_store(window: &[AsmInstructionEnum], _: &[AsmInstructionEnum]) -> Option<Vec<AsmInstructionEnum>> {
+    match window {
+        [Load(dst, mem), Store(src, other)] if dst == src && mem == other => {
+            let addressed = [mem.base, mem.index].iter().any(|reg| reg.full_register() == dst.full_register());
+
+            if addressed { None } else { Some(vec![window[0].to_owned()]) }
+        },
+        _ => None,
+    }
+}
+
+/// `mov r, r` -> nothing (not for 32 bit registers, the move clears their upper half)
+fn self_move(window: &[AsmInstructionEnum], _: &[AsmInstructionEnum]) -> Option<Vec<AsmInstructionEnum>> {
+    match window {
+        [MovReg(dst, src)] if dst == src && !dst.is_gpr32() => Some(vec![]),
+        _ => None,
+    }
+}
+
+/// `mov a, b; mov b, a` -> `mov a, b`
+fn move_back(window: &[AsmInstructionEnum], _: &[AsmInstructionEnum]) -> Option<Vec<AsmInstructionEnum>> {
+    match window {
+        [MovReg(a, b), MovReg(c, d)] if a == d && b == c && !a.is_gpr32() && !b.is_gpr32() => Some(vec![window[0].to_owned()]),
+        _ => None,
+    }
+}
+
+/// `add r, 0`, `sub r, 0` and `imul r, 1` -> nothing (if the flags aren't read)
+fn identity(window: &[AsmInstructionEnum], after: &[AsmInstructionEnum]) -> Option<Vec<AsmInstructionEnum>> {
+    match window {
+        [AddVal(reg, 0) | SubVal(reg, 0) | MulVal(reg, 1)] if !reg.is_gpr32() && !flags_live(after) => Some(vec![]),
+        _ => None,
+    }
+}
+
+/// `mov r, 0` -> `xor r32, r32` (if the flags aren't read)
+fn xor_zero(window: &[AsmInstructionEnum], after: &[AsmInstructionEnum]) -> Option<Vec<AsmInstructionEnum>> {
+    match window {
+        [MovVal(reg, 0)] if (reg.is_gpr64() || reg.is_gpr32()) && !flags_live(after) => {
+            let reg = reg.full_register32();
+            Some(vec![XorReg(reg, reg)])
+        },
+        _ => None,
+    }
+}
+
+/// `add r, 1` -> `inc r` and `add r, -1` -> `dec r` (if the carry flag, which inc and dec keep, isn't read)
+fn inc_dec(window: &[AsmInstructionEnum], after: &[AsmInstructionEnum]) -> Option<Vec<AsmInstructionEnum>> {
+    if carry_live(after) {
+        return None;
+    }
+
+    match window {
+        [AddVal(reg, 1) | SubVal(reg, -1)] => Some(vec![Inc(*reg)]),
+        [AddVal(reg, -1) | SubVal(reg, 1)] => Some(vec![Dec(*reg)]),
+        _ => None,
+    }
+}
@@ -0,0 +1,51 @@
+//! Optimization remarks (why a pass changed a function) and code size statistics
+
+use std::fmt;
+
+use crate::ir::AsmInstructionEnum::{self, *};
+
+/// A change an optimization made to a function
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remark {
+    /// The pass which made the change (empty if the pass didn't run in a `PassManager`)
+    pub pass: String,
+    /// The function which was changed
+    pub func: String,
+    pub message: String,
+}
+
+impl Remark {
+    /// Creates a new remark
+    pub fn new(pass: &str, func: &str, message: String) -> Self {
+        Self {
+            pass: pass.into(),
+            func: func.into(),
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Remark {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.func, self.pass, self.message)
+    }
+}
+
+/// The size of a function without and with the optimizations (both with the stack frame)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuncStats {
+    pub name: String,
+
+    /// The instructions (see `instr_count`)
+    pub instrs_before: usize,
+    pub instrs_after: usize,
+
+    /// The bytes of the machine code
+    pub bytes_before: usize,
+    pub bytes_after: usize,
+}
+
+/// Returns the count of the instructions (labels and source locations aren't counted)
+pub fn instr_count(code: &[AsmInstructionEnum]) -> usize {
+    code.iter().filter(|instr| !matches!(instr, Label(_) | Loc(_))).count()
+}
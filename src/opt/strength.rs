@@ -0,0 +1,300 @@
+//! Strength reduction: multiplications and divisions by constants get replaced with cheaper instructions
+//!
+//! * multiplications by powers of two become shifts, by small constants `lea`s
+//! * divisions by powers of two become shifts (with a rounding fix for signed values)
+//! * other divisions become a multiplication with the magic number of the divisor
+//!   (see "Division by Invariant Integers using Multiplication", Granlund and Montgomery)
+
+use std::error::Error;
+
+use iced_x86::{MemoryOperand, Register};
+
+use crate::ir::AsmInstructionEnum::{self, *};
+
+use super::{peephole::flags_live, PassContext};
+
+/// The pass which reduces multiplications and divisions
+pub fn strength_reduction(code: Vec<AsmInstructionEnum>, ctx: &PassContext) -> Result<Vec<AsmInstructionEnum>, Box<dyn Error>> {
+    Ok(remark(code, false, ctx))
+}
+
+/// The pass which reduces multiplications and divisions without making the code larger
+/// (multiplications only use a single `lea`)
+pub fn strength_reduction_small(code: Vec<AsmInstructionEnum>, ctx: &PassContext) -> Result<Vec<AsmInstructionEnum>, Box<dyn Error>> {
+    Ok(remark(code, true, ctx))
+}
+
+/// Reduces the code and records how many operations got replaced
+fn remark(code: Vec<AsmInstructionEnum>, small: bool, ctx: &PassContext) -> Vec<AsmInstructionEnum> {
+    let (reduced, count) = replace(code, small);
+
+    if count != 0 {
+        ctx.remark(format!("reduced {} multiplications and divisions by constants", count));
+    }
+
+    reduced
+}
+
+/// Replaces the multiplications and divisions by constants (`small` limits the multiplications to one `lea`)
+///
+/// Invalid divisions are kept for the verifier
+pub fn reduce(code: Vec<AsmInstructionEnum>, small: bool) -> Vec<AsmInstructionEnum> {
+    replace(code, small).0
+}
+
+/// Reduces the code and returns how many operations got replaced
+fn replace(code: Vec<AsmInstructionEnum>, small: bool) -> (Vec<AsmInstructionEnum>, usize) {
+    let mut reduced = vec![];
+    let mut count = 0;
+
+    for (index, instr) in code.iter().enumerate() {
+        let instrs = match instr {
+            MulVal(reg, value) if !flags_live(&code[index + 1..]) => mul(*reg, *value, small),
+            DivVal(reg, value) => div(*reg, *value, true, false),
+            UDivVal(reg, value) => div(*reg, *value, false, false),
+            RemVal(reg, value) => div(*reg, *value, true, true),
+            URemVal(reg, value) => div(*reg, *value, false, true),
+            _ => None,
+        };
+
+        match instrs {
+            Some(instrs) => {
+                reduced.extend(instrs);
+                count += 1;
+            },
+            None => reduced.push(instr.to_owned()),
+        }
+    }
+
+    (reduced, count)
+}
+
+/// Returns `reg + reg * scale`
+fn lea(reg: Register, scale: u32) -> AsmInstructionEnum {
+    Lea(reg, MemoryOperand::new(reg, reg, scale, 0, 0, false, Register::None))
+}
+
+/// Returns the cheaper instructions for the multiplication (if there are some)
+fn mul(reg: Register, value: i64, small: bool) -> Option<Vec<AsmInstructionEnum>> {
+    if value <= 1 || value > i32::MAX as i64 || !(reg.is_gpr64() || reg.is_gpr32()) {
+        return None;
+    }
+
+    let shift = value.trailing_zeros() as i64;
+    let odd = value >> shift;
+
+    let shl = if shift != 0 { vec![ShlVal(reg, shift)] } else { vec![] };
+
+    if odd == 1 {
+        return Some(shl);
+    }
+
+    // lea writes the whole register (a 32 bit imul clears the upper half)
+    if !reg.is_gpr64() {
+        return None;
+    }
+
+    // the odd factor as the product of one or two of 3, 5 and 9
+    let leas = match odd {
+        3 | 5 | 9 => vec![lea(reg, odd as u32 - 1)],
+        _ if small => return None,
+        15 => vec![lea(reg, 2), lea(reg, 4)],
+        25 => vec![lea(reg, 4), lea(reg, 4)],
+        27 => vec![lea(reg, 2), lea(reg, 8)],
+        45 => vec![lea(reg, 4), lea(reg, 8)],
+        81 => vec![lea(reg, 8), lea(reg, 8)],
+        _ => return None,
+    };
+
+    Some(leas.into_iter().chain(shl).collect())
+}
+
+/// Returns the magic number of the unsigned divisor (which isn't a power of two)
+///
+/// `(multiplier, shift, add)`: the quotient is `mulhi(n, multiplier) >> shift`,
+/// if `add` is set `(t + ((n - t) >> 1)) >> shift` with `t = mulhi(n, multiplier)`
+pub fn unsigned_magic(divisor: u64) -> (u64, u32, bool) {
+    // the smallest l with 2^l >= divisor
+    let l = 64 - (divisor - 1).leading_zeros();
+    let divisor = divisor as u128;
+
+    for shift in 0..l {
+        let pow = 1u128 << (64 + shift);
+        let multiplier = pow.div_ceil(divisor);
+
+        // the error of the multiplier is small enough for every 64 bit dividend
+        if multiplier < 1 << 64 && multiplier * divisor - pow <= 1 << shift {
+            return (multiplier as u64, shift, false);
+        }
+    }
+
+    // the multiplier needs 65 bits (the highest bit is added back with the dividend)
+    let multiplier = ((1u128 << 64) * ((1u128 << l) - divisor)) / divisor + 1;
+
+    (multiplier as u64, l - 1, true)
+}
+
+/// Returns the magic number of the signed divisor (its absolute value, which is at least 3)
+///
+/// `(multiplier, shift)`: the quotient is `(mulhs(n, multiplier) (+ n if the multiplier is negative)) >> shift`
+/// plus one for negative dividends
+pub fn signed_magic(divisor: u64) -> (i64, u32) {
+    let two63 = 1u64 << 63;
+
+    // the absolute value of the largest dividend which is congruent to -1 (mod divisor)
+    let anc = two63 - 1 - two63 % divisor;
+
+    let mut shift = 63;
+    let (mut q1, mut r1) = (two63 / anc, two63 % anc);
+    let (mut q2, mut r2) = (two63 / divisor, two63 % divisor);
+
+    loop {
+        shift += 1;
+
+        q1 = q1.wrapping_mul(2);
+        r1 = r1.wrapping_mul(2);
+        if r1 >= anc {
+            q1 = q1.wrapping_add(1);
+            r1 = r1.wrapping_sub(anc);
+        }
+
+        q2 = q2.wrapping_mul(2);
+        r2 = r2.wrapping_mul(2);
+        if r2 >= divisor {
+            q2 = q2.wrapping_add(1);
+            r2 = r2.wrapping_sub(divisor);
+        }
+
+        let delta = divisor - r2;
+
+        if !(q1 < delta || (q1 == delta && r1 == 0)) {
+            break;
+        }
+    }
+
+    (q2.wrapping_add(1) as i64, shift - 64)
+}
+
+/// Returns the instructions for the division (or remainder) by the constant
+fn div(reg: Register, value: i64, signed: bool, rem: bool) -> Option<Vec<AsmInstructionEnum>> {
+    // r11 holds the temporary values
+    if !(reg.is_gpr64() || reg.is_gpr32()) || reg.full_register() == Register::R11 {
+        return None;
+    }
+
+    let bits = reg.size() as i64 * 8;
+    let wide = reg.is_gpr64();
+    let (rdx, r11) = if wide { (Register::RDX, Register::R11) } else { (Register::EDX, Register::R11D) };
+
+    // the divisor as the dividend sees it
+    let divisor = match (signed, wide) {
+        (true, true) => value.unsigned_abs(),
+        (false, true) => value as u64,
+        (true, false) => i32::try_from(value).ok()?.unsigned_abs() as u64,
+        (false, false) => u32::try_from(value).ok()? as u64,
+    };
+
+    if divisor == 0 {
+        return None;
+    }
+
+    let mut code = vec![];
+
+    if divisor.is_power_of_two() {
+        let k = divisor.trailing_zeros() as i64;
+
+        match (signed, rem) {
+            _ if k == 0 && rem => code.push(MovVal(reg, 0)),
+            (true, false) if k == 0 && value < 0 => code.push(MulVal(reg, -1)),
+            // the division clears the upper half of 32 bit registers
+            _ if k == 0 => code.push(MovReg(reg, reg)),
+            (false, false) => code.push(ShrVal(reg, k)),
+            (false, true) => {
+                code.push(ShlVal(reg, bits - k));
+                code.push(ShrVal(reg, bits - k));
+            },
+            (true, _) => {
+                // negative dividends are rounded towards zero by adding divisor - 1
+                code.push(MovReg(r11, reg));
+                code.push(SarVal(r11, bits - 1));
+                code.push(ShrVal(r11, bits - k));
+                code.push(AddReg(r11, reg));
+                code.push(SarVal(r11, k));
+
+                if rem {
+                    code.push(ShlVal(r11, k));
+                    code.push(SubReg(reg, r11));
+                } else {
+                    if value < 0 {
+                        code.push(MulVal(r11, -1));
+                    }
+
+                    code.push(MovReg(reg, r11));
+                }
+            },
+        }
+
+        return Some(code);
+    }
+
+    // the dividend is extended to 64 bits into r11 and the quotient is calculated in rdx
+    code.push(match (signed, wide) {
+        (_, true) => MovReg(Register::R11, reg),
+        (true, false) => MovSx(Register::R11, reg),
+        (false, false) => MovReg(Register::R11D, reg),
+    });
+
+    if signed {
+        let (multiplier, shift) = signed_magic(divisor);
+
+        code.push(MovVal(Register::RAX, multiplier));
+        code.push(Imul(Register::R11));
+
+        if multiplier < 0 {
+            code.push(AddReg(Register::RDX, Register::R11));
+        }
+        if shift != 0 {
+            code.push(SarVal(Register::RDX, shift as i64));
+        }
+
+        // + 1 for negative dividends
+        code.push(MovReg(Register::RAX, Register::R11));
+        code.push(ShrVal(Register::RAX, 63));
+        code.push(AddReg(Register::RDX, Register::RAX));
+
+        if value < 0 && !rem {
+            code.push(MulVal(Register::RDX, -1));
+        }
+    } else {
+        let (multiplier, shift, add) = unsigned_magic(divisor);
+
+        code.push(MovVal(Register::RAX, multiplier as i64));
+        code.push(Mul(Register::R11));
+
+        if add {
+            code.push(MovReg(Register::RAX, Register::R11));
+            code.push(SubReg(Register::RAX, Register::RDX));
+            code.push(ShrVal(Register::RAX, 1));
+            code.push(AddReg(Register::RDX, Register::RAX));
+        }
+        if shift != 0 {
+            code.push(ShrVal(Register::RDX, shift as i64));
+        }
+    }
+
+    if rem { // dividend - quotient * divisor
+        if divisor <= i32::MAX as u64 {
+            code.push(MulVal(Register::RDX, divisor as i64));
+        } else {
+            code.push(MovVal(Register::RAX, divisor as i64));
+            code.push(MulReg(Register::RDX, Register::RAX));
+        }
+
+        code.push(SubReg(Register::R11, Register::RDX));
+        code.push(MovReg(reg, r11));
+    } else {
+        code.push(MovReg(reg, rdx));
+    }
+
+    Some(code)
+}
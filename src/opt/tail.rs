@@ -0,0 +1,113 @@
+//! Tail calls: a call whose result is returned directly becomes a jump
+//!
+//! The stack arguments of the callee get copied into the incoming argument area of the function,
+//! `lower_frame` tears down the frame before the jump
+
+use std::error::Error;
+
+use iced_x86::Register;
+
+use crate::{ir::AsmInstructionEnum::{self, *}, target::Abi};
+
+use super::{frame_escapes, PassContext};
+
+/// The pass which turns the calls in tail position into jumps
+pub fn tail_calls(code: Vec<AsmInstructionEnum>, ctx: &PassContext) -> Result<Vec<AsmInstructionEnum>, Box<dyn Error>> {
+    // the callee could use pointers into the frame which gets removed
+    if ctx.attrs.noreturn || frame_escapes(&code) {
+        return Ok(code);
+    }
+
+    let labels: Vec<&String> = code.iter().filter_map(|instr| match instr {
+        Label(label) => Some(label),
+        _ => None,
+    }).collect();
+
+    let mut opt = vec![];
+    let mut index = 0;
+
+    while index < code.len() {
+        if let Call(target) = &code[index] {
+            if !labels.contains(&target) {
+                if let Some((cleanup, ret)) = tail_position(&code, index + 1) {
+                    let args = pushed_args(&code[..index]).filter(|args| *args <= ctx.stack_args && stack_size(*args, ctx.abi) == cleanup);
+
+                    if let Some(args) = args {
+                        opt.extend(code[index + 1..ret].iter().filter(|instr| matches!(instr, Loc(_))).cloned());
+                        opt.extend(copy_stack_args(args, ctx.abi));
+
+                        if cleanup != 0 {
+                            opt.push(AddVal(Register::RSP, cleanup));
+                        }
+
+                        opt.push(Jmp(target.to_owned()));
+                        ctx.remark(format!("turned the call of {target} into a tail call"));
+
+                        index = ret + 1;
+                        continue;
+                    }
+                }
+            }
+        }
+
+        opt.push(code[index].to_owned());
+        index += 1;
+    }
+
+    Ok(opt)
+}
+
+/// Returns the size of the stack arguments the caller removes after the call and the index of the return
+/// if the call at `start - 1` is followed by a return
+fn tail_position(code: &[AsmInstructionEnum], start: usize) -> Option<(i64, usize)> {
+    let mut cleanup = 0;
+
+    for (index, instr) in code.iter().enumerate().skip(start) {
+        match instr {
+            Loc(_) => {},
+            AddVal(Register::RSP, value) if cleanup == 0 => cleanup = *value,
+            Ret => return Some((cleanup, index)),
+            _ => return None,
+        }
+    }
+
+    None
+}
+
+/// Returns the count of the stack arguments pushed in the block before the call
+/// (`None` if the stack pointer is changed otherwise)
+fn pushed_args(code: &[AsmInstructionEnum]) -> Option<usize> {
+    let mut pushed = 0i64;
+
+    for instr in code.iter().rev() {
+        match instr {
+            Push(_) | PushVal(_) => pushed += 1,
+            Pop(_) => pushed -= 1,
+            Label(_) | Call(_) | Jmp(_) | Ret => break,
+            SubVal(Register::RSP, _) | AddVal(Register::RSP, _) => {},
+            instr if super::written_reg(instr) == Some(Register::RSP) => return None,
+            _ => {},
+        }
+    }
+
+    usize::try_from(pushed).ok()
+}
+
+/// Returns the bytes `gen_call` reserves for `args` stack arguments (with the shadow space and padding)
+fn stack_size(args: usize, abi: &Abi) -> i64 {
+    let size = args as i64 * 8 + abi.shadow_space;
+
+    size + (abi.stack_align - size % abi.stack_align) % abi.stack_align
+}
+
+/// Returns the copies of the `args` outgoing stack arguments into the incoming argument area
+pub(crate) fn copy_stack_args(args: usize, abi: &Abi) -> Vec<AsmInstructionEnum> {
+    let mut copies = vec![];
+
+    for slot in 0..args {
+        copies.push(Load(Register::R11, abi.ptr(Register::RSP, abi.shadow_space + slot as i64 * 8)));
+        copies.push(Store(Register::R11, abi.stack(abi.stack_arg(slot))));
+    }
+
+    copies
+}
@@ -0,0 +1,576 @@
+//! Linear scan register allocation for the virtual registers
+//!
+//! Every virtual register gets a single location for its whole live range:
+//! a physical register from the abi or a stack slot below the variables.
+//! Spilled values are accessed through the scratch registers r10 and r11, which are never allocated
+
+use std::collections::{HashMap, HashSet};
+
+use iced_x86::Register;
+
+use crate::{
+    error::CodeGenLibError,
+    ir::{AsmInstructionEnum, VInstr, VReg},
+    opt::{frame_size, written_reg},
+    target::Abi,
+};
+
+/// The registers which are used to access spilled values
+const SCRATCH: [Register; 2] = [Register::R10, Register::R11];
+
+/// Returns the general purpose registers of the abi which can be allocated
+/// (the caller saved ones first, they don't need to be saved in the prologue)
+fn allocatable(abi: &Abi) -> Vec<Register> {
+    abi.caller_saved.iter().chain(abi.callee_saved.iter())
+        .filter(|reg| reg.is_gpr64() && !SCRATCH.contains(reg))
+        .copied()
+        .collect()
+}
+
+const REGS_64: [Register; 16] = [
+    Register::RAX, Register::RCX, Register::RDX, Register::RBX, Register::RSP, Register::RBP, Register::RSI, Register::RDI,
+    Register::R8, Register::R9, Register::R10, Register::R11, Register::R12, Register::R13, Register::R14, Register::R15,
+];
+
+const REGS_32: [Register; 16] = [
+    Register::EAX, Register::ECX, Register::EDX, Register::EBX, Register::ESP, Register::EBP, Register::ESI, Register::EDI,
+    Register::R8D, Register::R9D, Register::R10D, Register::R11D, Register::R12D, Register::R13D, Register::R14D, Register::R15D,
+];
+
+/// Returns the general purpose register with the size (4 or 8 bytes)
+fn sized(reg: Register, size: usize) -> Register {
+    match REGS_64.iter().position(|full| *full == reg.full_register()) {
+        Some(nr) if size == 4 => REGS_32[nr],
+        Some(nr) => REGS_64[nr],
+        None => reg,
+    }
+}
+
+/// The location of a virtual register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    /// The 64 bit register (the virtual register uses the part of its size)
+    Reg(Register),
+    /// The stack slot at the rbp displacement
+    Stack(i64),
+}
+
+/// Returns the ranges of the basic blocks (first and last instruction)
+fn blocks(code: &[VInstr]) -> Vec<(usize, usize)> {
+    use AsmInstructionEnum::*;
+
+    let mut blocks = vec![];
+    let mut start = 0;
+
+    for (index, instr) in code.iter().enumerate() {
+        let ends = matches!(instr,
+            VInstr::Ret(_) | VInstr::Asm(Ret | Jmp(_) | JmpReg(_) | Je(_) | Jne(_) | Jl(_) | Jg(_) | Jb(_) | Ja(_))
+        );
+
+        if let Some(VInstr::Asm(Label(_))) = code.get(index + 1) {
+            blocks.push((start, index));
+            start = index + 1;
+        } else if ends {
+            blocks.push((start, index));
+            start = index + 1;
+        }
+    }
+
+    if start < code.len() {
+        blocks.push((start, code.len() - 1));
+    }
+
+    blocks
+}
+
+/// Returns the successors of every basic block
+fn successors(code: &[VInstr], blocks: &[(usize, usize)]) -> Vec<Vec<usize>> {
+    use AsmInstructionEnum::*;
+
+    let mut labels = HashMap::new();
+
+    for (nr, block) in blocks.iter().enumerate() {
+        if let VInstr::Asm(Label(name)) = &code[block.0] {
+            labels.insert(name.to_owned(), nr);
+        }
+    }
+
+    blocks.iter().enumerate().map(|(nr, block)| {
+        let next = if nr + 1 < blocks.len() { vec![nr + 1] } else { vec![] };
+
+        match &code[block.1] {
+            VInstr::Ret(_) | VInstr::Asm(Ret) => vec![],
+            VInstr::Asm(Jmp(label)) => labels.get(label).map(|block| vec![*block]).unwrap_or_default(),
+            VInstr::Asm(Je(label) | Jne(label) | Jl(label) | Jg(label) | Jb(label) | Ja(label)) => {
+                let mut succs = next;
+                succs.extend(labels.get(label));
+                succs
+            },
+            VInstr::Asm(JmpReg(_)) => labels.values().copied().collect(), // every label could be a jump table entry
+            _ => next,
+        }
+    }).collect()
+}
+
+/// Returns the live range of every virtual register
+///
+/// Every instruction has three positions: it reads at `3 * index`,
+/// clobbers registers at `3 * index + 1` and writes at `3 * index + 2`
+fn live_ranges(code: &[VInstr]) -> HashMap<VReg, (usize, usize)> {
+    let blocks = blocks(code);
+    let succs = successors(code, &blocks);
+
+    // the registers which are read before they are written and the ones which are written
+    let mut uses = vec![HashSet::new(); blocks.len()];
+    let mut defs = vec![HashSet::new(); blocks.len()];
+
+    for (nr, block) in blocks.iter().enumerate() {
+        for instr in &code[block.0..=block.1] {
+            for reg in instr.uses() {
+                if !defs[nr].contains(&reg) {
+                    uses[nr].insert(reg);
+                }
+            }
+
+            if let Some(reg) = instr.def() {
+                defs[nr].insert(reg);
+            }
+        }
+    }
+
+    let mut live_in: Vec<HashSet<VReg>> = uses.clone();
+    let mut live_out: Vec<HashSet<VReg>> = vec![HashSet::new(); blocks.len()];
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for nr in (0..blocks.len()).rev() {
+            let out: HashSet<VReg> = succs[nr].iter().flat_map(|succ| live_in[*succ].iter().copied()).collect();
+
+            let mut ins = uses[nr].clone();
+            ins.extend(out.iter().filter(|reg| !defs[nr].contains(reg)));
+
+            if out != live_out[nr] || ins != live_in[nr] {
+                live_out[nr] = out;
+                live_in[nr] = ins;
+                changed = true;
+            }
+        }
+    }
+
+    let mut ranges: HashMap<VReg, (usize, usize)> = HashMap::new();
+    let mut extend = |reg: VReg, pos: usize| {
+        let range = ranges.entry(reg).or_insert((pos, pos));
+        range.0 = range.0.min(pos);
+        range.1 = range.1.max(pos);
+    };
+
+    for (index, instr) in code.iter().enumerate() {
+        for reg in instr.uses() {
+            extend(reg, 3 * index);
+        }
+
+        if let Some(reg) = instr.def() {
+            extend(reg, 3 * index + 2);
+        }
+    }
+
+    for (nr, block) in blocks.iter().enumerate() {
+        for reg in live_in[nr].iter() {
+            extend(*reg, 3 * block.0);
+        }
+
+        for reg in live_out[nr].iter() {
+            extend(*reg, 3 * block.1 + 2);
+        }
+    }
+
+    ranges
+}
+
+/// Returns the ranges in which the physical registers are used by the instructions themselves
+/// (divisions, shifts, calls, arguments and machine instructions)
+fn fixed_ranges(code: &[VInstr], abi: &Abi) -> Vec<(Register, usize, usize)> {
+    let caller_saved: Vec<Register> = allocatable(abi).into_iter()
+        .filter(|reg| !abi.callee_saved.contains(reg))
+        .collect();
+
+    let mut fixed = vec![];
+
+    for (index, instr) in code.iter().enumerate() {
+        let pos = 3 * index;
+
+        match instr {
+            VInstr::Div(..) | VInstr::Rem(..) => {
+                fixed.push((Register::RAX, pos, pos + 1));
+                fixed.push((Register::RDX, pos, pos + 1));
+            },
+            VInstr::Shl(..) | VInstr::Shr(..) | VInstr::Sar(..) => fixed.push((Register::RCX, pos, pos + 1)),
+            VInstr::Call(..) | VInstr::Asm(AsmInstructionEnum::Call(_)) => {
+                for reg in caller_saved.iter() {
+                    fixed.push((*reg, pos + 1, pos + 1));
+                }
+            },
+            VInstr::Arg(_, nr) => fixed.push((abi.arg64(*nr), 0, pos)), // the argument needs to stay until it is read
+            VInstr::Asm(instr) => {
+                if let Some(reg) = written_reg(instr) {
+                    fixed.push((reg.full_register(), pos, pos + 2));
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fixed
+}
+
+/// Checks the sizes of the virtual registers and the position of the arguments
+fn check(code: &[VInstr], abi: &Abi) -> Result<(), CodeGenLibError> {
+    let mut sizes: HashMap<u32, usize> = HashMap::new();
+    let mut prologue = true;
+
+    for instr in code {
+        for reg in instr.uses().into_iter().chain(instr.def()) {
+            if reg.size != 4 && reg.size != 8 {
+                return Err(CodeGenLibError::TypeMismatch(format!("virtual register {} with {} bytes", reg.id, reg.size)));
+            }
+
+            if *sizes.entry(reg.id).or_insert(reg.size) != reg.size {
+                return Err(CodeGenLibError::TypeMismatch(format!("virtual register {} is used with different sizes", reg.id)));
+            }
+        }
+
+        match instr {
+            VInstr::Arg(_, nr) if !prologue || *nr >= abi.reg_args() => {
+                return Err(CodeGenLibError::UnsuportedArg(format!("argument register {nr} (it needs to be read at the function start)")));
+            },
+            VInstr::Arg(..) | VInstr::Asm(AsmInstructionEnum::Loc(_)) => {},
+            VInstr::Call(name, args, _) if args.len() > abi.reg_args() => {
+                return Err(CodeGenLibError::UnsuportedArg(format!("{} arguments for {name} (only register arguments are supported)", args.len())));
+            },
+            VInstr::Lea(reg, _) if reg.size != 8 => {
+                return Err(CodeGenLibError::TypeMismatch(format!("lea into the virtual register {} with 4 bytes", reg.id)));
+            },
+            _ => prologue = false,
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the deepest rbp displacement the code accesses
+fn deepest_access(code: &[VInstr], abi: &Abi) -> i64 {
+    let asm: Vec<AsmInstructionEnum> = code.iter().filter_map(|instr| match instr {
+        VInstr::Asm(instr) => Some(instr.to_owned()),
+        _ => None,
+    }).collect();
+
+    code.iter().filter_map(|instr| match instr {
+        VInstr::Load(_, mem) | VInstr::Store(_, mem) | VInstr::Lea(_, mem) => Some(mem),
+        _ => None,
+    }).filter(|mem| mem.base == Register::RBP && mem.displacement < 0)
+        .map(|mem| -mem.displacement)
+        .fold(frame_size(&asm, None, abi), i64::max)
+}
+
+/// Assigns every virtual register a location with linear scan
+///
+/// The stack slots of the spilled registers are placed below `frame` (the size of the variables)
+pub fn assign(code: &[VInstr], abi: &Abi, frame: i64) -> Result<HashMap<VReg, Location>, CodeGenLibError> {
+    check(code, abi)?;
+
+    let fixed = fixed_ranges(code, abi);
+    let blocked = |reg: Register, from: usize, to: usize| {
+        fixed.iter().any(|range| range.0 == reg && range.1 <= to && from <= range.2)
+    };
+
+    let pool = allocatable(abi);
+
+    let mut ranges: Vec<(VReg, (usize, usize))> = live_ranges(code).into_iter().collect();
+    ranges.sort_by_key(|range| (range.1, range.0));
+
+    let mut locations = HashMap::new();
+    let mut active: Vec<(VReg, usize, Register)> = vec![]; // (register, end, location)
+    let mut spilled = vec![];
+
+    for (vreg, (start, end)) in ranges {
+        active.retain(|active| active.1 >= start);
+
+        let free = pool.iter().find(|reg| {
+            !active.iter().any(|active| active.2 == **reg) && !blocked(**reg, start, end)
+        });
+
+        if let Some(reg) = free {
+            active.push((vreg, end, *reg));
+            locations.insert(vreg, Location::Reg(*reg));
+            continue;
+        }
+
+        // spill the register which lives the longest
+        let candidate = active.iter().enumerate()
+            .filter(|(_, active)| active.1 > end && !blocked(active.2, start, end))
+            .max_by_key(|(_, active)| active.1)
+            .map(|(nr, _)| nr);
+
+        match candidate {
+            Some(nr) => {
+                let (other, _, reg) = active.remove(nr);
+
+                spilled.push(other);
+                active.push((vreg, end, reg));
+                locations.insert(vreg, Location::Reg(reg));
+            },
+            None => spilled.push(vreg),
+        }
+    }
+
+    let base = frame.max(deepest_access(code, abi));
+
+    for (nr, vreg) in spilled.into_iter().enumerate() {
+        locations.insert(vreg, Location::Stack(-(base + 8 * (nr as i64 + 1))));
+    }
+
+    Ok(locations)
+}
+
+/// Lowers the virtual instructions with the locations of the registers
+struct Lowering<'a> {
+    locations: &'a HashMap<VReg, Location>,
+    abi: &'a Abi,
+    code: Vec<AsmInstructionEnum>,
+}
+
+impl Lowering<'_> {
+    fn location(&self, vreg: VReg) -> Location {
+        self.locations[&vreg]
+    }
+
+    /// Returns the register of the value (a spilled value is loaded into the scratch register)
+    fn read(&mut self, vreg: VReg, scratch: Register) -> Register {
+        match self.location(vreg) {
+            Location::Reg(reg) => sized(reg, vreg.size),
+            Location::Stack(displ) => {
+                let reg = sized(scratch, vreg.size);
+                self.code.push(AsmInstructionEnum::Load(reg, self.abi.stack(displ)));
+                reg
+            },
+        }
+    }
+
+    /// Returns the register the result is calculated in (the scratch register for spilled values)
+    fn target(&self, vreg: VReg, scratch: Register) -> Register {
+        match self.location(vreg) {
+            Location::Reg(reg) => sized(reg, vreg.size),
+            Location::Stack(_) => sized(scratch, vreg.size),
+        }
+    }
+
+    /// Moves the result from the register into the location of the value
+    fn write(&mut self, vreg: VReg, reg: Register) {
+        match self.location(vreg) {
+            Location::Reg(target) if target == reg.full_register() => {},
+            Location::Reg(target) => self.code.push(AsmInstructionEnum::MovReg(sized(target, vreg.size), reg)),
+            Location::Stack(displ) => self.code.push(AsmInstructionEnum::Store(reg, self.abi.stack(displ))),
+        }
+    }
+
+    /// Moves the value into the register
+    fn read_into(&mut self, vreg: VReg, reg: Register) {
+        match self.location(vreg) {
+            Location::Reg(src) if src == reg.full_register() => {},
+            Location::Reg(src) => self.code.push(AsmInstructionEnum::MovReg(reg, sized(src, reg.size()))),
+            Location::Stack(displ) => self.code.push(AsmInstructionEnum::Load(reg, self.abi.stack(displ))),
+        }
+    }
+
+    /// Moves the arguments into the argument registers
+    fn args(&mut self, args: &[VReg]) {
+        let mut moves: Vec<(Register, Register)> = vec![];
+        let mut loads = vec![];
+
+        for (nr, arg) in args.iter().enumerate() {
+            let target = self.abi.arg64(nr);
+
+            match self.location(*arg) {
+                Location::Reg(reg) if reg == target => {},
+                Location::Reg(reg) => moves.push((target, reg)),
+                Location::Stack(_) => loads.push((sized(target, arg.size), *arg)),
+            }
+        }
+
+        // a move can be done once no other move reads its target
+        while !moves.is_empty() {
+            let free = moves.iter().position(|mv| !moves.iter().any(|other| other.1 == mv.0));
+
+            match free {
+                Some(nr) => {
+                    let (target, src) = moves.remove(nr);
+                    self.code.push(AsmInstructionEnum::MovReg(target, src));
+                },
+                None => { // a cycle: free the target of the first move
+                    let src = moves[0].1;
+                    self.code.push(AsmInstructionEnum::MovReg(SCRATCH[1], src));
+                    moves[0].1 = SCRATCH[1];
+                },
+            }
+        }
+
+        for (target, arg) in loads {
+            self.read_into(arg, target);
+        }
+    }
+
+    fn lower(&mut self, instr: &VInstr) {
+        use AsmInstructionEnum as Asm;
+
+        let [r10, r11] = SCRATCH;
+
+        match instr {
+            VInstr::MovVal(dst, value) => {
+                let reg = self.target(*dst, r10);
+                self.code.push(Asm::MovVal(reg, *value));
+                self.write(*dst, reg);
+            },
+            VInstr::Mov(dst, src) => match self.location(*dst) {
+                Location::Reg(reg) => self.read_into(*src, sized(reg, dst.size)),
+                Location::Stack(_) => {
+                    let reg = self.read(*src, r10);
+                    self.write(*dst, reg);
+                },
+            },
+            VInstr::MovPtr(dst, label) => {
+                let reg = self.target(*dst, r10);
+                self.code.push(Asm::MovPtr(reg, label.to_owned()));
+                self.write(*dst, reg);
+            },
+
+            VInstr::Load(dst, mem) => {
+                let reg = self.target(*dst, r10);
+                self.code.push(Asm::Load(reg, *mem));
+                self.write(*dst, reg);
+            },
+            VInstr::Store(src, mem) => {
+                let reg = self.read(*src, r10);
+                self.code.push(Asm::Store(reg, *mem));
+            },
+            VInstr::Lea(dst, mem) => {
+                let reg = self.target(*dst, r10);
+                self.code.push(Asm::Lea(reg, *mem));
+                self.write(*dst, reg);
+            },
+
+            VInstr::AddVal(dst, value) | VInstr::SubVal(dst, value) | VInstr::MulVal(dst, value) | VInstr::CmpVal(dst, value) => {
+                let reg = self.read(*dst, r10);
+
+                self.code.push(match instr {
+                    VInstr::AddVal(..) => Asm::AddVal(reg, *value),
+                    VInstr::SubVal(..) => Asm::SubVal(reg, *value),
+                    VInstr::MulVal(..) => Asm::MulVal(reg, *value),
+                    _ => Asm::CmpVal(reg, *value),
+                });
+
+                if !matches!(instr, VInstr::CmpVal(..)) {
+                    self.write(*dst, reg);
+                }
+            },
+            VInstr::Add(dst, src) | VInstr::Sub(dst, src) | VInstr::Mul(dst, src) | VInstr::Cmp(dst, src) => {
+                let reg = self.read(*dst, r10);
+                let src = self.read(*src, r11);
+
+                self.code.push(match instr {
+                    VInstr::Add(..) => Asm::AddReg(reg, src),
+                    VInstr::Sub(..) => Asm::SubReg(reg, src),
+                    VInstr::Mul(..) => Asm::MulReg(reg, src),
+                    _ => Asm::CmpReg(reg, src),
+                });
+
+                if !matches!(instr, VInstr::Cmp(..)) {
+                    self.write(*dst, reg);
+                }
+            },
+
+            VInstr::Div(dst, src) | VInstr::Rem(dst, src) => {
+                let rax = sized(Register::RAX, dst.size);
+                self.read_into(*dst, rax);
+
+                self.code.push(if dst.size == 8 { Asm::Cqo } else { Asm::Cdq });
+
+                let src = self.read(*src, r11);
+                self.code.push(Asm::Idiv(src));
+
+                let result = match instr {
+                    VInstr::Div(..) => rax,
+                    _ => sized(Register::RDX, dst.size),
+                };
+                self.write(*dst, result);
+            },
+
+            VInstr::Shl(dst, count) | VInstr::Shr(dst, count) | VInstr::Sar(dst, count) => {
+                self.read_into(*count, sized(Register::RCX, count.size));
+
+                let reg = self.read(*dst, r10);
+
+                self.code.push(match instr {
+                    VInstr::Shl(..) => Asm::ShlCl(reg),
+                    VInstr::Shr(..) => Asm::ShrCl(reg),
+                    _ => Asm::SarCl(reg),
+                });
+
+                self.write(*dst, reg);
+            },
+
+            VInstr::Arg(dst, nr) => {
+                let reg = sized(self.abi.arg64(*nr), dst.size);
+                self.write(*dst, reg);
+            },
+
+            VInstr::Call(func, args, ret) => {
+                self.args(args);
+
+                if self.abi.shadow_space != 0 {
+                    self.code.push(Asm::SubVal(Register::RSP, self.abi.shadow_space));
+                }
+
+                self.code.push(Asm::Call(func.to_owned()));
+
+                if self.abi.shadow_space != 0 {
+                    self.code.push(Asm::AddVal(Register::RSP, self.abi.shadow_space));
+                }
+
+                if let Some(ret) = ret {
+                    self.write(*ret, sized(self.abi.ret_reg(), ret.size));
+                }
+            },
+
+            VInstr::Ret(value) => {
+                if let Some(value) = value {
+                    self.read_into(*value, sized(self.abi.ret_reg(), value.size));
+                }
+
+                self.code.push(Asm::Ret);
+            },
+
+            VInstr::Asm(instr) => self.code.push(instr.to_owned()),
+        }
+    }
+}
+
+/// Allocates the registers of the virtual instructions and lowers them to the ir
+///
+/// The stack slots of the spilled registers are placed below `frame` (the size of the variables)
+pub fn allocate(code: &[VInstr], abi: &Abi, frame: i64) -> Result<Vec<AsmInstructionEnum>, CodeGenLibError> {
+    let locations = assign(code, abi, frame)?;
+
+    let mut lowering = Lowering {
+        locations: &locations,
+        abi,
+        code: vec![],
+    };
+
+    for instr in code {
+        lowering.lower(instr);
+    }
+
+    Ok(lowering.code)
+}
@@ -0,0 +1,150 @@
+//! The control flow graph of the ssa functions
+
+use super::{BlockId, Function, Terminator};
+
+impl Function {
+    /// Returns the blocks the block branches to
+    pub fn successors(&self, block: BlockId) -> Vec<BlockId> {
+        match self.blocks[block.0].term {
+            Terminator::Br(target) => vec![target],
+            Terminator::CondBr(_, _, _, then, other) if then == other => vec![then],
+            Terminator::CondBr(_, _, _, then, other) => vec![then, other],
+            Terminator::Ret(_) | Terminator::None => vec![],
+        }
+    }
+
+    /// Returns the predecessors of every block (the index is the block)
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut preds = vec![vec![]; self.blocks.len()];
+
+        for block in 0..self.blocks.len() {
+            for succ in self.successors(BlockId(block)) {
+                preds[succ.0].push(BlockId(block));
+            }
+        }
+
+        preds
+    }
+
+    /// Returns the blocks which are reachable from the entry in reverse postorder
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut order = vec![];
+
+        // (block, index of the next successor)
+        let mut stack = vec![(self.entry(), 0)];
+        visited[self.entry().0] = true;
+
+        while let Some((block, next)) = stack.pop() {
+            let succs = self.successors(block);
+
+            match succs.get(next) {
+                Some(succ) => {
+                    stack.push((block, next + 1));
+
+                    if !visited[succ.0] {
+                        visited[succ.0] = true;
+                        stack.push((*succ, 0));
+                    }
+                },
+                None => order.push(block),
+            }
+        }
+
+        order.reverse();
+        order
+    }
+}
+
+/// The dominator tree of a function
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DomTree {
+    /// The immediate dominator of every block (`None` for the entry and unreachable blocks)
+    pub idoms: Vec<Option<BlockId>>,
+}
+
+impl DomTree {
+    /// Builds the dominator tree with the algorithm of Cooper, Harvey and Kennedy
+    pub fn new(func: &Function) -> Self {
+        let order = func.reverse_postorder();
+        let preds = func.predecessors();
+
+        let mut index = vec![usize::MAX; func.blocks.len()];
+        for (nr, block) in order.iter().enumerate() {
+            index[block.0] = nr;
+        }
+
+        let mut idoms: Vec<Option<BlockId>> = vec![None; func.blocks.len()];
+        idoms[func.entry().0] = Some(func.entry());
+
+        let intersect = |idoms: &Vec<Option<BlockId>>, mut a: BlockId, mut b: BlockId| {
+            while a != b {
+                while index[a.0] > index[b.0] {
+                    a = idoms[a.0].unwrap();
+                }
+                while index[b.0] > index[a.0] {
+                    b = idoms[b.0].unwrap();
+                }
+            }
+
+            a
+        };
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for block in order.iter().skip(1) {
+                let mut idom = None;
+
+                for pred in preds[block.0].iter() {
+                    if idoms[pred.0].is_none() {
+                        continue;
+                    }
+
+                    idom = Some(match idom {
+                        Some(idom) => intersect(&idoms, *pred, idom),
+                        None => *pred,
+                    });
+                }
+
+                if idom != idoms[block.0] {
+                    idoms[block.0] = idom;
+                    changed = true;
+                }
+            }
+        }
+
+        idoms[func.entry().0] = None;
+
+        Self { idoms }
+    }
+
+    /// Returns the immediate dominator of the block
+    pub fn idom(&self, block: BlockId) -> Option<BlockId> {
+        self.idoms[block.0]
+    }
+
+    /// Returns if every path from the entry to `block` goes through `dominator`
+    pub fn dominates(&self, dominator: BlockId, block: BlockId) -> bool {
+        let mut block = Some(block);
+
+        while let Some(current) = block {
+            if current == dominator {
+                return true;
+            }
+
+            block = self.idom(current);
+        }
+
+        false
+    }
+
+    /// Returns the blocks which are immediately dominated by the block
+    pub fn children(&self, block: BlockId) -> Vec<BlockId> {
+        self.idoms.iter().enumerate()
+            .filter(|(_, idom)| **idom == Some(block))
+            .map(|(nr, _)| BlockId(nr))
+            .collect()
+    }
+}
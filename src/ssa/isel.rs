@@ -0,0 +1,252 @@
+//! Instruction selection: lowers the ssa ir to virtual registers
+//!
+//! The phi nodes are replaced by copies at the end of the predecessors
+//! (critical edges get their own copy block)
+
+use crate::{
+    error::CodeGenLibError,
+    ir::{AsmInstructionEnum, Type, VInstr, VReg},
+    regalloc::allocate,
+    target::Abi,
+};
+
+use super::{BinOp, BlockId, CmpOp, Function, Inst, Terminator, Value};
+
+/// Returns the conditional jump of the comparison and if the targets need to be swapped
+pub(crate) fn jump(op: CmpOp) -> (fn(String) -> AsmInstructionEnum, bool) {
+    use AsmInstructionEnum::*;
+
+    match op {
+        CmpOp::Eq => (Je, false),
+        CmpOp::Ne => (Jne, false),
+        CmpOp::Lt => (Jl, false),
+        CmpOp::Gt => (Jg, false),
+        CmpOp::Le => (Jg, true),
+        CmpOp::Ge => (Jl, true),
+        CmpOp::ULt => (Jb, false),
+        CmpOp::UGt => (Ja, false),
+        CmpOp::ULe => (Ja, true),
+        CmpOp::UGe => (Jb, true),
+    }
+}
+
+fn label(block: BlockId) -> String {
+    format!(".bb{}", block.0)
+}
+
+struct Selector<'a> {
+    func: &'a Function,
+    preds: Vec<Vec<BlockId>>,
+
+    /// The id of the next virtual register which isn't a value
+    temp: u32,
+
+    code: Vec<VInstr>,
+}
+
+impl Selector<'_> {
+    fn reg(&self, value: Value) -> VReg {
+        VReg::new(value.0, self.func.typ(value).size() as usize)
+    }
+
+    fn check_types(&self) -> Result<(), CodeGenLibError> {
+        for (nr, typ) in self.func.types.iter().enumerate() {
+            if !typ.in_reg() || ![4, 8].contains(&typ.size()) {
+                return Err(CodeGenLibError::TypeMismatch(format!("value {nr} with the type {typ:?} doesn't fit into a register")));
+            }
+        }
+
+        for block in self.func.blocks.iter() {
+            for (value, inst) in block.insts.iter() {
+                let same = |other: &Value| self.func.typ(*other).size() == self.func.typ(*value).size();
+
+                let ok = match inst {
+                    Inst::Binary(_, a, b) => same(a) && same(b),
+                    Inst::Phi(incoming) => incoming.iter().all(|(_, other)| same(other)),
+                    _ => true,
+                };
+
+                if !ok {
+                    return Err(CodeGenLibError::TypeMismatch(format!("the operands of value {} have different sizes", value.0)));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the copies for the phi nodes of `succ` when it is entered from `pred`
+    fn copies(&mut self, pred: BlockId, succ: BlockId) -> Result<Vec<VInstr>, CodeGenLibError> {
+        let mut moves = vec![];
+
+        for (value, inst) in self.func.blocks[succ.0].insts.iter() {
+            if let Inst::Phi(incoming) = inst {
+                let src = match incoming.iter().find(|(block, _)| *block == pred) {
+                    Some((_, src)) => *src,
+                    None => return Err(CodeGenLibError::InvalidSsa(format!("the phi of value {} has no value for block {}", value.0, pred.0))),
+                };
+
+                moves.push((self.reg(*value), self.reg(src)));
+            }
+        }
+
+        if moves.len() <= 1 {
+            return Ok(moves.into_iter().map(|(dst, src)| VInstr::Mov(dst, src)).collect());
+        }
+
+        // the phis read their values at the same time, so they are copied through temporaries
+        let mut copies = vec![];
+        let mut temps = vec![];
+
+        for (_, src) in moves.iter() {
+            let temp = VReg::new(self.temp, src.size);
+            self.temp += 1;
+
+            copies.push(VInstr::Mov(temp, *src));
+            temps.push(temp);
+        }
+
+        for ((dst, _), temp) in moves.iter().zip(temps) {
+            copies.push(VInstr::Mov(*dst, temp));
+        }
+
+        Ok(copies)
+    }
+
+    fn inst(&mut self, value: Value, inst: &Inst) -> Result<(), CodeGenLibError> {
+        let reg = self.reg(value);
+
+        match inst {
+            Inst::Const(typ) => {
+                let constant = match typ {
+                    Type::u64(value) => *value as i64,
+                    Type::u32(value) => *value as i64,
+                    Type::i64(value) => *value,
+                    Type::i32(value) => *value as i64,
+                    typ => return Err(CodeGenLibError::TypeMismatch(format!("{typ:?} isn't an integer constant"))),
+                };
+
+                self.code.push(VInstr::MovVal(reg, constant));
+            },
+            Inst::Binary(op, a, b) => {
+                let b = self.reg(*b);
+
+                self.code.push(VInstr::Mov(reg, self.reg(*a)));
+                self.code.push(match op {
+                    BinOp::Add => VInstr::Add(reg, b),
+                    BinOp::Sub => VInstr::Sub(reg, b),
+                    BinOp::Mul => VInstr::Mul(reg, b),
+                    BinOp::Div => VInstr::Div(reg, b),
+                    BinOp::Rem => VInstr::Rem(reg, b),
+                    BinOp::Shl => VInstr::Shl(reg, b),
+                    BinOp::Shr => VInstr::Shr(reg, b),
+                    BinOp::Sar => VInstr::Sar(reg, b),
+                });
+            },
+            Inst::Call(func, args) => {
+                let args = args.iter().map(|arg| self.reg(*arg)).collect();
+                self.code.push(VInstr::Call(func.to_owned(), args, Some(reg)));
+            },
+            Inst::Arg(_) | Inst::Phi(_) => {}, // the arguments are read at the start and phis are copies
+        }
+
+        Ok(())
+    }
+
+    fn terminator(&mut self, block: BlockId, next: Option<BlockId>) -> Result<(), CodeGenLibError> {
+        match self.func.blocks[block.0].term.to_owned() {
+            Terminator::Ret(value) => self.code.push(VInstr::Ret(value.map(|value| self.reg(value)))),
+            Terminator::Br(target) => {
+                let copies = self.copies(block, target)?;
+                self.code.extend(copies);
+
+                if next != Some(target) {
+                    self.code.push(VInstr::Asm(AsmInstructionEnum::Jmp(label(target))));
+                }
+            },
+            Terminator::CondBr(op, a, b, then, other) => {
+                let (jcc, swap) = jump(op);
+                let (taken, fallthrough) = if swap { (other, then) } else { (then, other) };
+
+                let taken_copies = self.copies(block, taken)?;
+                let fallthrough_copies = self.copies(block, fallthrough)?;
+
+                let edge = format!(".bb{}_{}", block.0, taken.0);
+
+                self.code.push(VInstr::Cmp(self.reg(a), self.reg(b)));
+                self.code.push(VInstr::Asm(jcc(if taken_copies.is_empty() { label(taken) } else { edge.to_owned() })));
+
+                self.code.extend(fallthrough_copies);
+
+                if next != Some(fallthrough) || !taken_copies.is_empty() {
+                    self.code.push(VInstr::Asm(AsmInstructionEnum::Jmp(label(fallthrough))));
+                }
+
+                if !taken_copies.is_empty() {
+                    self.code.push(VInstr::Asm(AsmInstructionEnum::Label(edge)));
+                    self.code.extend(taken_copies);
+                    self.code.push(VInstr::Asm(AsmInstructionEnum::Jmp(label(taken))));
+                }
+            },
+            Terminator::None => return Err(CodeGenLibError::InvalidSsa(format!("block {} has no terminator", block.0))),
+        }
+
+        Ok(())
+    }
+}
+
+/// Lowers the function to virtual registers
+pub fn lower(func: &Function) -> Result<Vec<VInstr>, CodeGenLibError> {
+    let mut selector = Selector {
+        func,
+        preds: func.predecessors(),
+        temp: func.types.len() as u32,
+        code: vec![],
+    };
+
+    selector.check_types()?;
+
+    // the arguments need to be read before anything else
+    for (nr, block) in func.blocks.iter().enumerate() {
+        for (value, inst) in block.insts.iter() {
+            if let Inst::Arg(arg) = inst {
+                if nr != func.entry().0 {
+                    return Err(CodeGenLibError::InvalidSsa(format!("argument {arg} is read outside of the entry block")));
+                }
+
+                selector.code.push(VInstr::Arg(selector.reg(*value), *arg));
+            }
+        }
+    }
+
+    // the returning blocks are placed last, so the function returns at its end
+    let mut layout = func.reverse_postorder();
+    layout.sort_by_key(|block| matches!(func.blocks[block.0].term, Terminator::Ret(_)));
+
+    for (nr, block) in layout.iter().enumerate() {
+        for (value, inst) in func.blocks[block.0].insts.iter() {
+            if let Inst::Phi(incoming) = inst {
+                if incoming.len() != selector.preds[block.0].len() {
+                    return Err(CodeGenLibError::InvalidSsa(format!("the phi of value {} doesn't have a value for every predecessor", value.0)));
+                }
+            }
+        }
+
+        selector.code.push(VInstr::Asm(AsmInstructionEnum::Label(label(*block))));
+
+        for (value, inst) in func.blocks[block.0].insts.iter() {
+            selector.inst(*value, inst)?;
+        }
+
+        selector.terminator(*block, layout.get(nr + 1).copied())?;
+    }
+
+    Ok(selector.code)
+}
+
+impl Function {
+    /// Lowers the function and allocates its registers
+    pub fn compile(&self, abi: &Abi) -> Result<Vec<AsmInstructionEnum>, CodeGenLibError> {
+        allocate(&lower(self)?, abi, 0)
+    }
+}
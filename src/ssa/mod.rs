@@ -0,0 +1,132 @@
+//! A target independent ssa ir
+//!
+//! A function is made of basic blocks, which contain instructions and end with a terminator.
+//! Every instruction defines exactly one value, values which come from different blocks are merged with phi nodes.
+//! The ir gets lowered to virtual registers by the instruction selection (see `isel`)
+
+use crate::ir::Type;
+
+pub mod cfg;
+pub mod isel;
+
+pub use cfg::DomTree;
+
+/// A value which is defined by an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Value(pub u32);
+
+/// The index of a basic block in the function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlockId(pub usize);
+
+/// A binary operation (`Div`/`Rem` are signed, `Shr` is logical and `Sar` arithmetic)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Shl,
+    Shr,
+    Sar,
+}
+
+/// A comparison (the ones starting with `U` are unsigned)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    ULt,
+    ULe,
+    UGt,
+    UGe,
+}
+
+/// An instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inst {
+    /// An integer constant
+    Const(Type),
+    /// The `nr`th argument of the function
+    Arg(usize),
+    Binary(BinOp, Value, Value),
+    /// Calls the function (the value is its return value)
+    Call(String, Vec<Value>),
+    /// Merges the values of the predecessors
+    Phi(Vec<(BlockId, Value)>),
+}
+
+/// The instruction which ends a basic block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Terminator {
+    Ret(Option<Value>),
+    Br(BlockId),
+    /// Branches to the first block if the comparison is true, else to the second one
+    CondBr(CmpOp, Value, Value, BlockId, BlockId),
+    /// The block isn't finished yet
+    None,
+}
+
+/// A basic block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub insts: Vec<(Value, Inst)>,
+    pub term: Terminator,
+}
+
+/// A function of the ssa ir
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub name: String,
+    pub blocks: Vec<Block>,
+
+    /// The type of every value (the index is the value)
+    pub types: Vec<Type>,
+}
+
+impl Function {
+    /// Creates a new function with an empty entry block
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            blocks: vec![Block { insts: vec![], term: Terminator::None }],
+            types: vec![],
+        }
+    }
+
+    /// Returns the entry block
+    pub fn entry(&self) -> BlockId {
+        BlockId(0)
+    }
+
+    /// Adds a new empty block
+    pub fn add_block(&mut self) -> BlockId {
+        self.blocks.push(Block { insts: vec![], term: Terminator::None });
+        BlockId(self.blocks.len() - 1)
+    }
+
+    /// Adds the instruction with the type of its value to the end of the block
+    pub fn push(&mut self, block: BlockId, inst: Inst, typ: Type) -> Value {
+        let value = Value(self.types.len() as u32);
+
+        self.types.push(typ);
+        self.blocks[block.0].insts.push((value, inst));
+
+        value
+    }
+
+    /// Sets the terminator of the block
+    pub fn terminate(&mut self, block: BlockId, term: Terminator) {
+        self.blocks[block.0].term = term;
+    }
+
+    /// Returns the type of the value
+    pub fn typ(&self, value: Value) -> &Type {
+        &self.types[value.0 as usize]
+    }
+}
@@ -0,0 +1,208 @@
+#![allow(unused_imports)]
+
+use iced_x86::{MemoryOperand, Register};
+
+use crate::ir::Type;
+
+use super::windows::WindowsAbi;
+use super::linux::LinuxAbi;
+
+/// Struct which saves the target ABI
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Abi {
+    pub reg_args: usize,
+    
+    pub regs_64: Vec<Register>,
+    pub regs_32: Vec<Register>,
+
+    pub return_reg: Register,
+
+    /// The register for the upper part of 9 - 16 byte return values
+    pub return_reg2: Option<Register>,
+
+    pub stack_base: i64,
+
+    /// The alignment of rsp at a call instruction
+    pub stack_align: i64,
+
+    /// If variadic functions expect the count of used vector registers in al
+    pub varargs_al: bool,
+
+    /// The stack space the caller needs to reserve for the register arguments
+    pub shadow_space: i64,
+
+    /// If every argument uses the register of its position (so ints and floats share the registers)
+    pub positional_args: bool,
+
+    /// The size of a stack page
+    pub page_size: i64,
+
+    /// The function which needs to be called with the frame size in rax before
+    /// allocating frames which are larger than a page
+    pub stack_probe: Option<String>,
+
+    /// The registers a function needs to restore before it returns (without rbp and rsp)
+    pub callee_saved: Vec<Register>,
+
+    /// The general purpose registers a called function can change
+    pub caller_saved: Vec<Register>,
+
+    /// The bytes below rsp which leaf functions can use without allocating them
+    pub red_zone: i64,
+
+    /// If rbp gets set after the stack allocation (`lea rbp, [rsp + n]`),
+    /// so the unwinder can find the registers which are saved at the bottom of the frame
+    pub frame_after_alloc: bool,
+}
+
+impl Abi {
+    /// Returns the host ABI
+    /// 
+    /// ! Only works for **Linux and Windows**
+    pub fn host() -> Self {
+        #[cfg(target_os = "windows")]
+        return Abi::windows();
+
+        #[cfg(target_os = "linux")]
+        return Abi::linux();
+    }
+
+    /// Returns how many arguments are stored in registers
+    pub fn reg_args(&self) -> usize {
+        self.reg_args
+    }
+
+    /// Returns a 64bit register in which the argument is stored
+    /// else the Register is Register::None
+    pub fn arg64(&self, nr: usize) -> Register {
+        if nr < self.reg_args {
+            let opt = self.regs_64.get(nr);
+
+            if opt.is_some() {
+                *opt.unwrap()
+            } else {
+                Register::None
+            }
+        } else {
+            Register::None
+        }
+    }
+
+    /// Returns a 32bit register in which the argument is stored
+    /// else the register is `Register::None`
+    pub fn arg32(&self, nr: usize) -> Register {
+        if nr < self.reg_args {
+            let opt = self.regs_32.get(nr);
+
+            if opt.is_some() {
+                *opt.unwrap()
+            } else {
+                Register::None
+            }
+        } else {
+            Register::None
+        }
+    }
+
+    /// Returns for every argument the number of the register in which it is passed
+    /// (`None` if it is passed on the stack)
+    pub fn arg_regs(&self, args: &[Type]) -> Vec<Option<usize>> {
+        if self.positional_args { // types which don't fit get passed by reference
+            return (0..args.len()).map(|nr| {
+                if nr < self.reg_args { Some(nr) } else { None }
+            }).collect();
+        }
+
+        let mut used_regs = 0;
+
+        args.iter().map(|arg| {
+            if arg.in_reg() && used_regs < self.reg_args {
+                used_regs += 1;
+                Some(used_regs - 1)
+            } else {
+                None
+            }
+        }).collect()
+    }
+
+    /// Returns if the argument is passed as a pointer to a copy of it
+    /// (with positional arguments only aggregates with 1, 2, 4 or 8 bytes are passed by value)
+    pub fn by_ref(&self, typ: &Type) -> bool {
+        self.positional_args && matches!(typ, Type::Bytes(_)) && ![1, 2, 4, 8].contains(&typ.size())
+    }
+
+    /// Returns the stack position (for `Abi::stack`) of the incoming argument
+    /// which is the `nr`th argument passed on the stack
+    pub fn stack_arg(&self, nr: usize) -> i64 {
+        // rbp + 8 is the return address, the shadow space follows it
+        8 + self.shadow_space + nr as i64 * 8
+    }
+
+    /// Returns the `MemoryOperand` for the stack position (rbp + pos)
+    pub fn stack(&self, pos: i64) -> MemoryOperand {
+
+        let displ = {
+            if pos.is_positive() {
+                pos + self.stack_base
+            } else {
+                pos
+            }
+        };
+
+        MemoryOperand::new(
+            Register::RBP,
+            Register::None,
+            1,
+            displ,
+            1,
+            false,
+            Register::None,
+        )
+    }
+
+    /// Returns the `MemoryOperand` for the memory position
+    pub fn mem(&self, adr: i64) -> MemoryOperand {
+        MemoryOperand::new(
+            Register::None,
+            Register::None,
+            1,
+            adr,
+            1,
+            false,
+            Register::None,
+        )
+    }
+
+    /// Returns the `Register` in which the return value is stored
+    pub fn ret_reg(&self) -> Register {
+        self.return_reg
+    }
+
+    /// Returns in how many registers a value with `size` bytes is returned
+    /// (0 if it is returned in memory via the hidden struct return pointer)
+    ///
+    /// With a second return register every value up to 16 bytes is returned in registers,
+    /// else only values with 1, 2, 4 or 8 bytes are
+    pub fn ret_regs(&self, size: u64) -> usize {
+        if size <= 8 && (self.return_reg2.is_some() || size.is_power_of_two()) {
+            1
+        } else if size <= 16 && self.return_reg2.is_some() {
+            2
+        } else {
+            0
+        }
+    }
+
+    /// Returns the `MemoryOperand` for the memory at `reg + displ`
+    pub fn ptr(&self, reg: Register, displ: i64) -> MemoryOperand {
+        MemoryOperand::new(
+            reg,
+            Register::None,
+            1,
+            displ,
+            1,
+            false,
+            Register::None,
+        )
+    }
+}
\ No newline at end of file
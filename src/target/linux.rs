@@ -0,0 +1,38 @@
+use iced_x86::Register::*;
+
+use super::Abi;
+
+pub trait LinuxAbi {
+    /// Returns new ABI struct with Linux ABI values
+    fn linux() -> Self;
+}
+
+impl LinuxAbi for Abi {
+    fn linux() -> Self {
+        Abi {
+            reg_args: 6, 
+            regs_64: vec![RDI , RSI , RDX , RCX , R8 , R9], 
+            regs_32: vec![EDI, ESI, EDX, ECX, R8D, R9D], 
+
+            return_reg: RAX,
+            return_reg2: Some(RDX),
+
+            stack_base: 8,
+
+            stack_align: 16,
+            varargs_al: true,
+
+            shadow_space: 0,
+            positional_args: false,
+
+            page_size: 4096,
+            stack_probe: Option::None,
+
+            callee_saved: vec![RBX, R12, R13, R14, R15],
+            caller_saved: vec![RAX, RCX, RDX, RSI, RDI, R8, R9, R10, R11],
+
+            red_zone: 128,
+            frame_after_alloc: false,
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,41 @@
+use iced_x86::Register::*;
+
+use super::Abi;
+
+pub trait WindowsAbi {
+    /// Returns new ABI struct with Windows ABI values
+    fn windows() -> Self;
+}
+
+impl WindowsAbi for Abi {
+    fn windows() -> Self {
+        Abi {
+            reg_args: 4, 
+            regs_64: vec![RCX, RDX, R8, R9], 
+            regs_32: vec![ECX, EDX, R8D, R9D], 
+
+            return_reg: RAX,
+            return_reg2: Option::None, // `None` would be `Register::None`
+
+            stack_base: 8,
+
+            stack_align: 16,
+            varargs_al: false,
+
+            shadow_space: 32,
+            positional_args: true,
+
+            page_size: 4096,
+            stack_probe: Some("__chkstk".into()),
+
+            callee_saved: vec![
+                RBX, RSI, RDI, R12, R13, R14, R15,
+                XMM6, XMM7, XMM8, XMM9, XMM10, XMM11, XMM12, XMM13, XMM14, XMM15,
+            ],
+            caller_saved: vec![RAX, RCX, RDX, R8, R9, R10, R11],
+
+            red_zone: 0,
+            frame_after_alloc: true,
+        }
+    }
+}
\ No newline at end of file
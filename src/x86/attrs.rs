@@ -0,0 +1,76 @@
+//! Attributes which change how a function is generated and placed
+
+/// The attributes of a function
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuncAttrs {
+    /// The code is emitted verbatim (without prologue and epilogue)
+    pub naked: bool,
+
+    /// The function never returns (it doesn't get an epilogue)
+    pub noreturn: bool,
+
+    /// The alignment of the function start in bytes (default: 16)
+    pub align: u64,
+
+    /// The section the function is placed in (default: `.text`)
+    pub section: Option<String>,
+
+    /// The function is rarely executed (it is placed after the other functions)
+    pub cold: bool,
+
+    /// The function is often executed (it is placed before the other functions)
+    pub hot: bool,
+
+    /// Calls of the function get inlined regardless of its size (see `inline_module`)
+    pub always_inline: bool,
+
+    /// Calls of the function never get inlined
+    pub noinline: bool,
+
+    /// The function sets up rbp even if it is a leaf function (see `lower_frame`)
+    pub keep_frame_pointer: bool,
+
+    /// The returns jump to a single epilogue instead of restoring the frame themselves (smaller code)
+    pub shared_epilogue: bool,
+}
+
+impl Default for FuncAttrs {
+    fn default() -> Self {
+        Self {
+            naked: false,
+            noreturn: false,
+            align: 16,
+            section: None,
+            cold: false,
+            hot: false,
+            always_inline: false,
+            noinline: false,
+            keep_frame_pointer: false,
+            shared_epilogue: false,
+        }
+    }
+}
+
+impl FuncAttrs {
+    /// Returns the attributes for a function which is emitted verbatim
+    pub fn naked() -> Self {
+        Self {
+            naked: true,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the name of the section the function is placed in
+    /// (cold/hot functions get their own section on elf)
+    pub fn section_name(&self, elf: bool) -> Option<String> {
+        if self.section.is_some() {
+            self.section.to_owned()
+        } else if elf && self.cold {
+            Some(".text.unlikely".into())
+        } else if elf && self.hot {
+            Some(".text.hot".into())
+        } else {
+            None
+        }
+    }
+}
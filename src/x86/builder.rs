@@ -1,16 +1,61 @@
-use crate::{ir::AsmInstructionEnum, ir::resolve::resolve, Optimize};
-use formatic::{Arch, BinFormat, Decl, Endian, Link, ObjectBuilder, Scope};
+use crate::{error::CodeGenLibError, ir::{loc::with_locs, verify::verify_module, AsmInstructionEnum, SourceLoc, Type, VInstr}, ir::resolve::resolve, opt::{dce::used_symbols, frame_size, inline::inline_module, lower_frame, lower_frame_remarks, remark::{instr_count, FuncStats, Remark}, OptLevel, PassContext, PassManager}, regalloc::allocate, ssa::{isel::lower, Function}, target::Abi};
+use super::{attrs::FuncAttrs, dwarf::{write_debug_info, DebugFunc}, unwind::{unwind_ops, write_eh_frame, write_pdata_xdata, UnwindFunc}, writer::ObjectWriter};
+use formatic::{BinFormat, Link};
+use object::RelocationKind;
 use std::collections::HashMap;
 
 /// The builder is a wrapper around the entire code generation
 ///
-/// It also create the object file via the `formatic` crate
+/// It also create the object file (see `ObjectWriter`)
 #[derive(Debug, Clone)]
 pub struct Builder {
     pub funcs: HashMap<String, (bool, Vec<AsmInstructionEnum>)>,
     pub labels: HashMap<String, (bool, Vec<u8>)>,
     pub func_names: Vec<String>,
     pub label_names: Vec<String>,
+
+    /// Symbols which are defined outside of the object file
+    pub externs: Vec<String>,
+
+    /// The variable layout of the functions: `(name, rbp displacement, type)`
+    pub vars: HashMap<String, Vec<(String, i64, Type)>>,
+
+    /// If the ir gets verified before it is written (default: true)
+    pub verify: bool,
+
+    /// If the verifier rejects calls and jumps to functions which aren't defined or declared with `define_extern`
+    /// (default: true, when it is false they are treated as externals)
+    pub strict_externs: bool,
+
+    /// The abi the functions are generated for (default: host)
+    pub abi: Abi,
+
+    /// The jump tables (data labels) and the function and code labels of their entries
+    pub jump_tables: HashMap<String, (String, Vec<String>)>,
+
+    /// The attributes of the functions (functions without an entry use the default attributes)
+    pub func_attrs: HashMap<String, FuncAttrs>,
+
+    /// The source files (the index is the file id of `SourceLoc`)
+    pub files: Vec<String>,
+
+    /// If DWARF debug info is written into elf objects (default: false)
+    pub debug: bool,
+
+    /// The optimization passes which run over every function (default: `OptLevel::O1`)
+    pub passes: PassManager,
+
+    /// If unwind tables (`.eh_frame` on elf, `.pdata`/`.xdata` on coff) are written (default: true)
+    pub unwind: bool,
+
+    /// Why the optimizations changed the functions (filled by `write`)
+    pub remarks: Vec<Remark>,
+
+    /// If `write` compares the size of every function with its unoptimized version (default: false)
+    pub collect_stats: bool,
+
+    /// The size of every written function without and with the optimizations (filled by `write` if `collect_stats` is set)
+    pub stats: Vec<FuncStats>,
 }
 
 impl Builder {
@@ -21,6 +66,20 @@ impl Builder {
             labels: HashMap::new(),
             func_names: vec![],
             label_names: vec![],
+            externs: vec![],
+            vars: HashMap::new(),
+            verify: true,
+            strict_externs: true,
+            abi: Abi::host(),
+            jump_tables: HashMap::new(),
+            func_attrs: HashMap::new(),
+            files: vec![],
+            debug: false,
+            unwind: true,
+            passes: PassManager::default(),
+            remarks: vec![],
+            collect_stats: false,
+            stats: vec![],
         }
     }
 
@@ -30,13 +89,90 @@ impl Builder {
         public: bool,
         code: Vec<AsmInstructionEnum>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let opt_code = Optimize(code)?;
-        self.funcs.insert(name.into(), (public, opt_code));
+        self.funcs.insert(name.into(), (public, code));
         self.func_names.push(name.into());
 
         Ok(())
     }
 
+    /// Defines the function with the source location of every instruction
+    /// (the locations are added as `Loc` markers, see `with_locs`)
+    pub fn define_located(
+        &mut self,
+        name: &str,
+        public: bool,
+        code: Vec<(SourceLoc, AsmInstructionEnum)>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.define(name, public, with_locs(code))
+    }
+
+    /// Defines the function with virtual registers, which get allocated for the abi of the builder
+    ///
+    /// The spilled registers are placed below the variables (so `define_vars` needs to be called before)
+    pub fn define_virtual(
+        &mut self,
+        name: &str,
+        public: bool,
+        code: Vec<VInstr>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let frame = frame_size(&[], self.vars.get(name), &self.abi);
+        let code = allocate(&code, &self.abi, frame)?;
+
+        self.define(name, public, code)
+    }
+
+    /// Defines the function of the ssa ir (it gets lowered and its registers get allocated)
+    pub fn define_ssa(&mut self, func: &Function, public: bool) -> Result<(), Box<dyn std::error::Error>> {
+        self.define_virtual(&func.name, public, lower(func)?)
+    }
+
+    /// Defines the function with the attributes (e.g. a naked function is emitted verbatim)
+    pub fn define_with_attrs(
+        &mut self,
+        name: &str,
+        public: bool,
+        code: Vec<AsmInstructionEnum>,
+        attrs: FuncAttrs,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.func_attrs.insert(name.into(), attrs);
+
+        self.define(name, public, code)
+    }
+
+    /// Adds the source file and returns its file id (for `SourceLoc`)
+    pub fn define_file(&mut self, name: &str) -> u32 {
+        match self.files.iter().position(|file| file == name) {
+            Some(id) => id as u32,
+            None => {
+                self.files.push(name.into());
+                self.files.len() as u32 - 1
+            },
+        }
+    }
+
+    /// Sets the optimization passes to the preset of the level
+    pub fn set_opt_level(&mut self, level: OptLevel) {
+        self.passes = PassManager::new(level);
+    }
+
+    /// Returns the attributes of the function
+    pub fn attrs(&self, name: &str) -> FuncAttrs {
+        self.func_attrs.get(name).cloned().unwrap_or_default()
+    }
+
+    /// Declares a symbol which is defined outside of the object file
+    pub fn define_extern(&mut self, name: &str) {
+        if !self.externs.contains(&name.to_string()) {
+            self.externs.push(name.into());
+        }
+    }
+
+    /// Sets the variable layout of the function `name`, which is used
+    /// by the verifier to find accesses to undeclared variables
+    pub fn define_vars(&mut self, name: &str, vars: Vec<(String, i64, Type)>) {
+        self.vars.insert(name.into(), vars);
+    }
+
     pub fn define_label(
         &mut self,
         name: &str,
@@ -47,38 +183,171 @@ impl Builder {
         self.label_names.push(name.into());
     }
 
+    /// Defines the jump table `name` for the function `func`
+    ///
+    /// Every entry is the signed 32 bit offset of the label (in `targets`) from the start of the table,
+    /// the entries are relocated when the object is written
+    pub fn define_jump_table(&mut self, name: &str, func: &str, targets: Vec<String>) {
+        self.define_label(name, false, vec![0; targets.len() * 4]);
+        self.jump_tables.insert(name.into(), (func.into(), targets));
+    }
+
+    /// Fills in the entries of every jump table of the function `func`
+    /// with the label offsets returned by `resolve` (relative to the start of the function)
+    ///
+    /// The object writer turns them into offsets from the table (see `define_jump_table`)
+    pub fn resolve_jump_tables(&mut self, func: &str, offsets: &HashMap<String, usize>) -> Result<(), CodeGenLibError> {
+        for (name, table) in self.jump_tables.iter() {
+            if table.0 != func {
+                continue;
+            }
+
+            let mut data = vec![];
+
+            for target in table.1.iter() {
+                let offset = match offsets.get(target) {
+                    Some(offset) => *offset as u32,
+                    None => return Err(CodeGenLibError::LabelNotExist(target.to_owned())),
+                };
+
+                data.extend_from_slice(&offset.to_le_bytes());
+            }
+
+            if let Some(label) = self.labels.get_mut(name) {
+                label.1 = data;
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn write(&mut self, outpath: &str, bin: BinFormat) -> Result<(), Box<dyn std::error::Error>> {
-        let mut obj = ObjectBuilder::new(outpath);
+        let mut obj = ObjectWriter::new(bin)?;
+
+        if self.verify {
+            let violations = verify_module(self);
+
+            if !violations.is_empty() {
+                return Err(Box::from(CodeGenLibError::InvalidIr(violations)));
+            }
+        }
+
+        // hot functions are placed first, cold ones last
+        let mut names: Vec<String> = vec![];
+        for name in self.func_names.iter() {
+            if !names.contains(name) && self.funcs.contains_key(name) {
+                names.push(name.to_owned());
+            }
+        }
+
+        let (funcs, remarks) = inline_module(self, self.passes.inline_threshold);
+
+        self.remarks = remarks;
+        self.stats.clear();
+
+        let used = self.passes.remove_unused.then(|| used_symbols(self, &funcs));
+        let used = |name: &String| used.as_ref().is_none_or(|used| used.contains(name));
+
+        for name in names.iter().filter(|name| !used(name)) {
+            self.remarks.push(Remark::new("remove-unused", name, "removed the unused function".into()));
+        }
 
-        let mut resolved_funcs: HashMap<String, Vec<u8>> = HashMap::new();
+        names.retain(|name| used(name));
+
+        names.sort_by_key(|name| {
+            let attrs = self.attrs(name);
+            (!attrs.hot, attrs.cold)
+        });
+
+        let mut resolved_funcs: Vec<(String, Vec<u8>)> = vec![];
+        let mut label_offsets: Vec<(String, HashMap<String, usize>)> = vec![];
+        let mut debug_funcs: Vec<DebugFunc> = vec![];
+        let mut unwind_funcs: Vec<UnwindFunc> = vec![];
 
         // Resolve machine code
-        for func in self.funcs.iter() {
-            let ir = &func.1 .1;
+        for name in names.iter() {
+            let public = self.funcs[name].0;
+            let mut attrs = self.attrs(name);
+
+            // the debug info addresses the variables relative to rbp
+            attrs.keep_frame_pointer |= self.passes.keep_frame_pointers || self.debug;
+            attrs.shared_epilogue |= self.passes.shared_epilogues;
+
+            // the stack arguments are declared at their displacement in the incoming argument area
+            let first_arg = self.abi.stack(self.abi.stack_arg(0)).displacement;
+            let stack_args = self.vars.get(name).into_iter().flatten()
+                .filter(|var| var.1 >= first_arg)
+                .map(|var| ((var.1 - first_arg) / 8 + 1) as usize)
+                .max()
+                .unwrap_or(0);
+
+            let ctx = PassContext { name, abi: &self.abi, attrs: &attrs, stack_args, remarks: Default::default() };
+            let ir = self.passes.run(funcs[name].to_owned(), &ctx)?;
+
+            self.remarks.extend(ctx.remarks.into_inner());
+
+            let frame = frame_size(&ir, self.vars.get(name), &self.abi);
+            let (ir, remarks) = lower_frame_remarks(ir, &self.abi, frame, &attrs, name)?;
+
+            self.remarks.extend(remarks);
 
             let resolved = resolve(self.func_names.clone(), self.label_names.clone(), &ir)?;
 
-            resolved_funcs.insert(func.0.to_owned(), resolved.0);
+            if self.collect_stats {
+                // the function as it was defined (without inlining, passes and leaf frames)
+                let unoptimized = {
+                    let code = self.funcs[name].1.to_owned();
+                    let attrs = FuncAttrs { keep_frame_pointer: true, shared_epilogue: false, ..attrs.to_owned() };
+
+                    let frame = frame_size(&code, self.vars.get(name), &self.abi);
+                    lower_frame(code, &self.abi, frame, &attrs)?
+                };
+
+                self.stats.push(FuncStats {
+                    name: name.to_owned(),
+                    instrs_before: instr_count(&unoptimized),
+                    instrs_after: instr_count(&ir),
+                    bytes_before: resolve(self.func_names.clone(), self.label_names.clone(), &unoptimized)?.code.len(),
+                    bytes_after: resolved.code.len(),
+                });
+            }
+
+            unwind_funcs.push(UnwindFunc {
+                name: name.to_owned(),
+                size: resolved.code.len() as u64,
+                ops: if attrs.naked { vec![] } else { unwind_ops(&ir, &resolved.offsets, resolved.code.len()) },
+            });
+
+            debug_funcs.push(DebugFunc {
+                name: name.to_owned(),
+                public,
+                size: resolved.code.len() as u64,
+                vars: self.vars.get(name).cloned().unwrap_or_default(),
+                locs: resolved.locs,
+            });
+
+            resolved_funcs.push((name.to_owned(), resolved.code));
+            label_offsets.push((name.to_owned(), resolved.labels));
 
             // add decls
-            let decls = resolved.2;
+            let decls = resolved.decls;
             for decl in decls {
                 obj.add_decl(&decl.0, decl.1);
             }
 
             // add links
-            let links = resolved.1;
+            let links = resolved.links;
             for link in links {
                 if self.label_names.contains(&link.to) {
                     obj.link(Link {
-                        from: func.0.to_string(),
+                        from: name.to_string(),
                         to: format!(".L{}", link.to),
                         at: link.at,
                     });
 
                 } else {
                     obj.link(Link {
-                        from: func.0.to_string(),
+                        from: name.to_string(),
                         to: link.to,
                         at: link.at,
                     });
@@ -86,37 +355,55 @@ impl Builder {
             }
         }
 
+        for (func, offsets) in label_offsets {
+            self.resolve_jump_tables(&func, &offsets)?;
+        }
+
         // Defining functions
         for func in resolved_funcs {
-            let decl = Decl::Function({
-                let public = self.funcs.get(&func.0).unwrap().0;
-                match public {
-                    true => Scope::Export,
-                    false => Scope::Private,
-                }
-            });
-
-            obj.add_decl(&func.0, decl);
+            let public = self.funcs.get(&func.0).unwrap().0;
 
-            obj.define(&func.0, func.1);
+            obj.define_func(&func.0, &func.1, public, &self.attrs(&func.0));
         }
 
         // Defining labels
-        for label in self.labels.iter() {
+        let mut defined: Vec<&String> = vec![];
+        for label in self.label_names.iter() {
+            if defined.contains(&label) || !used(label) {
+                continue;
+            }
+            defined.push(label);
 
-            let name = format!(".L{}", label.0);
+            let data = &self.labels[label];
 
-            obj.add_decl(&name, Decl::RData({
-                match label.1.0 {
-                    true => Scope::Export,
-                    false => Scope::Private,
-                }
-            }));
+            obj.define_data(&format!(".L{}", label), &data.1, data.0);
+        }
+
+        // the entries point into the section of the function, a public function could be replaced in a shared object
+        for (name, table) in self.jump_tables.iter() {
+            let (Some(entries), Some(func)) = (obj.symbol_offset(&format!(".L{name}")), obj.symbol_offset(&table.0)) else {
+                continue;
+            };
 
-            obj.define(&name, label.1.1.to_owned());
+            for (index, entry) in self.labels[name].1.chunks(4).enumerate() {
+                let at = index as u64 * 4;
+                let offset = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]) as i64;
+
+                obj.relocate_to_section(entries.0, entries.1 + at, func.0, func.1 as i64 + offset + at as i64, RelocationKind::Relative, 32)?;
+            }
         }
 
-        obj.write(bin, Arch::X86_64, Endian::Litte)
+        if self.debug && obj.elf() {
+            write_debug_info(&mut obj, &self.files, &debug_funcs)?;
+        }
+
+        if self.unwind && obj.elf() {
+            write_eh_frame(&mut obj, &unwind_funcs)?;
+        } else if self.unwind && obj.coff() {
+            write_pdata_xdata(&mut obj, &unwind_funcs)?;
+        }
+
+        obj.write(outpath)
     }
 
     /// Syncronices the symbols/links/etc. from the other builder into the current if they doesn't exits
@@ -138,5 +425,31 @@ impl Builder {
                 self.func_names.push(func.to_owned());
             }
         }
+
+        for name in other.externs.iter() {
+            self.define_extern(name);
+        }
+
+        for file in other.files.iter() {
+            self.define_file(file);
+        }
+
+        for (name, attrs) in other.func_attrs.iter() {
+            if !self.func_attrs.contains_key(name) {
+                self.func_attrs.insert(name.to_owned(), attrs.to_owned());
+            }
+        }
+
+        for (name, table) in other.jump_tables.iter() {
+            if !self.jump_tables.contains_key(name) {
+                self.jump_tables.insert(name.to_owned(), table.to_owned());
+            }
+        }
+
+        for (func, vars) in other.vars.iter() {
+            if !self.vars.contains_key(func) {
+                self.vars.insert(func.to_owned(), vars.to_owned());
+            }
+        }
     }
 }
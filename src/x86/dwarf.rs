@@ -0,0 +1,281 @@
+//! Generates the DWARF debug info (`.debug_info`, `.debug_abbrev`, `.debug_line`) via the `gimli` crate
+
+use std::{collections::HashMap, error::Error};
+
+use gimli::{
+    constants,
+    write::{
+        Address, AttributeValue, DwarfUnit, EndianVec, Expression, LineProgram, LineString, Range, RangeList,
+        RelocateWriter, Relocation, RelocationTarget, Sections, UnitEntryId,
+    },
+    Encoding, Format, LineEncoding, LittleEndian, X86_64,
+};
+use object::{RelocationKind, SectionKind};
+
+use crate::ir::{SourceLoc, Type};
+use super::writer::ObjectWriter;
+
+/// The debug information of a single function
+#[derive(Debug, Clone)]
+pub struct DebugFunc {
+    pub name: String,
+    pub public: bool,
+
+    /// The size of the machine code
+    pub size: u64,
+
+    /// The variables as `(name, rbp displacement, type)`
+    pub vars: Vec<(String, i64, Type)>,
+
+    /// The byte offsets at which the code of a source location starts
+    pub locs: Vec<(usize, SourceLoc)>,
+}
+
+/// A section which records the relocations gimli writes
+#[derive(Debug, Clone)]
+pub struct DebugSection {
+    pub data: EndianVec<LittleEndian>,
+    pub relocs: Vec<Relocation>,
+}
+
+impl Default for DebugSection {
+    fn default() -> Self {
+        Self {
+            data: EndianVec::new(LittleEndian),
+            relocs: vec![],
+        }
+    }
+}
+
+impl RelocateWriter for DebugSection {
+    type Writer = EndianVec<LittleEndian>;
+
+    fn writer(&self) -> &Self::Writer {
+        &self.data
+    }
+
+    fn writer_mut(&mut self) -> &mut Self::Writer {
+        &mut self.data
+    }
+
+    fn relocate(&mut self, relocation: Relocation) {
+        self.relocs.push(relocation);
+    }
+}
+
+/// Returns the debug info entry of the type (types are only added once)
+fn type_entry(dwarf: &mut DwarfUnit, types: &mut HashMap<String, UnitEntryId>, typ: &Type) -> UnitEntryId {
+    let (key, name, encoding) = match typ {
+        Type::u64(_) => ("u64".to_string(), "u64", constants::DW_ATE_unsigned),
+        Type::u32(_) => ("u32".to_string(), "u32", constants::DW_ATE_unsigned),
+        Type::i64(_) => ("i64".to_string(), "i64", constants::DW_ATE_signed),
+        Type::i32(_) => ("i32".to_string(), "i32", constants::DW_ATE_signed),
+        Type::Bytes(bytes) => (format!("[u8; {}]", bytes.len()), "", constants::DW_ATE_unsigned),
+        _ => ("*u8".to_string(), "", constants::DW_ATE_unsigned), // strings and pointers
+    };
+
+    if let Some(entry) = types.get(&key) {
+        return *entry;
+    }
+
+    let root = dwarf.unit.root();
+
+    let id = match typ {
+        Type::u64(_) | Type::u32(_) | Type::i64(_) | Type::i32(_) => {
+            let id = dwarf.unit.add(root, constants::DW_TAG_base_type);
+            let entry = dwarf.unit.get_mut(id);
+
+            entry.set(constants::DW_AT_name, AttributeValue::String(name.into()));
+            entry.set(constants::DW_AT_encoding, AttributeValue::Encoding(encoding));
+            entry.set(constants::DW_AT_byte_size, AttributeValue::Udata(typ.size()));
+
+            id
+        },
+        Type::Bytes(bytes) => {
+            let element = u8_entry(dwarf, types);
+
+            let id = dwarf.unit.add(root, constants::DW_TAG_array_type);
+            dwarf.unit.get_mut(id).set(constants::DW_AT_type, AttributeValue::UnitRef(element));
+
+            let range = dwarf.unit.add(id, constants::DW_TAG_subrange_type);
+            dwarf.unit.get_mut(range).set(constants::DW_AT_count, AttributeValue::Udata(bytes.len() as u64));
+
+            id
+        },
+        _ => {
+            let element = u8_entry(dwarf, types);
+
+            let id = dwarf.unit.add(root, constants::DW_TAG_pointer_type);
+            let entry = dwarf.unit.get_mut(id);
+
+            entry.set(constants::DW_AT_type, AttributeValue::UnitRef(element));
+            entry.set(constants::DW_AT_byte_size, AttributeValue::Udata(8));
+
+            id
+        },
+    };
+
+    types.insert(key, id);
+
+    id
+}
+
+/// Returns the debug info entry of the byte type (used by strings, pointers and bytes)
+fn u8_entry(dwarf: &mut DwarfUnit, types: &mut HashMap<String, UnitEntryId>) -> UnitEntryId {
+    if let Some(entry) = types.get("u8") {
+        return *entry;
+    }
+
+    let root = dwarf.unit.root();
+    let id = dwarf.unit.add(root, constants::DW_TAG_base_type);
+    let entry = dwarf.unit.get_mut(id);
+
+    entry.set(constants::DW_AT_name, AttributeValue::String("u8".into()));
+    entry.set(constants::DW_AT_encoding, AttributeValue::Encoding(constants::DW_ATE_unsigned_char));
+    entry.set(constants::DW_AT_byte_size, AttributeValue::Udata(1));
+
+    types.insert("u8".into(), id);
+
+    id
+}
+
+/// Adds the debug sections for the functions into the object
+///
+/// `files` are the names of the source files (the index is the file id of the source locations)
+pub fn write_debug_info(obj: &mut ObjectWriter, files: &[String], funcs: &[DebugFunc]) -> Result<(), Box<dyn Error>> {
+    let encoding = Encoding {
+        format: Format::Dwarf32,
+        version: 4,
+        address_size: 8,
+    };
+
+    let comp_dir = std::env::current_dir()?.to_string_lossy().to_string();
+    let comp_file = files.first().cloned().unwrap_or("<generated>".into());
+
+    let mut dwarf = DwarfUnit::new(encoding);
+
+    dwarf.unit.line_program = LineProgram::new(
+        encoding,
+        LineEncoding::default(),
+        LineString::String(comp_dir.as_bytes().to_vec()),
+        LineString::String(comp_file.as_bytes().to_vec()),
+        None,
+    );
+
+    let dir = dwarf.unit.line_program.default_directory();
+    let file_ids: Vec<_> = files.iter().map(|file| {
+        dwarf.unit.line_program.add_file(LineString::String(file.as_bytes().to_vec()), dir, None)
+    }).collect();
+
+    // The compile unit
+    let ranges = funcs.iter().enumerate().map(|(index, func)| Range::StartLength {
+        begin: Address::Symbol { symbol: index, addend: 0 },
+        length: func.size,
+    }).collect();
+    let ranges = dwarf.unit.ranges.add(RangeList(ranges));
+
+    let root = dwarf.unit.root();
+    let entry = dwarf.unit.get_mut(root);
+
+    entry.set(constants::DW_AT_producer, AttributeValue::String(format!("CodeGenLib {}", env!("CARGO_PKG_VERSION")).into_bytes()));
+    entry.set(constants::DW_AT_language, AttributeValue::Language(constants::DW_LANG_C99));
+    entry.set(constants::DW_AT_name, AttributeValue::String(comp_file.into_bytes()));
+    entry.set(constants::DW_AT_comp_dir, AttributeValue::String(comp_dir.into_bytes()));
+    entry.set(constants::DW_AT_low_pc, AttributeValue::Address(Address::Constant(0)));
+    entry.set(constants::DW_AT_ranges, AttributeValue::RangeListRef(ranges));
+
+    let mut types = HashMap::new();
+
+    for (index, func) in funcs.iter().enumerate() {
+        let address = Address::Symbol { symbol: index, addend: 0 };
+
+        // The line table
+        dwarf.unit.line_program.begin_sequence(Some(address));
+
+        for (offset, loc) in func.locs.iter() {
+            let file = match file_ids.get(loc.file as usize) {
+                Some(file) => *file,
+                None => continue,
+            };
+
+            let row = dwarf.unit.line_program.row();
+            row.address_offset = *offset as u64;
+            row.file = file;
+            row.line = loc.line as u64;
+            row.column = loc.column as u64;
+
+            dwarf.unit.line_program.generate_row();
+        }
+
+        dwarf.unit.line_program.end_sequence(func.size);
+
+        // The function
+        let id = dwarf.unit.add(root, constants::DW_TAG_subprogram);
+        let entry = dwarf.unit.get_mut(id);
+
+        let mut frame_base = Expression::new();
+        frame_base.op_reg(X86_64::RBP);
+
+        entry.set(constants::DW_AT_name, AttributeValue::String(func.name.as_bytes().to_vec()));
+        entry.set(constants::DW_AT_external, AttributeValue::Flag(func.public));
+        entry.set(constants::DW_AT_low_pc, AttributeValue::Address(address));
+        entry.set(constants::DW_AT_high_pc, AttributeValue::Udata(func.size));
+        entry.set(constants::DW_AT_frame_base, AttributeValue::Exprloc(frame_base));
+
+        if let Some((_, loc)) = func.locs.first() {
+            if let Some(file) = file_ids.get(loc.file as usize) {
+                entry.set(constants::DW_AT_decl_file, AttributeValue::FileIndex(Some(*file)));
+                entry.set(constants::DW_AT_decl_line, AttributeValue::Udata(loc.line as u64));
+            }
+        }
+
+        // The variables (hidden ones start with a dot)
+        for var in func.vars.iter().filter(|var| !var.0.starts_with('.')) {
+            let typ = type_entry(&mut dwarf, &mut types, &var.2);
+
+            let mut location = Expression::new();
+            location.op_fbreg(var.1);
+
+            let var_id = dwarf.unit.add(id, constants::DW_TAG_variable);
+            let entry = dwarf.unit.get_mut(var_id);
+
+            entry.set(constants::DW_AT_name, AttributeValue::String(var.0.as_bytes().to_vec()));
+            entry.set(constants::DW_AT_type, AttributeValue::UnitRef(typ));
+            entry.set(constants::DW_AT_location, AttributeValue::Exprloc(location));
+        }
+    }
+
+    let mut sections = Sections::new(DebugSection::default());
+
+    dwarf.write(&mut sections)?;
+
+    // Add the sections and their relocations into the object
+    let mut ids = HashMap::new();
+
+    sections.for_each(|id, section| -> Result<(), Box<dyn Error>> {
+        if !section.data.slice().is_empty() {
+            ids.insert(id, obj.add_section(id.name(), SectionKind::Debug, section.data.slice().to_vec(), 1));
+        }
+
+        Ok(())
+    })?;
+
+    sections.for_each(|id, section| -> Result<(), Box<dyn Error>> {
+        for reloc in section.relocs.iter() {
+            let at = ids[&id];
+
+            match reloc.target {
+                RelocationTarget::Symbol(index) => {
+                    obj.relocate(at, reloc.offset as u64, &funcs[index].name, reloc.addend, RelocationKind::Absolute, reloc.size * 8)?;
+                },
+                RelocationTarget::Section(target) => {
+                    obj.relocate_to_section(at, reloc.offset as u64, ids[&target], reloc.addend, RelocationKind::Absolute, reloc.size * 8)?;
+                },
+            }
+        }
+
+        Ok(())
+    })?;
+
+    Ok(())
+}
@@ -1,3 +1,7 @@
 //! The sublibary for x86-64Bit CodeGeneration
 
+pub mod attrs;
 pub mod builder;
+pub mod dwarf;
+pub mod unwind;
+pub mod writer;
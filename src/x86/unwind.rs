@@ -0,0 +1,333 @@
+//! Generates the unwind tables (`.eh_frame` on elf, `.pdata`/`.xdata` on coff)
+//! for the prologue and epilogues which `lower_frame` emits
+
+use std::error::Error;
+
+use gimli::{
+    constants,
+    write::{Address, CallFrameInstruction, CommonInformationEntry, EhFrame, FrameDescriptionEntry, FrameTable, RelocationTarget},
+    Encoding, Format, X86_64,
+};
+use iced_x86::Register;
+use object::{RelocationKind, SectionKind};
+
+use crate::ir::AsmInstructionEnum;
+use super::{dwarf::DebugSection, writer::ObjectWriter};
+
+/// A change of the stack frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnwindOp {
+    /// `push rbp`
+    PushFrame,
+    /// `mov rbp, rsp` or `lea rbp, [rsp + offset]` (the offset is in the tuple)
+    SetFrame(i64),
+    /// `sub rsp, size`
+    Alloc(i64),
+    /// A callee saved register got stored at the rbp displacement
+    Save(Register, i64),
+    /// `pop rbp` of an epilogue (the frame is gone)
+    Epilogue,
+    /// `ret` (or tail jump) of an epilogue which isn't at the end of the function
+    EpilogueEnd,
+}
+
+/// The unwind information of a single function
+#[derive(Debug, Clone)]
+pub struct UnwindFunc {
+    pub name: String,
+
+    /// The size of the machine code
+    pub size: u64,
+
+    /// The byte offsets after the instructions which change the frame
+    pub ops: Vec<(usize, UnwindOp)>,
+}
+
+/// Returns the frame changes of the (optimized) code
+///
+/// `offsets` are the byte offsets of the instructions (see `resolve`) and `size` is the size of the machine code.
+/// Code which doesn't start with `push rbp` is a leaf function without a frame pointer (see `lower_frame`),
+/// naked functions have no frame changes
+pub fn unwind_ops(code: &[AsmInstructionEnum], offsets: &[usize], size: usize) -> Vec<(usize, UnwindOp)> {
+    use AsmInstructionEnum::*;
+
+    let end = |index: usize| offsets.get(index + 1).copied().unwrap_or(size);
+
+    let mut ops = vec![];
+
+    let framed = code.first() == Some(&Push(Register::RBP));
+    let mut alloc = 0;
+
+    // The prologue
+    let mut index = 0;
+
+    if framed {
+        ops.push((end(0), UnwindOp::PushFrame));
+        index = 1;
+    }
+
+    while index < code.len() {
+        match (&code[index], code.get(index + 1), code.get(index + 2)) {
+            (MovReg(Register::RBP, Register::RSP), _, _) if framed => ops.push((end(index), UnwindOp::SetFrame(0))),
+            (Lea(Register::RBP, mem), _, _) if framed && mem.base == Register::RSP => {
+                ops.push((end(index), UnwindOp::SetFrame(mem.displacement)));
+            },
+            (SubVal(Register::RSP, frame), _, _) => {
+                alloc = *frame;
+                ops.push((end(index), UnwindOp::Alloc(*frame)));
+            },
+            (MovVal(Register::RAX, frame), Some(Call(_)), Some(SubReg(Register::RSP, Register::RAX))) => { // stack probe
+                index += 2;
+                ops.push((end(index), UnwindOp::Alloc(*frame)));
+            },
+            (Store(reg, mem), _, _) if mem.base == Register::RBP => ops.push((end(index), UnwindOp::Save(*reg, mem.displacement))),
+            // the displacement from where rbp would be (8 bytes below the return address)
+            (Store(reg, mem), _, _) if mem.base == Register::RSP && !framed => {
+                ops.push((end(index), UnwindOp::Save(*reg, mem.displacement - alloc + 8)));
+            },
+            _ => break,
+        }
+
+        index += 1;
+    }
+
+    // The epilogues
+    let last = if framed { Pop(Register::RBP) } else { AddVal(Register::RSP, alloc) };
+
+    for index in index..code.len() {
+        if (framed || alloc != 0) && code[index] == last && matches!(code.get(index + 1), Some(Ret | Jmp(_))) {
+            ops.push((end(index), UnwindOp::Epilogue));
+
+            if end(index + 1) < size {
+                ops.push((end(index + 1), UnwindOp::EpilogueEnd));
+            }
+        }
+    }
+
+    ops
+}
+
+/// Returns the dwarf register number of the general purpose register
+fn dwarf_reg(reg: Register) -> Option<gimli::Register> {
+    Some(match reg {
+        Register::RAX => X86_64::RAX,
+        Register::RBX => X86_64::RBX,
+        Register::RCX => X86_64::RCX,
+        Register::RDX => X86_64::RDX,
+        Register::RSI => X86_64::RSI,
+        Register::RDI => X86_64::RDI,
+        Register::R12 => X86_64::R12,
+        Register::R13 => X86_64::R13,
+        Register::R14 => X86_64::R14,
+        Register::R15 => X86_64::R15,
+        _ => return None,
+    })
+}
+
+/// Adds the `.eh_frame` section with the call frame information of the functions into the object
+pub fn write_eh_frame(obj: &mut ObjectWriter, funcs: &[UnwindFunc]) -> Result<(), Box<dyn Error>> {
+    let encoding = Encoding {
+        format: Format::Dwarf32,
+        version: 1,
+        address_size: 8,
+    };
+
+    let mut table = FrameTable::default();
+
+    // At the function start the return address is at [rsp]
+    let mut cie = CommonInformationEntry::new(encoding, 1, -8, X86_64::RA);
+    cie.fde_address_encoding = constants::DW_EH_PE_pcrel | constants::DW_EH_PE_sdata4;
+    cie.add_instruction(CallFrameInstruction::Cfa(X86_64::RSP, 8));
+    cie.add_instruction(CallFrameInstruction::Offset(X86_64::RA, -8));
+
+    let cie = table.add_cie(cie);
+
+    for (index, func) in funcs.iter().enumerate() {
+        if func.ops.is_empty() {
+            continue;
+        }
+
+        let mut fde = FrameDescriptionEntry::new(Address::Symbol { symbol: index, addend: 0 }, func.size as u32);
+
+        // the distance of the cfa from rsp (until rbp is set) and from rbp (or where it would be)
+        let mut cfa = 8;
+        let mut rbp_cfa = 16;
+        let mut rbp_set = false;
+
+        for (offset, op) in func.ops.iter() {
+            let offset = *offset as u32;
+
+            match op {
+                UnwindOp::PushFrame => {
+                    cfa = 16;
+                    fde.add_instruction(offset, CallFrameInstruction::CfaOffset(16));
+                    fde.add_instruction(offset, CallFrameInstruction::Offset(X86_64::RBP, -16));
+                },
+                UnwindOp::SetFrame(displ) => {
+                    rbp_cfa = cfa - *displ as i32;
+                    rbp_set = true;
+                    fde.add_instruction(offset, CallFrameInstruction::Cfa(X86_64::RBP, rbp_cfa));
+                },
+                UnwindOp::Alloc(_) if rbp_set => {}, // the cfa is based on rbp
+                UnwindOp::Alloc(size) => {
+                    cfa += *size as i32;
+                    fde.add_instruction(offset, CallFrameInstruction::CfaOffset(cfa));
+                },
+                UnwindOp::Save(reg, displ) => if let Some(reg) = dwarf_reg(*reg) {
+                    fde.add_instruction(offset, CallFrameInstruction::Offset(reg, *displ as i32 - rbp_cfa));
+                },
+                UnwindOp::Epilogue => {
+                    fde.add_instruction(offset, CallFrameInstruction::RememberState);
+                    fde.add_instruction(offset, CallFrameInstruction::Cfa(X86_64::RSP, 8));
+                },
+                UnwindOp::EpilogueEnd => fde.add_instruction(offset, CallFrameInstruction::RestoreState),
+            }
+        }
+
+        table.add_fde(cie, fde);
+    }
+
+    let mut eh_frame = EhFrame(DebugSection::default());
+    table.write_eh_frame(&mut eh_frame)?;
+
+    let section = obj.add_section(".eh_frame", SectionKind::ReadOnlyData, eh_frame.0.data.slice().to_vec(), 8);
+
+    for reloc in eh_frame.0.relocs.iter() {
+        if let RelocationTarget::Symbol(index) = reloc.target {
+            let kind = match reloc.eh_pe {
+                Some(eh_pe) if eh_pe.application() == constants::DW_EH_PE_pcrel => RelocationKind::Relative,
+                _ => RelocationKind::Absolute,
+            };
+
+            // relative to the section of the function (a public function could be replaced in a shared object)
+            match obj.symbol_offset(&funcs[index].name) {
+                Some((target, offset)) => {
+                    obj.relocate_to_section(section, reloc.offset as u64, target, offset as i64 + reloc.addend, kind, reloc.size * 8)?;
+                },
+                None => obj.relocate(section, reloc.offset as u64, &funcs[index].name, reloc.addend, kind, reloc.size * 8)?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+const UWOP_PUSH_NONVOL: u8 = 0;
+const UWOP_ALLOC_LARGE: u8 = 1;
+const UWOP_ALLOC_SMALL: u8 = 2;
+const UWOP_SET_FPREG: u8 = 3;
+const UWOP_SAVE_NONVOL: u8 = 4;
+const UWOP_SAVE_NONVOL_FAR: u8 = 5;
+const UWOP_SAVE_XMM128: u8 = 8;
+const UWOP_SAVE_XMM128_FAR: u8 = 9;
+
+/// Returns the `UNWIND_INFO` of the function for `.xdata`
+///
+/// The saved registers are described relative to the bottom of the allocation,
+/// which rbp points above with the frame offset (see `Abi::frame_after_alloc`)
+pub fn unwind_info(ops: &[(usize, UnwindOp)]) -> Vec<u8> {
+    let frame_offset = ops.iter().find_map(|op| match op.1 {
+        UnwindOp::SetFrame(offset) => Some(offset),
+        _ => None,
+    });
+
+    let alloc: i64 = ops.iter().filter_map(|op| match op.1 {
+        UnwindOp::Alloc(size) => Some(size),
+        _ => None,
+    }).sum();
+
+    // the distance of rbp (or where it would be in leaf functions) from the bottom of the allocation
+    let base = frame_offset.unwrap_or(alloc - 8);
+
+    let mut codes: Vec<u16> = vec![];
+    let mut prolog = 0;
+
+    // the codes are in the reverse order of the prologue
+    for (offset, op) in ops.iter().rev() {
+        let code = |op: u8, info: u8| (*offset as u16 & 0xFF) | ((op | info << 4) as u16) << 8;
+
+        match op {
+            UnwindOp::PushFrame => codes.push(code(UWOP_PUSH_NONVOL, 5)),
+            UnwindOp::SetFrame(_) => codes.push(code(UWOP_SET_FPREG, 0)),
+            UnwindOp::Alloc(size) if *size <= 128 => codes.push(code(UWOP_ALLOC_SMALL, (*size as u8 - 8) / 8)),
+            UnwindOp::Alloc(size) if *size < 512 * 1024 => {
+                codes.push(code(UWOP_ALLOC_LARGE, 0));
+                codes.push((*size / 8) as u16);
+            },
+            UnwindOp::Alloc(size) => {
+                codes.push(code(UWOP_ALLOC_LARGE, 1));
+                codes.push(*size as u16);
+                codes.push((*size >> 16) as u16);
+            },
+            UnwindOp::Save(reg, displ) => {
+                let at = base + displ;
+                let (scale, near, far) = if reg.is_xmm() {
+                    (16, UWOP_SAVE_XMM128, UWOP_SAVE_XMM128_FAR)
+                } else {
+                    (8, UWOP_SAVE_NONVOL, UWOP_SAVE_NONVOL_FAR)
+                };
+
+                if at % scale == 0 && at / scale <= u16::MAX as i64 {
+                    codes.push(code(near, reg.number() as u8));
+                    codes.push((at / scale) as u16);
+                } else {
+                    codes.push(code(far, reg.number() as u8));
+                    codes.push(at as u16);
+                    codes.push((at >> 16) as u16);
+                }
+            },
+            _ => continue,
+        }
+
+        prolog = prolog.max(*offset);
+    }
+
+    let mut info = vec![
+        1, // version 1 without flags
+        prolog as u8,
+        codes.len() as u8, // the count of the slots (without padding)
+        // rbp is the frame register with the scaled offset, leaf functions have none
+        frame_offset.map_or(0, |offset| 5 | ((offset / 16) as u8) << 4),
+    ];
+
+    if !codes.len().is_multiple_of(2) { // the codes are padded to a multiple of 4 bytes
+        codes.push(0);
+    }
+
+    for slot in codes {
+        info.extend_from_slice(&slot.to_le_bytes());
+    }
+
+    info
+}
+
+/// Adds the `.pdata` and `.xdata` sections of the functions into the object
+pub fn write_pdata_xdata(obj: &mut ObjectWriter, funcs: &[UnwindFunc]) -> Result<(), Box<dyn Error>> {
+    let funcs: Vec<&UnwindFunc> = funcs.iter().filter(|func| !func.ops.is_empty()).collect();
+
+    if funcs.is_empty() {
+        return Ok(());
+    }
+
+    let mut xdata = vec![];
+    let mut xdata_offsets = vec![];
+
+    for func in funcs.iter() {
+        xdata_offsets.push(xdata.len() as i64);
+        xdata.extend(unwind_info(&func.ops));
+    }
+
+    let xdata = obj.add_section(".xdata", SectionKind::ReadOnlyData, xdata, 4);
+    let pdata = obj.add_section(".pdata", SectionKind::ReadOnlyData, vec![0; funcs.len() * 12], 4);
+
+    // RUNTIME_FUNCTION: begin, end, unwind info
+    for (index, func) in funcs.iter().enumerate() {
+        let at = index as u64 * 12;
+
+        obj.relocate(pdata, at, &func.name, 0, RelocationKind::ImageOffset, 32)?;
+        obj.relocate(pdata, at + 4, &func.name, func.size as i64, RelocationKind::ImageOffset, 32)?;
+        obj.relocate_to_section(pdata, at + 8, xdata, xdata_offsets[index], RelocationKind::ImageOffset, 32)?;
+    }
+
+    Ok(())
+}
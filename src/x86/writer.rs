@@ -0,0 +1,254 @@
+//! Writes the object file via the `object` crate
+
+use std::{collections::HashMap, error::Error, fs};
+
+use formatic::{BinFormat, Decl, Link, Scope};
+use object::{
+    elf,
+    write::{Object, Relocation, SectionId, StandardSection, StandardSegment, Symbol, SymbolId, SymbolSection},
+    Architecture, BinaryFormat, Endianness, RelocationEncoding, RelocationFlags, RelocationKind, SectionKind,
+    SymbolFlags, SymbolKind, SymbolScope,
+};
+
+use crate::error::CodeGenLibError;
+
+use super::attrs::FuncAttrs;
+
+/// Converts the formatic binary format into the one of the `object` crate
+fn binary_format(bin: BinFormat) -> Result<BinaryFormat, CodeGenLibError> {
+    #[allow(unreachable_patterns)]
+    match bin {
+        BinFormat::Elf => Ok(BinaryFormat::Elf),
+        BinFormat::Coff => Ok(BinaryFormat::Coff),
+        BinFormat::Macho => Ok(BinaryFormat::MachO),
+        _ => Err(CodeGenLibError::UnsupportedFormat(format!("{bin:?}"))),
+    }
+}
+
+/// Builds an object file out of functions, data and links
+pub struct ObjectWriter {
+    obj: Object<'static>,
+
+    /// The sections which got created for the section names of the function attributes
+    sections: HashMap<String, SectionId>,
+
+    /// Every symbol which is defined or imported
+    symbols: HashMap<String, SymbolId>,
+
+    links: Vec<Link>,
+}
+
+impl ObjectWriter {
+    /// Creates a new empty object for the binary format
+    pub fn new(bin: BinFormat) -> Result<Self, CodeGenLibError> {
+        let mut obj = Object::new(binary_format(bin)?, Architecture::X86_64, Endianness::Little);
+
+        if obj.format() == BinaryFormat::Elf { // the stack isn't executable
+            obj.add_section(vec![], b".note.GNU-stack".to_vec(), SectionKind::Elf(elf::SHT_PROGBITS));
+        }
+
+        Ok(Self {
+            obj,
+            sections: HashMap::new(),
+            symbols: HashMap::new(),
+            links: vec![],
+        })
+    }
+
+    /// Returns if the object is an elf file
+    pub fn elf(&self) -> bool {
+        self.obj.format() == BinaryFormat::Elf
+    }
+
+    /// Returns if the object is a coff file
+    pub fn coff(&self) -> bool {
+        self.obj.format() == BinaryFormat::Coff
+    }
+
+    fn scope(public: bool) -> SymbolScope {
+        match public {
+            true => SymbolScope::Dynamic,
+            false => SymbolScope::Compilation,
+        }
+    }
+
+    /// Returns the section with the name (or the standard section if it is `None`)
+    fn section(&mut self, name: Option<String>, standard: StandardSection, kind: SectionKind) -> SectionId {
+        let name = match name {
+            Some(name) => name,
+            None => return self.obj.section_id(standard),
+        };
+
+        if let Some(section) = self.sections.get(&name) {
+            return *section;
+        }
+
+        let segment = self.obj.segment_name(StandardSegment::Text).to_vec();
+        let section = self.obj.add_section(segment, name.as_bytes().to_vec(), kind);
+
+        self.sections.insert(name, section);
+
+        section
+    }
+
+    fn define(&mut self, name: &str, section: SectionId, data: &[u8], align: u64, kind: SymbolKind, public: bool) -> (SymbolId, u64) {
+        let symbol = self.obj.add_symbol(Symbol {
+            name: name.as_bytes().to_vec(),
+            value: 0,
+            size: 0,
+            kind,
+            scope: Self::scope(public),
+            weak: false,
+            section: SymbolSection::Undefined,
+            flags: SymbolFlags::None,
+        });
+
+        let offset = self.obj.add_symbol_data(symbol, section, data, align);
+
+        self.symbols.insert(name.into(), symbol);
+
+        (symbol, offset)
+    }
+
+    /// Defines the function and returns its section and offset in it
+    pub fn define_func(&mut self, name: &str, code: &[u8], public: bool, attrs: &FuncAttrs) -> (SectionId, u64) {
+        let section = self.section(attrs.section_name(self.elf()), StandardSection::Text, SectionKind::Text);
+
+        let (_, offset) = self.define(name, section, code, attrs.align.max(1), SymbolKind::Text, public);
+
+        (section, offset)
+    }
+
+    /// Defines read only data
+    pub fn define_data(&mut self, name: &str, data: &[u8], public: bool) {
+        let section = self.obj.section_id(StandardSection::ReadOnlyData);
+
+        self.define(name, section, data, 8, SymbolKind::Data, public);
+    }
+
+    /// Declares a symbol which is defined outside of the object file
+    pub fn import(&mut self, name: &str, decl: Decl) {
+        if self.symbols.contains_key(name) {
+            return;
+        }
+
+        let kind = match decl {
+            Decl::Function(_) => SymbolKind::Text,
+            _ => SymbolKind::Data,
+        };
+
+        let symbol = self.obj.add_symbol(Symbol {
+            name: name.as_bytes().to_vec(),
+            value: 0,
+            size: 0,
+            kind,
+            scope: SymbolScope::Dynamic,
+            weak: false,
+            section: SymbolSection::Undefined,
+            flags: SymbolFlags::None,
+        });
+
+        self.symbols.insert(name.into(), symbol);
+    }
+
+    /// Adds the declaration (only imports need to be declared, everything else gets defined)
+    pub fn add_decl(&mut self, name: &str, decl: Decl) {
+        if matches!(decl, Decl::Function(Scope::Import) | Decl::Data(Scope::Import)) {
+            self.import(name, decl);
+        }
+    }
+
+    /// Adds a 32 bit pc relative link which gets resolved when the object is written
+    pub fn link(&mut self, link: Link) {
+        self.links.push(link);
+    }
+
+    /// Returns the section and offset of a defined symbol
+    pub fn symbol_offset(&mut self, name: &str) -> Option<(SectionId, u64)> {
+        let symbol = *self.symbols.get(name)?;
+
+        match self.obj.symbol(symbol).section {
+            SymbolSection::Section(section) => Some((section, self.obj.symbol(symbol).value)),
+            _ => None,
+        }
+    }
+
+    /// Adds a relocation at `offset` in `section`
+    pub fn relocate(&mut self, section: SectionId, offset: u64, target: &str, addend: i64, kind: RelocationKind, size: u8) -> Result<(), Box<dyn Error>> {
+        let symbol = match self.symbols.get(target) {
+            Some(symbol) => *symbol,
+            None => {
+                self.import(target, Decl::Function(Scope::Import));
+                self.symbols[target]
+            },
+        };
+
+        self.obj.add_relocation(section, Relocation {
+            offset,
+            symbol,
+            addend,
+            flags: RelocationFlags::Generic {
+                kind,
+                encoding: RelocationEncoding::Generic,
+                size,
+            },
+        })?;
+
+        Ok(())
+    }
+
+    /// Adds a section with the data (like debug or unwind info)
+    pub fn add_section(&mut self, name: &str, kind: SectionKind, data: Vec<u8>, align: u64) -> SectionId {
+        let segment = match kind {
+            SectionKind::Debug => self.obj.segment_name(StandardSegment::Debug),
+            _ => self.obj.segment_name(StandardSegment::Data),
+        }.to_vec();
+
+        let section = self.obj.add_section(segment, name.as_bytes().to_vec(), kind);
+
+        self.obj.set_section_data(section, data, align);
+
+        section
+    }
+
+    /// Adds a relocation at `offset` in `section` to the start of the section `target`
+    pub fn relocate_to_section(&mut self, section: SectionId, offset: u64, target: SectionId, addend: i64, kind: RelocationKind, size: u8) -> Result<(), Box<dyn Error>> {
+        let symbol = self.obj.section_symbol(target);
+
+        self.obj.add_relocation(section, Relocation {
+            offset,
+            symbol,
+            addend,
+            flags: RelocationFlags::Generic {
+                kind,
+                encoding: RelocationEncoding::Generic,
+                size,
+            },
+        })?;
+
+        Ok(())
+    }
+
+    /// Resolves the links and writes the object into the file `outpath`
+    pub fn write(mut self, outpath: &str) -> Result<(), Box<dyn Error>> {
+        for link in std::mem::take(&mut self.links) {
+            let (section, offset) = match self.symbol_offset(&link.from) {
+                Some(pos) => pos,
+                None => return Err(Box::from(CodeGenLibError::FuncNotExist(link.from))),
+            };
+
+            // calls to other functions can go through the plt
+            let kind = match self.symbols.get(&link.to) {
+                Some(symbol) if self.obj.symbol(*symbol).kind == SymbolKind::Data => RelocationKind::Relative,
+                _ if self.elf() => RelocationKind::PltRelative,
+                _ => RelocationKind::Relative,
+            };
+
+            self.relocate(section, offset + link.at as u64, &link.to, -4, kind, 32)?;
+        }
+
+        fs::write(outpath, self.obj.write()?)?;
+
+        Ok(())
+    }
+}
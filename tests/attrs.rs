@@ -0,0 +1,97 @@
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use object::{Object, ObjectSection, ObjectSymbol};
+    use formatic::Link;
+    use CodeGenLib::{
+        attrs::FuncAttrs, error::CodeGenLibError, opt::frame_size, target::{linux::LinuxAbi, Abi}, writer::ObjectWriter, BinFormat, Builder,
+        optimize, IR::*,
+    };
+
+    #[test]
+    fn naked() -> Result<(), Box<dyn Error>> {
+        let code = vec![Push(Register::RAX), Jmp("target".into())];
+
+        let abi = Abi::linux();
+        let frame = frame_size(&code, None, &abi);
+
+        assert_eq!(optimize(code.to_owned(), &abi, frame, &FuncAttrs::naked())?, code);
+
+        Ok(())
+    }
+
+    #[test]
+    fn noreturn() -> Result<(), Box<dyn Error>> {
+        let attrs = FuncAttrs {
+            noreturn: true,
+            ..Default::default()
+        };
+
+        let code = optimize(vec![MovVal(Register::RBX, 1), Call("exit".into())], &Abi::linux(), 0, &attrs)?;
+
+        assert!(!code.contains(&Ret));
+        assert!(!code.iter().any(|instr| matches!(instr, Store(_, _)))); // rbx doesn't get saved
+
+        Ok(())
+    }
+
+    #[test]
+    fn noreturn_with_ret() -> Result<(), Box<dyn Error>> {
+        let mut builder = Builder::new();
+
+        builder.define_with_attrs("test", true, vec![Ret], FuncAttrs { noreturn: true, ..Default::default() })?;
+
+        assert!(builder.write("tmp/attrs_ret.o", BinFormat::Elf).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn layout() -> Result<(), Box<dyn Error>> {
+        let mut builder = Builder::new();
+
+        builder.define_with_attrs("cold", true, vec![Ret], FuncAttrs { cold: true, ..Default::default() })?;
+        builder.define_with_attrs("stub", true, vec![Jmp("cold".into())], FuncAttrs::naked())?;
+        builder.define_with_attrs("aligned", true, vec![Ret], FuncAttrs { align: 64, section: Some(".text.custom".into()), ..Default::default() })?;
+        builder.define_with_attrs("hot", true, vec![Ret], FuncAttrs { hot: true, ..Default::default() })?;
+
+        builder.write("tmp/attrs.o", BinFormat::Elf)?;
+
+        let data = std::fs::read("tmp/attrs.o")?;
+        let file = object::File::parse(&*data)?;
+
+        let section_of = |name: &str| {
+            let symbol = file.symbol_by_name(name).unwrap();
+            let section = file.section_by_index(symbol.section_index().unwrap()).unwrap();
+
+            (section.name().unwrap().to_string(), symbol.address(), section.align())
+        };
+
+        assert_eq!(section_of("cold").0, ".text.unlikely");
+        assert_eq!(section_of("hot").0, ".text.hot");
+
+        let aligned = section_of("aligned");
+        assert_eq!(aligned.0, ".text.custom");
+        assert_eq!(aligned.2, 64);
+
+        // the naked stub is only the jmp
+        let stub = file.symbol_by_name("stub").unwrap();
+        assert_eq!(stub.size(), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn missing_link_source() -> Result<(), Box<dyn Error>> {
+        let mut obj = ObjectWriter::new(BinFormat::Elf)?;
+
+        obj.define_func("func", &[0xE8, 0, 0, 0, 0, 0xC3], true, &FuncAttrs::default());
+        obj.link(Link { from: "missing".into(), to: "func".into(), at: 1 });
+
+        let err = obj.write("tmp/missing_link.o").err().and_then(|err| err.downcast::<CodeGenLibError>().ok());
+        assert!(matches!(err.as_deref(), Some(CodeGenLibError::FuncNotExist(name)) if name == "missing"));
+
+        Ok(())
+    }
+}
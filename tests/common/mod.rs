@@ -0,0 +1,97 @@
+//! Helpers to run generated machine code inside of the tests
+
+#![allow(dead_code)]
+
+use std::{collections::HashMap, error::Error};
+
+use CodeGenLib::{attrs::FuncAttrs, ir::AsmInstructionEnum, opt::frame_size, resolve, target::Abi, Builder, optimize};
+
+/// Copies `code` into executable memory and returns a pointer to it
+///
+/// The memory is never freed (it only lives as long as the test process)
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub fn executable(code: &[u8]) -> *const u8 {
+    let ptr: i64;
+
+    unsafe {
+        // mmap(NULL, len, PROT_READ | PROT_WRITE | PROT_EXEC, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0)
+        std::arch::asm!(
+            "syscall",
+            inlateout("rax") 9i64 => ptr,
+            in("rdi") 0i64,
+            in("rsi") code.len() as i64,
+            in("rdx") 7i64,
+            in("r10") 0x22i64,
+            in("r8") -1i64,
+            in("r9") 0i64,
+            lateout("rcx") _,
+            lateout("r11") _,
+            options(nostack),
+        );
+
+        assert!(ptr > 0, "mmap failed ({ptr})");
+
+        std::ptr::copy_nonoverlapping(code.as_ptr(), ptr as *mut u8, code.len());
+    }
+
+    ptr as *const u8
+}
+
+/// Optimizes and resolves the function and returns the machine code
+pub fn compile(name: &str, code: Vec<AsmInstructionEnum>, abi: &Abi) -> Result<Vec<u8>, Box<dyn Error>> {
+    let frame = frame_size(&code, None, abi);
+    let code = optimize(code, abi, frame, &FuncAttrs::default())?;
+
+    let resolved = resolve(vec![name.into()], vec![], &code)?;
+
+    assert!(resolved.links.is_empty(), "the function needs relocations");
+
+    Ok(resolved.code)
+}
+
+/// Optimizes and resolves the function and links it with the labels of the builder
+///
+/// The labels get placed after the code (only links to the labels and to the function itself are supported)
+pub fn compile_linked(name: &str, code: Vec<AsmInstructionEnum>, builder: &mut Builder, abi: &Abi) -> Result<Vec<u8>, Box<dyn Error>> {
+    let frame = frame_size(&code, None, abi);
+    let code = optimize(code, abi, frame, &FuncAttrs::default())?;
+
+    let resolved = resolve(vec![name.into()], builder.label_names.clone(), &code)?;
+
+    builder.resolve_jump_tables(name, &resolved.labels)?;
+
+    let mut linked = resolved.code;
+    let mut symbols: HashMap<String, usize> = HashMap::new();
+
+    symbols.insert(name.into(), 0);
+
+    for label in builder.label_names.iter() {
+        linked.resize(linked.len().next_multiple_of(8), 0);
+        symbols.insert(label.to_owned(), linked.len());
+        linked.extend_from_slice(&builder.labels[label].1);
+    }
+
+    for link in resolved.links {
+        let target = symbols[&link.to] as i64;
+        let rel = (target - (link.at as i64 + 4)) as i32;
+
+        linked[link.at..link.at + 4].copy_from_slice(&rel.to_le_bytes());
+    }
+
+    // the entries of the jump tables are relative to the table
+    for (table, (func, targets)) in builder.jump_tables.iter() {
+        if func != name {
+            continue;
+        }
+
+        let start = symbols[table];
+
+        for (index, target) in targets.iter().enumerate() {
+            let rel = (resolved.labels[target] as i64 - start as i64) as i32;
+
+            linked[start + index * 4..start + index * 4 + 4].copy_from_slice(&rel.to_le_bytes());
+        }
+    }
+
+    Ok(linked)
+}
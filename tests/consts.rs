@@ -0,0 +1,264 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use CodeGenLib::{ir::{AsmInstructionEnum::*, IrFunctionBuilder, Type}, ssa::CmpOp, target::{linux::LinuxAbi, Abi}, Builder, IR::Register};
+
+    fn func() -> IrFunctionBuilder {
+        let abi = Abi::linux();
+        let mut func = IrFunctionBuilder::new("consts", &mut Builder::new(), &abi);
+
+        func.args(vec![]);
+        func.vars(vec![("a", Type::u64(0)), ("b", Type::u64(0)), ("c", Type::u64(0)), ("p", Type::Ptr(Box::from(Type::u64(0))))]);
+        func.efuncs(vec![("write", vec![Type::Ptr(Box::from(Type::u64(0)))])]);
+
+        func
+    }
+
+    #[test]
+    fn fold_add() -> Result<(), Box<dyn Error>> {
+        let mut func = func();
+
+        func.build_set("a", Type::u64(2))?;
+        func.build_set("b", Type::u64(3))?;
+        func.build_add("a", "b", "c")?;
+        func.build_add("c", "c", "c")?;
+        func.build_return_var("c")?;
+
+        assert!(!func.generated.iter().any(|instr| matches!(instr, Load(..) | AddMem(..))), "{:?}", func.generated);
+        assert!(func.generated.contains(&MovVal(Register::RAX, 10)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn fold_u32() -> Result<(), Box<dyn Error>> {
+        let mut func = func();
+        func.vars(vec![("x", Type::u32(0)), ("y", Type::i32(0)), ("z", Type::u32(0))]);
+
+        func.build_set("x", Type::u32(0xFFFF_FFFF))?;
+        func.build_set("y", Type::i32(2))?;
+        func.build_add("x", "y", "z")?;
+
+        assert!(!func.generated.iter().any(|instr| matches!(instr, Load(..) | AddMem(..))), "{:?}", func.generated);
+        assert!(func.generated.contains(&MovVal(Register::EAX, 1)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn fold_sub() -> Result<(), Box<dyn Error>> {
+        let mut func = func();
+
+        func.build_set("a", Type::u64(2))?;
+        func.build_set("b", Type::u64(3))?;
+        func.build_sub("a", "b", "c")?;
+
+        assert!(!func.generated.iter().any(|instr| matches!(instr, Load(..) | SubMem(..))), "{:?}", func.generated);
+        assert!(func.generated.contains(&MovVal(Register::RAX, -1)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn fold_mul() -> Result<(), Box<dyn Error>> {
+        let mut func = func();
+
+        func.build_set("a", Type::u64(6))?;
+        func.build_set("b", Type::u64(7))?;
+        func.build_mul("a", "b", "c")?;
+        func.build_mul("c", "a", "c")?;
+
+        assert!(!func.generated.iter().any(|instr| matches!(instr, Load(..) | MulMem(..))), "{:?}", func.generated);
+        assert!(func.generated.contains(&MovVal(Register::RAX, 252)));
+
+        // b isn't known anymore
+        func.build_label("join");
+        func.build_mul("a", "b", "c")?;
+
+        assert!(func.generated.iter().any(|instr| matches!(instr, MulMem(..))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn fold_branch() -> Result<(), Box<dyn Error>> {
+        let mut func = func();
+
+        func.build_set("a", Type::u64(1))?;
+        func.build_set("b", Type::i64(-1))?;
+
+        // -1 is less than 1 signed, but greater unsigned
+        func.build_branch("b", CmpOp::Lt, "a", "less")?;
+        assert_eq!(func.generated.last(), Some(&Jmp("less".into())));
+
+        let len = func.generated.len();
+        func.build_set("a", Type::u64(1))?;
+        func.build_set("b", Type::i64(-1))?;
+        func.build_branch("b", CmpOp::ULt, "a", "below")?;
+
+        assert_eq!(func.generated.len(), len + 4);
+        assert!(!func.generated.iter().any(|instr| matches!(instr, CmpReg(..))));
+
+        // the values aren't known after a label
+        func.build_label("join");
+        func.build_branch("b", CmpOp::ULt, "a", "below")?;
+        assert!(func.generated.iter().any(|instr| matches!(instr, CmpReg(..))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn label() -> Result<(), Box<dyn Error>> {
+        let mut func = func();
+
+        func.build_set("a", Type::u64(2))?;
+        func.build_label("join");
+        func.build_add("a", "a", "c")?;
+
+        assert!(func.generated.iter().any(|instr| matches!(instr, AddMem(..))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn stored_through_pointer() -> Result<(), Box<dyn Error>> {
+        let mut func = func();
+
+        func.build_set("a", Type::u64(1))?;
+        func.build_set("p", Type::Ptr(Box::from(Type::InVar("a".into()))))?;
+
+        // the callee can write a through p
+        func.build_call("write", vec![Type::InVar("p".into())])?;
+        func.build_set("a", Type::u64(5))?;
+        func.build_return_var("a")?;
+
+        assert_eq!(func.generated[func.generated.len() - 2], Load(Register::RAX, Abi::linux().stack(-8)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn passed_by_address() -> Result<(), Box<dyn Error>> {
+        let mut func = func();
+
+        func.build_set("b", Type::u64(1))?;
+        func.build_call("write", vec![Type::Ptr(Box::from(Type::InVar("b".into())))])?;
+        func.build_return_var("b")?;
+
+        let abi = Abi::linux();
+
+        assert!(func.generated.contains(&Lea(Register::RDI, abi.stack(-16))));
+        assert_eq!(func.generated[func.generated.len() - 2], Load(Register::RAX, abi.stack(-16)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn fold_switch() -> Result<(), Box<dyn Error>> {
+        let mut func = func();
+
+        func.build_set("a", Type::u64(7))?;
+        func.build_switch("a", vec![(1, "one"), (7, "seven")], "default")?;
+
+        assert_eq!(func.generated.last(), Some(&Jmp("seven".into())));
+        assert!(!func.generated.iter().any(|instr| matches!(instr, CmpVal(..))));
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, target_os = "linux", target_arch = "x86_64"))]
+mod jit_tests {
+    use std::error::Error;
+
+    use CodeGenLib::{ir::{IrFunctionBuilder, Type}, ssa::CmpOp, target::{linux::LinuxAbi, Abi}, Builder};
+
+    use super::common::{compile_linked, executable};
+
+    #[test]
+    fn extended() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+        let mut builder = Builder::new();
+        let mut func = IrFunctionBuilder::new("consts", &mut builder, &abi);
+
+        func.args(vec![]);
+        func.vars(vec![("x", Type::i32(0)), ("y", Type::i64(0)), ("z", Type::u32(0)), ("r", Type::i64(0))]);
+
+        // -5 gets sign extended, truncated into z and zero extended for the switch
+        func.build_set("x", Type::i32(-5))?;
+        func.build_set("y", Type::InVar("x".into()))?;
+        func.build_add("y", "y", "y")?;
+        func.build_set("z", Type::InVar("y".into()))?;
+        func.build_switch("z", vec![(-10, "negative"), (0xFFFF_FFF6, "truncated")], "default")?;
+
+        for (label, value) in [("negative", 1), ("truncated", 2), ("default", 3)] {
+            func.build_label(label);
+            func.build_set("r", Type::i64(value))?;
+            func.build_jmp("end");
+        }
+
+        func.build_label("end");
+        func.build_return_var("r")?;
+
+        let code = compile_linked("consts", func.generated, &mut func.builder, &abi)?;
+        let func = unsafe { std::mem::transmute::<*const u8, extern "sysv64" fn() -> i64>(executable(&code)) };
+
+        assert_eq!(func(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn branches() -> Result<(), Box<dyn Error>> {
+        let ops = [
+            (CmpOp::Eq, i64::eq as fn(&i64, &i64) -> bool), (CmpOp::Ne, i64::ne), (CmpOp::Lt, i64::lt),
+            (CmpOp::Le, i64::le), (CmpOp::Gt, i64::gt), (CmpOp::Ge, i64::ge),
+        ];
+        let uops = [(CmpOp::ULt, u64::lt as fn(&u64, &u64) -> bool), (CmpOp::ULe, u64::le), (CmpOp::UGt, u64::gt), (CmpOp::UGe, u64::ge)];
+
+        let compile = |op: CmpOp, typ: Type| -> Result<extern "sysv64" fn(i64, i64) -> i64, Box<dyn Error>> {
+            let abi = Abi::linux();
+            let mut builder = Builder::new();
+            let mut func = IrFunctionBuilder::new("branch", &mut builder, &abi);
+
+            func.args(vec![("a", typ.to_owned()), ("b", typ)]);
+            func.vars(vec![]);
+
+            func.build_branch("a", op, "b", "taken")?;
+            func.build_return_int(0)?;
+            func.build_label("taken");
+            func.build_return_int(1)?;
+
+            let code = compile_linked("branch", func.generated, &mut func.builder, &abi)?;
+
+            Ok(unsafe { std::mem::transmute::<*const u8, extern "sysv64" fn(i64, i64) -> i64>(executable(&code)) })
+        };
+
+        let values = [-2, -1, 0, 1, 2];
+
+        for (op, expected) in ops {
+            let func = compile(op, Type::i64(0))?;
+            let func32 = compile(op, Type::i32(0))?;
+
+            for (a, b) in values.iter().flat_map(|a| values.iter().map(move |b| (*a, *b))) {
+                assert_eq!(func(a, b) == 1, expected(&a, &b), "{a} {op:?} {b}");
+                assert_eq!(func32(a, b) == 1, expected(&a, &b), "{a} {op:?} {b} (32 bit)");
+            }
+        }
+
+        for (op, expected) in uops {
+            let func = compile(op, Type::u64(0))?;
+            let func32 = compile(op, Type::u32(0))?;
+
+            for (a, b) in values.iter().flat_map(|a| values.iter().map(move |b| (*a, *b))) {
+                assert_eq!(func(a, b) == 1, expected(&(a as u64), &(b as u64)), "{a} {op:?} {b}");
+                assert_eq!(func32(a, b) == 1, expected(&(a as u32 as u64), &(b as u32 as u64)), "{a} {op:?} {b} (32 bit)");
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use object::{Object, ObjectSymbol};
+    use CodeGenLib::{
+        attrs::FuncAttrs, ir::AsmInstructionEnum::{self, *}, opt::{dce::{dce, dse}, pass::PassFn, OptLevel, PassContext},
+        target::{linux::LinuxAbi, Abi}, BinFormat, Builder, IR::Register,
+    };
+
+    fn run(pass: &PassFn, code: Vec<AsmInstructionEnum>) -> Vec<AsmInstructionEnum> {
+        pass(code, &PassContext { name: "test", abi: &Abi::linux(), attrs: &FuncAttrs::default(), stack_args: 0, remarks: Default::default() }).unwrap()
+    }
+
+    #[test]
+    fn unreachable() {
+        let code = vec![
+            CmpVal(Register::RAX, 0),
+            Je("zero".into()),
+            Ret,
+            MovVal(Register::RAX, 1), // after the return
+            Label("unused".into()),
+            MovVal(Register::RAX, 2), // behind a label nobody jumps to
+            Label("zero".into()),
+            Jmp("end".into()),
+            Label("loop".into()), // only reachable from itself
+            Jmp("loop".into()),
+            Label("end".into()),
+            Ret,
+        ];
+
+        assert_eq!(run(&dce, code), vec![
+            CmpVal(Register::RAX, 0),
+            Je("zero".into()),
+            Ret,
+            Label("zero".into()),
+            Jmp("end".into()),
+            Label("end".into()),
+            Ret,
+        ]);
+
+        // an indirect jump could reach every label
+        let code = vec![JmpReg(Register::RAX), Label("case".into()), Ret];
+        assert_eq!(run(&dce, code.to_owned()), code);
+    }
+
+    #[test]
+    fn dead_stores() {
+        let abi = Abi::linux();
+        let (a, b) = (abi.stack(-8), abi.stack(-16));
+
+        // the first store to a is overwritten and b is never read
+        let code = vec![
+            Store(Register::RAX, a),
+            Store(Register::RBX, a),
+            Store(Register::RCX, b),
+            Load(Register::RAX, a),
+            Ret,
+        ];
+
+        assert_eq!(run(&dse, code), vec![Store(Register::RBX, a), Load(Register::RAX, a), Ret]);
+
+        // b is read in the next iteration of the loop and a partly by the 4 byte load
+        let code = vec![
+            Label("loop".into()),
+            Load(Register::RAX, b),
+            Store(Register::RAX, b),
+            Store(Register::RCX, a),
+            Load(Register::EDX, abi.stack(-4)),
+            CmpVal(Register::RAX, 0),
+            Jne("loop".into()),
+            Ret,
+        ];
+
+        assert_eq!(run(&dse, code.to_owned()), code);
+    }
+
+    #[test]
+    fn escaped() {
+        let abi = Abi::linux();
+
+        // the callee can read the slot through the pointer
+        let code = vec![
+            Store(Register::RAX, abi.stack(-8)),
+            Lea(Register::RDI, abi.stack(-8)),
+            Call("read".into()),
+            Ret,
+        ];
+        assert_eq!(run(&dse, code.to_owned()), code);
+
+        let code = vec![Store(Register::RAX, abi.stack(-8)), MovReg(Register::RDI, Register::RBP), Call("read".into()), Ret];
+        assert_eq!(run(&dse, code.to_owned()), code);
+    }
+
+    fn written_symbols(level: OptLevel) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut builder = Builder::new();
+        builder.set_opt_level(level);
+
+        builder.define("main", true, vec![Call("helper".into()), MovPtr(Register::RAX, "used".into()), Ret])?;
+        builder.define("helper", false, vec![Jmp("tail".into())])?;
+        builder.define("tail", false, vec![Ret])?;
+        builder.define("unused", false, vec![Call("unused_helper".into()), Ret])?;
+        builder.define("unused_helper", false, vec![Ret])?;
+
+        builder.define_label("used", false, vec![1]);
+        builder.define_label("unused_data", false, vec![2]);
+        builder.define_label("public_data", true, vec![3]);
+
+        let path = format!("tmp/dce_{level:?}.o");
+        builder.write(&path, BinFormat::Elf)?;
+
+        let data = std::fs::read(&path)?;
+        let file = object::File::parse(&*data)?;
+
+        Ok(file.symbols().filter_map(|symbol| symbol.name().ok().map(|name| name.to_string())).collect())
+    }
+
+    #[test]
+    fn unused_symbols() -> Result<(), Box<dyn Error>> {
+        let symbols = written_symbols(OptLevel::O1)?;
+
+        for name in ["main", "helper", "tail", ".Lused", ".Lpublic_data"] {
+            assert!(symbols.contains(&name.to_string()), "{name} is missing: {symbols:?}");
+        }
+        for name in ["unused", "unused_helper", ".Lunused_data"] {
+            assert!(!symbols.contains(&name.to_string()), "{name} wasn't removed");
+        }
+
+        assert!(written_symbols(OptLevel::O0)?.contains(&"unused".to_string()));
+
+        Ok(())
+    }
+}
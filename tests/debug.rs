@@ -0,0 +1,106 @@
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, error::Error};
+
+    use gimli::{EndianSlice, RunTimeEndian};
+    use object::{Object, ObjectSection};
+    use CodeGenLib::{ir::{AsmInstructionEnum::*, SourceLoc, Type}, target::{linux::LinuxAbi, Abi}, BinFormat, Builder, IR::Register};
+
+    /// The names of the debug info entries and the `(address, line)` rows of the line table
+    type DebugInfo = (Vec<String>, Vec<(u64, u64)>);
+
+    fn read_debug_info(path: &str) -> Result<DebugInfo, Box<dyn Error>> {
+        let data = std::fs::read(path)?;
+        let file = object::File::parse(&*data)?;
+
+        let load = |id: gimli::SectionId| -> Result<Cow<[u8]>, gimli::Error> {
+            Ok(match file.section_by_name(id.name()) {
+                Some(section) => section.uncompressed_data().unwrap_or(Cow::Borrowed(&[])),
+                None => Cow::Borrowed(&[]),
+            })
+        };
+
+        let sections = gimli::DwarfSections::load(load)?;
+        let dwarf = sections.borrow(|section| EndianSlice::new(section, RunTimeEndian::Little));
+
+        let mut names = vec![];
+        let mut rows = vec![];
+
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+
+            let mut entries = unit.entries();
+            while let Some((_, entry)) = entries.next_dfs()? {
+                if let Some(name) = entry.attr_value(gimli::DW_AT_name)? {
+                    names.push(dwarf.attr_string(&unit, name)?.to_string_lossy().to_string());
+                }
+            }
+
+            if let Some(program) = unit.line_program.clone() {
+                let mut program_rows = program.rows();
+
+                while let Some((_, row)) = program_rows.next_row()? {
+                    if row.end_sequence() {
+                        continue;
+                    }
+
+                    if let Some(line) = row.line() {
+                        rows.push((row.address(), line.get()));
+                    }
+                }
+            }
+        }
+
+        Ok((names, rows))
+    }
+
+    #[test]
+    fn line_table_and_vars() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+        let mut builder = Builder::new();
+        builder.debug = true;
+
+        let file = builder.define_file("test.src");
+
+        builder.define("func", true, vec![
+            Loc(SourceLoc::new(file, 3, 1)),
+            Load(Register::RAX, abi.stack(-8)),
+            Loc(SourceLoc::new(file, 4, 1)),
+            Store(Register::EAX, abi.stack(-16)),
+            Ret,
+        ])?;
+        builder.define_vars("func", vec![("x".into(), -8, Type::u64(0)), ("y".into(), -16, Type::i32(0))]);
+
+        builder.write("tmp/debug.o", BinFormat::Elf)?;
+
+        let (names, rows) = read_debug_info("tmp/debug.o")?;
+
+        for name in ["test.src", "func", "x", "y", "u64", "i32"] {
+            assert!(names.contains(&name.to_string()), "{name} is missing in {names:?}");
+        }
+
+        let lines: Vec<u64> = rows.iter().map(|row| row.1).collect();
+        assert_eq!(lines, vec![3, 4]);
+
+        // the prologue comes before the first source location
+        assert!(rows[0].0 > 0 && rows[1].0 > rows[0].0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn no_debug_info() -> Result<(), Box<dyn Error>> {
+        let mut builder = Builder::new();
+
+        builder.define("func", true, vec![Ret])?;
+        builder.write("tmp/no_debug.o", BinFormat::Elf)?;
+
+        let data = std::fs::read("tmp/no_debug.o")?;
+        let file = object::File::parse(&*data)?;
+
+        assert!(file.section_by_name(".debug_info").is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use object::{Object, ObjectSymbol};
+    use CodeGenLib::{
+        attrs::FuncAttrs, ir::{AsmInstructionEnum::{self, *}, IrBuilder, Type}, opt::{inline::inline_module, remark::Remark, OptLevel},
+        target::{linux::LinuxAbi, Abi, Target}, Builder, IR::Register,
+    };
+
+    fn builder(helper: Vec<AsmInstructionEnum>, attrs: FuncAttrs) -> Result<Builder, Box<dyn Error>> {
+        let mut builder = Builder::new();
+        builder.abi = Abi::linux();
+
+        builder.define("main", true, vec![MovVal(Register::RDI, 5), Call("helper".into()), Ret])?;
+        builder.define_vars("main", vec![("a".into(), -8, Type::u64(0))]);
+        builder.define_with_attrs("helper", false, helper, attrs)?;
+
+        Ok(builder)
+    }
+
+    #[test]
+    fn renamed() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+
+        let helper = vec![
+            Store(Register::RDI, abi.stack(-8)),
+            Load(Register::RAX, abi.stack(-8)),
+            CmpVal(Register::RAX, 0),
+            Je("zero".into()),
+            Ret,
+            Label("zero".into()),
+            MovVal(Register::RAX, 1),
+            Ret,
+        ];
+
+        let (funcs, remarks) = inline_module(&builder(helper, FuncAttrs::default())?, 12);
+
+        // the slot of the helper is placed below the 16 byte frame of main
+        assert_eq!(funcs["main"], vec![
+            MovVal(Register::RDI, 5),
+            Store(Register::RDI, abi.stack(-24)),
+            Load(Register::RAX, abi.stack(-24)),
+            CmpVal(Register::RAX, 0),
+            Je(".helper.0.zero".into()),
+            Jmp(".helper.0".into()),
+            Label(".helper.0.zero".into()),
+            MovVal(Register::RAX, 1),
+            Label(".helper.0".into()),
+            Ret,
+        ]);
+
+        assert_eq!(remarks, vec![Remark::new("inline", "main", "inlined helper into main".into())]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn limits() -> Result<(), Box<dyn Error>> {
+        let inlined = |helper: Vec<AsmInstructionEnum>, attrs: FuncAttrs, threshold: usize| -> Result<bool, Box<dyn Error>> {
+            let (funcs, _) = inline_module(&builder(helper, attrs)?, threshold);
+            Ok(!funcs["main"].contains(&Call("helper".into())))
+        };
+
+        let helper = vec![MovVal(Register::RAX, 1), AddVal(Register::RAX, 2), Ret];
+
+        assert!(inlined(helper.to_owned(), FuncAttrs::default(), 3)?);
+        assert!(!inlined(helper.to_owned(), FuncAttrs::default(), 2)?);
+        assert!(inlined(helper.to_owned(), FuncAttrs { always_inline: true, ..Default::default() }, 0)?);
+        assert!(!inlined(helper.to_owned(), FuncAttrs { noinline: true, ..Default::default() }, 12)?);
+
+        // public functions are kept
+        let mut module = builder(helper, FuncAttrs::default())?;
+        module.funcs.get_mut("helper").unwrap().0 = true;
+        assert!(inline_module(&module, 12).0["main"].contains(&Call("helper".into())));
+
+        // a tail jump, a stack argument, a callee saved register and recursion
+        assert!(!inlined(vec![Jmp("main".into())], FuncAttrs::default(), 12)?);
+        assert!(!inlined(vec![Load(Register::RAX, Abi::linux().stack(16)), Ret], FuncAttrs::default(), 12)?);
+        assert!(!inlined(vec![MovVal(Register::RBX, 1), Ret], FuncAttrs::default(), 12)?);
+        assert!(!inlined(vec![Call("helper".into()), Ret], FuncAttrs::default(), 12)?);
+
+        // changing rbp and leaving something on the stack
+        assert!(!inlined(vec![SubVal(Register::RBP, 8), Ret], FuncAttrs::default(), 12)?);
+        assert!(!inlined(vec![SubVal(Register::RSP, 8), Ret], FuncAttrs::default(), 12)?);
+        assert!(!inlined(vec![PushVal(1), Ret], FuncAttrs::default(), 12)?);
+        assert!(inlined(vec![PushVal(1), Pop(Register::RAX), Ret], FuncAttrs::default(), 12)?);
+
+        Ok(())
+    }
+
+    fn written_symbols(level: OptLevel) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut builder = IrBuilder::new(Target::linux());
+        builder.set_opt_level(level);
+
+        let add = builder.add("add");
+        add.args(vec![("x", Type::u64(0)), ("y", Type::u64(0))]);
+        add.vars(vec![("z", Type::u64(0))]);
+        add.build_add("x", "y", "z")?;
+        add.build_return_var("z")?;
+
+        let main = builder.add("main");
+        main.efuncs(vec![("add", vec![Type::u64(0), Type::u64(0)])]);
+        main.vars(vec![("a", Type::u64(0))]);
+        main.build_call_ret("add", vec![Type::u64(1), Type::u64(2)], "a")?;
+        main.build_return_var("a")?;
+        main.set_public();
+
+        let path = format!("tmp/inline_{level:?}.o");
+        builder.write(&path)?;
+
+        let data = std::fs::read(&path)?;
+        let file = object::File::parse(&*data)?;
+
+        Ok(file.symbols().filter_map(|symbol| symbol.name().ok().map(|name| name.to_string())).collect())
+    }
+
+    #[test]
+    fn module() -> Result<(), Box<dyn Error>> {
+        // the inlined helper isn't used anymore
+        assert!(!written_symbols(OptLevel::O1)?.contains(&"add".to_string()));
+        assert!(written_symbols(OptLevel::O0)?.contains(&"add".to_string()));
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, target_os = "linux", target_arch = "x86_64"))]
+mod jit_tests {
+    use std::error::Error;
+
+    use CodeGenLib::{opt::inline::inline_module, target::{linux::LinuxAbi, Abi}, Builder, IR::*};
+
+    use super::common::{compile, executable};
+
+    #[test]
+    fn inlined() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+        let mut builder = Builder::new();
+        builder.abi = abi.to_owned();
+
+        // 100 + 1 + ... + x (the slot of the helper overlaps with the one of main if it isn't moved)
+        builder.define("helper", false, vec![
+            MovVal(Register::RDX, 100),
+            Store(Register::RDX, abi.stack(-8)),
+            MovVal(Register::RAX, 0),
+            Label("loop".into()),
+            CmpVal(Register::RDI, 0),
+            Je("done".into()),
+            AddReg(Register::RAX, Register::RDI),
+            Dec(Register::RDI),
+            Jmp("loop".into()),
+            Label("done".into()),
+            AddMem(Register::RAX, abi.stack(-8)),
+            Ret,
+        ])?;
+
+        // helper(x) + helper(x + 1)
+        builder.define("main", true, vec![
+            Store(Register::RDI, abi.stack(-8)),
+            Call("helper".into()),
+            Store(Register::RAX, abi.stack(-16)),
+            Load(Register::RDI, abi.stack(-8)),
+            AddVal(Register::RDI, 1),
+            Call("helper".into()),
+            AddMem(Register::RAX, abi.stack(-16)),
+            Ret,
+        ])?;
+
+        let (funcs, _) = inline_module(&builder, 12);
+        let code = compile("main", funcs["main"].to_owned(), &abi)?;
+        let main = unsafe { std::mem::transmute::<*const u8, extern "sysv64" fn(i64) -> i64>(executable(&code)) };
+
+        let helper = |x: i64| 100 + x * (x + 1) / 2;
+
+        for x in [0, 1, 5, 10] {
+            assert_eq!(main(x), helper(x) + helper(x + 1));
+        }
+
+        Ok(())
+    }
+}
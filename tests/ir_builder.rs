@@ -20,4 +20,214 @@ mod tests {
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod call_tests {
+    use std::error::Error;
+
+    use CodeGenLib::{ir::{AsmInstructionEnum::*, IrFunctionBuilder, Type}, target::{linux::LinuxAbi, windows::WindowsAbi, Abi}, Builder, IR::Register};
+
+    #[test]
+    fn sysv_stack_args() -> Result<(), Box<dyn Error>> {
+        let mut builder = Builder::new();
+        let mut func = IrFunctionBuilder::new("test", &mut builder, &Abi::linux());
+
+        func.efuncs(vec![("printf", vec![Type::Unlim(vec![])])]);
+
+        func.build_call("printf", vec![
+            Type::Str(b"%d %d %d %d %d %d %d".into()),
+            Type::u64(1), Type::u64(2), Type::u64(3), Type::u64(4), Type::u64(5),
+            Type::u64(6), Type::u64(7),
+        ])?;
+
+        assert_eq!(
+            func.generated,
+            vec![
+                PushVal(7),
+                PushVal(6),
+                MovPtr(Register::RDI, "test.0".into()),
+                MovVal(Register::RSI, 1),
+                MovVal(Register::RDX, 2),
+                MovVal(Register::RCX, 3),
+                MovVal(Register::R8, 4),
+                MovVal(Register::R9, 5),
+                MovVal(Register::AL, 0),
+                Call("printf".into()),
+                AddVal(Register::RSP, 16),
+            ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn sysv_bytes_stack_arg() -> Result<(), Box<dyn Error>> {
+        let mut builder = Builder::new();
+        let mut func = IrFunctionBuilder::new("test", &mut builder, &Abi::linux());
+
+        func.efuncs(vec![("take", vec![Type::Bytes(vec![0; 16])])]);
+
+        assert!(func.build_call("take", vec![Type::Bytes(vec![1; 16])]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn push_label() -> Result<(), Box<dyn Error>> {
+        let resolved = CodeGenLib::resolve(vec![], vec![], &vec![PushLabel("data".into())])?;
+
+        // push qword [rip + disp32] with the displacement at offset 2
+        assert_eq!(resolved.code, vec![0xFF, 0x35, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(resolved.links[0].at, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn sysv_alignment() -> Result<(), Box<dyn Error>> {
+        let mut builder = Builder::new();
+        let mut func = IrFunctionBuilder::new("test", &mut builder, &Abi::linux());
+
+        func.efuncs(vec![("callme", vec![Type::u32(0); 7])]);
+
+        func.build_call("callme", vec![Type::u32(1); 7])?;
+
+        assert_eq!(func.generated[0], SubVal(Register::RSP, 8));
+        assert_eq!(func.generated[1], PushVal(1));
+        assert_eq!(func.generated[2], MovVal(Register::EDI, 1));
+        assert_eq!(func.generated.last(), Some(&AddVal(Register::RSP, 16)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn win64_shadow_space() -> Result<(), Box<dyn Error>> {
+        let mut builder = Builder::new();
+        let mut func = IrFunctionBuilder::new("test", &mut builder, &Abi::windows());
+
+        func.efuncs(vec![("callme", vec![Type::u64(0); 5])]);
+
+        func.build_call("callme", vec![Type::u64(1), Type::u64(2), Type::u64(3), Type::u64(4), Type::u64(5)])?;
+
+        assert_eq!(
+            func.generated,
+            vec![
+                SubVal(Register::RSP, 8),
+                PushVal(5),
+                SubVal(Register::RSP, 32),
+                MovVal(Register::RCX, 1),
+                MovVal(Register::RDX, 2),
+                MovVal(Register::R8, 3),
+                MovVal(Register::R9, 4),
+                Call("callme".into()),
+                AddVal(Register::RSP, 48),
+            ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn win64_positional_args() -> Result<(), Box<dyn Error>> {
+        let mut builder = Builder::new();
+        let mut func = IrFunctionBuilder::new("test", &mut builder, &Abi::windows());
+
+        func.args(vec![
+            ("x", Type::u64(0)),
+            ("data", Type::Bytes(vec![0; 32])),
+            ("y", Type::u32(0)),
+        ]);
+        func.vars(vec![]);
+
+        // the bytes are passed by reference in the second register
+        assert!(matches!(func.generated[0], Store(Register::RCX, _)));
+        assert!(matches!(func.generated[1], Store(Register::RDX, _)));
+        assert!(matches!(func.generated[2], Store(Register::R8D, _)));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod ret_tests {
+    use std::error::Error;
+
+    use CodeGenLib::{ir::{AsmInstructionEnum::*, IrFunctionBuilder, Type}, target::{linux::LinuxAbi, windows::WindowsAbi, Abi}, Builder, IR::Register};
+
+    #[test]
+    fn sysv_register_pair() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+
+        let mut builder = Builder::new();
+        let mut func = IrFunctionBuilder::new("test", &mut builder, &abi);
+
+        func.set_ret(Type::Bytes(vec![0; 16]));
+        func.vars(vec![("r", Type::Bytes(vec![0; 16]))]);
+
+        func.build_return_var("r")?;
+
+        assert_eq!(
+            func.generated,
+            vec![
+                Load(Register::RAX, abi.stack(-16)),
+                Load(Register::RDX, abi.stack(-8)),
+                Ret,
+            ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn win64_sret() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::windows();
+
+        let mut builder = Builder::new();
+        let mut func = IrFunctionBuilder::new("test", &mut builder, &abi);
+
+        func.args(vec![("x", Type::u64(0))]);
+        func.set_ret(Type::Bytes(vec![0; 16]));
+        func.vars(vec![("r", Type::Bytes(vec![0; 16]))]);
+
+        func.build_return_var("r")?;
+
+        assert_eq!(
+            func.generated,
+            vec![
+                Store(Register::RCX, abi.stack(-8)), // hidden pointer
+                Store(Register::RDX, abi.stack(-16)),
+                Load(Register::RAX, abi.stack(-8)),
+                Load(Register::R11, abi.stack(-32)),
+                Store(Register::R11, abi.ptr(Register::RAX, 0)),
+                Load(Register::R11, abi.stack(-24)),
+                Store(Register::R11, abi.ptr(Register::RAX, 8)),
+                Ret,
+            ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn call_sret() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+
+        let mut builder = Builder::new();
+        let mut func = IrFunctionBuilder::new("test", &mut builder, &abi);
+
+        func.efuncs(vec![("big", vec![Type::u64(0)]), ("pair", vec![])]);
+        func.vars(vec![("r", Type::Bytes(vec![0; 24])), ("p", Type::Bytes(vec![0; 16]))]);
+
+        func.build_call_ret("big", vec![Type::u64(1)], "r")?;
+        func.build_call_ret("pair", vec![], "p")?;
+
+        assert_eq!(
+            func.generated,
+            vec![
+                MovVal(Register::RSI, 1),
+                Lea(Register::RDI, abi.stack(-24)),
+                Call("big".into()),
+                Call("pair".into()),
+                Store(Register::RAX, abi.stack(-40)),
+                Store(Register::RDX, abi.stack(-32)),
+            ]);
+
+        Ok(())
+    }
+}
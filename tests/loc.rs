@@ -0,0 +1,82 @@
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use CodeGenLib::{
+        attrs::FuncAttrs, error::CodeGenLibError, ir::{loc::with_locs, AsmInstructionEnum::*, IrFunctionBuilder, SourceLoc},
+        opt::frame_size, resolve, target::{linux::LinuxAbi, Abi}, BinFormat, Builder, optimize, IR::Register,
+    };
+
+    #[test]
+    fn set_loc() -> Result<(), Box<dyn Error>> {
+        let mut builder = Builder::new();
+        let mut func = IrFunctionBuilder::new("test", &mut builder, &Abi::linux());
+
+        func.set_loc(SourceLoc::new(0, 1, 1));
+        func.build_return_int(1)?;
+        func.set_loc(SourceLoc::new(0, 1, 1)); // same location
+        func.set_loc(SourceLoc::new(0, 2, 5));
+        func.build_return_int(2)?;
+
+        assert_eq!(func.generated, vec![
+            Loc(SourceLoc::new(0, 1, 1)),
+            MovVal(Register::RAX, 1),
+            Ret,
+            Loc(SourceLoc::new(0, 2, 5)),
+            MovVal(Register::RAX, 2),
+            Ret,
+        ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn locations_survive_optimize() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+
+        let code = with_locs(vec![
+            (SourceLoc::new(0, 1, 1), MovVal(Register::RBX, 1)),
+            (SourceLoc::new(0, 1, 1), AddVal(Register::RBX, 1)),
+            (SourceLoc::new(0, 2, 1), Ret),
+        ]);
+
+        let frame = frame_size(&code, None, &abi);
+        let ir = optimize(code, &abi, frame, &FuncAttrs::default())?;
+        let resolved = resolve(vec![], vec![], &ir)?;
+
+        let locs: Vec<u32> = resolved.locs.iter().map(|loc| loc.1.line).collect();
+        assert_eq!(locs, vec![1, 2]);
+
+        // line 1 starts after the prologue, line 2 is the epilogue
+        let offsets: Vec<usize> = resolved.locs.iter().map(|loc| loc.0).collect();
+        let start = ir.iter().position(|instr| *instr == MovVal(Register::RBX, 1)).unwrap();
+        let epilogue = ir.iter().position(|instr| *instr == Loc(SourceLoc::new(0, 2, 1))).unwrap();
+
+        assert_eq!(offsets, vec![resolved.offsets[start], resolved.offsets[epilogue]]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn violation_loc() -> Result<(), Box<dyn Error>> {
+        let mut builder = Builder::new();
+
+        builder.define_located("func", true, vec![
+            (SourceLoc::new(0, 7, 3), MovVal(Register::RAX, 1)),
+            (SourceLoc::new(0, 8, 3), Call("missing".into())),
+            (SourceLoc::new(0, 9, 3), Ret),
+        ])?;
+
+        let err = builder.write("tmp/loc.o", BinFormat::Elf).unwrap_err();
+
+        match err.downcast_ref::<CodeGenLibError>() {
+            Some(CodeGenLibError::InvalidIr(violations)) => {
+                assert_eq!(violations[0].loc, Some(SourceLoc::new(0, 8, 3)));
+                assert!(violations[0].to_string().contains("at 0:8:3"));
+            },
+            _ => panic!("expected invalid ir, got {err}"),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,280 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use iced_x86::MemoryOperand;
+    use CodeGenLib::{attrs::FuncAttrs, ir::{AsmInstructionEnum::{self, *}, IrFunctionBuilder, Type}, opt::{frame_size, lower_frame}, target::{linux::LinuxAbi, windows::WindowsAbi, Abi}, Builder, optimize, Optimize, IR::Register};
+
+    #[test]
+    fn frame_from_vars() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+
+        let mut builder = Builder::new();
+        let mut func = IrFunctionBuilder::new("test", &mut builder, &abi);
+
+        let names = ["a", "b", "c", "d", "e", "f", "g", "h", "i"];
+
+        func.vars(names.iter().map(|name| (*name, Type::u64(0))).collect());
+        func.build_return_var("i")?;
+
+        // 9 * 8 bytes aligned to 16 bytes
+        assert_eq!(frame_size(&func.generated, None, &abi), 80);
+
+        // unused variables also get space
+        let vars = vec![("unused".into(), -100, Type::u32(0))];
+        assert_eq!(frame_size(&func.generated, Some(&vars), &abi), 112);
+
+        let attrs = FuncAttrs { keep_frame_pointer: true, ..Default::default() };
+        let code = optimize(func.generated, &abi, 80, &attrs)?;
+
+        assert_eq!(code[2], SubVal(Register::RSP, 80));
+        assert_eq!(code[code.len() - 3], AddVal(Register::RSP, 80));
+
+        Ok(())
+    }
+
+    #[test]
+    fn no_frame() -> Result<(), Box<dyn Error>> {
+        let code = vec![MovVal(Register::RAX, 5), Ret];
+
+        // a leaf function doesn't need rbp
+        assert_eq!(optimize(code.to_owned(), &Abi::linux(), 0, &FuncAttrs::default())?, code);
+
+        let attrs = FuncAttrs { keep_frame_pointer: true, ..Default::default() };
+
+        assert_eq!(
+            optimize(code, &Abi::linux(), 0, &attrs)?,
+            vec![
+                Push(Register::RBP),
+                MovReg(Register::RBP, Register::RSP),
+                MovVal(Register::RAX, 5),
+                Pop(Register::RBP),
+                Ret,
+            ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn inc_before_ret() -> Result<(), Box<dyn Error>> {
+        let code = optimize(vec![AddVal(Register::RAX, 1), Ret], &Abi::linux(), 0, &FuncAttrs::default())?;
+
+        assert_eq!(code, vec![Inc(Register::RAX), Ret]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn leaf() -> Result<(), Box<dyn Error>> {
+        let rsp = |displ| MemoryOperand::new(Register::RSP, Register::None, 1, displ, 1, false, Register::None);
+
+        let linux = Abi::linux();
+        let code = vec![Store(Register::RDI, linux.stack(-8)), Load(Register::RAX, linux.stack(-8)), Ret];
+
+        // the slot is in the red zone (rbp would be 8 bytes below the return address)
+        assert_eq!(lower_frame(code.to_owned(), &linux, 16, &FuncAttrs::default())?, vec![
+            Store(Register::RDI, rsp(-16)),
+            Load(Register::RAX, rsp(-16)),
+            Ret,
+        ]);
+
+        // windows has no red zone and larger frames don't fit into it
+        let windows = Abi::windows();
+
+        assert_eq!(lower_frame(code.to_owned(), &windows, 16, &FuncAttrs::default())?, vec![
+            SubVal(Register::RSP, 24),
+            Store(Register::RDI, rsp(8)),
+            Load(Register::RAX, rsp(8)),
+            AddVal(Register::RSP, 24),
+            Ret,
+        ]);
+        assert_eq!(lower_frame(code.to_owned(), &linux, 256, &FuncAttrs::default())?[0], SubVal(Register::RSP, 264));
+
+        // stack arguments are above the return address
+        assert_eq!(lower_frame(vec![Load(Register::RAX, linux.stack(16)), Ret], &linux, 0, &FuncAttrs::default())?[0], Load(Register::RAX, rsp(16)));
+
+        // calls and pushes need a frame
+        for instr in [Call("other".into()), Push(Register::RAX), MovReg(Register::RAX, Register::RBP)] {
+            assert_eq!(lower_frame(vec![instr, Ret], &linux, 0, &FuncAttrs::default())?[0], Push(Register::RBP));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn host_defaults() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::host();
+        let code = vec![Store(Register::RDI, abi.stack(-8)), Load(Register::RAX, abi.stack(-8)), Ret];
+
+        // the old entry point uses the host abi, the slots of the code and the default attributes
+        assert_eq!(Optimize(code.to_owned())?, optimize(code, &abi, 16, &FuncAttrs::default())?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn stack_probe() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::windows();
+
+        let code = optimize(vec![Ret], &abi, 8192, &FuncAttrs::default())?;
+
+        assert_eq!(code[1], MovVal(Register::RAX, 8192));
+        assert_eq!(code[2], Call("__chkstk".into()));
+        assert_eq!(code[3], SubReg(Register::RSP, Register::RAX));
+
+        // rbp gets set after the allocation, at most 240 bytes above it
+        assert_eq!(code[4], Lea(Register::RBP, abi.ptr(Register::RSP, 240)));
+
+        // linux doesn't need probing
+        assert_eq!(optimize(vec![Ret], &Abi::linux(), 8192, &FuncAttrs::default())?[2], SubVal(Register::RSP, 8192));
+
+        Ok(())
+    }
+
+    #[test]
+    fn callee_saved() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+
+        let attrs = FuncAttrs { keep_frame_pointer: true, ..Default::default() };
+        let code = optimize(vec![MovVal(Register::EBX, 1), MovReg(Register::R12, Register::RAX), MovVal(Register::RCX, 1), Ret], &abi, 16, &attrs)?;
+
+        assert_eq!(
+            code,
+            vec![
+                Push(Register::RBP),
+                MovReg(Register::RBP, Register::RSP),
+                SubVal(Register::RSP, 32),
+                Store(Register::RBX, abi.stack(-24)),
+                Store(Register::R12, abi.stack(-32)),
+                MovVal(Register::EBX, 1),
+                MovReg(Register::R12, Register::RAX),
+                MovVal(Register::RCX, 1),
+                Load(Register::RBX, abi.stack(-24)),
+                Load(Register::R12, abi.stack(-32)),
+                AddVal(Register::RSP, 32),
+                Pop(Register::RBP),
+                Ret,
+            ]);
+
+        Ok(())
+    }
+
+    /// Returns 1 if rdi isn't zero and 2 otherwise (with a return inside of the if)
+    fn early_return() -> Vec<AsmInstructionEnum> {
+        vec![
+            MovVal(Register::R12, 1),
+            CmpVal(Register::RDI, 0),
+            Je("zero".into()),
+            MovReg(Register::RAX, Register::R12),
+            Ret,
+            Label("zero".into()),
+            MovVal(Register::RAX, 2),
+            Ret,
+        ]
+    }
+
+    #[test]
+    fn returns() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+        let attrs = FuncAttrs { keep_frame_pointer: true, ..Default::default() };
+
+        let epilogue = [Load(Register::R12, abi.stack(-8)), AddVal(Register::RSP, 16), Pop(Register::RBP), Ret];
+
+        // every return restores the frame
+        let code = lower_frame(early_return(), &abi, 0, &attrs)?;
+
+        assert_eq!(code[8..12], epilogue);
+        assert_eq!(code[14..], epilogue);
+
+        // or they jump to a single epilogue
+        let attrs = FuncAttrs { shared_epilogue: true, ..attrs };
+
+        assert_eq!(lower_frame(early_return(), &abi, 0, &attrs)?[4..], [
+            &[
+                MovVal(Register::R12, 1),
+                CmpVal(Register::RDI, 0),
+                Je("zero".into()),
+                MovReg(Register::RAX, Register::R12),
+                Jmp(".epilogue".into()),
+                Label("zero".into()),
+                MovVal(Register::RAX, 2),
+                Label(".epilogue".into()),
+            ][..],
+            &epilogue[..],
+        ].concat());
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn early_returns() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+
+        for shared_epilogue in [false, true] {
+            for keep_frame_pointer in [false, true] {
+                let attrs = FuncAttrs { shared_epilogue, keep_frame_pointer, ..Default::default() };
+                let code = CodeGenLib::resolve(vec!["early".into()], vec![], &lower_frame(early_return(), &abi, 0, &attrs)?)?;
+
+                let func = super::common::executable(&code.code);
+
+                for (arg, expected) in [(0u64, 2u64), (5, 1)] {
+                    let mut r12: u64 = 12;
+                    let result: u64;
+
+                    unsafe {
+                        std::arch::asm!(
+                            "call {func}",
+                            func = in(reg) func,
+                            in("rdi") arg,
+                            inout("r12") r12,
+                            lateout("rax") result,
+                            clobber_abi("sysv64"),
+                        );
+                    }
+
+                    assert_eq!((result, r12), (expected, 12), "{attrs:?}");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn callee_saved_restored() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+
+        let code = super::common::compile("clobber", vec![
+            MovVal(Register::R12, 1),
+            MovVal(Register::R13, 2),
+            MovVal(Register::R14, 3),
+            MovVal(Register::R15, 4),
+            MovVal(Register::RAX, 0),
+            Ret,
+        ], &abi)?;
+
+        let func = super::common::executable(&code);
+
+        let mut regs: (u64, u64, u64, u64) = (12, 13, 14, 15);
+
+        unsafe {
+            std::arch::asm!(
+                "call {func}",
+                func = in(reg) func,
+                inout("r12") regs.0,
+                inout("r13") regs.1,
+                inout("r14") regs.2,
+                inout("r15") regs.3,
+                clobber_abi("sysv64"),
+            );
+        }
+
+        assert_eq!(regs, (12, 13, 14, 15));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+mod common;
+
+#[cfg(all(test, target_os = "linux", target_arch = "x86_64"))]
+mod tests {
+    use std::error::Error;
+
+    use CodeGenLib::{ir::{IrFunctionBuilder, Type}, ssa::CmpOp, target::{linux::LinuxAbi, windows::WindowsAbi, Abi}, Builder};
+
+    use super::common::{compile, compile_linked, executable};
+
+    const NAMES: [&str; 8] = ["a", "b", "c", "d", "e", "f", "g", "h"];
+
+    /// Builds a function with 8 arguments which returns the argument `nr`
+    fn return_arg(abi: &Abi, nr: usize, typ: Type) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut builder = Builder::new();
+        let mut func = IrFunctionBuilder::new("arg", &mut builder, abi);
+
+        func.args(NAMES.iter().map(|name| (*name, typ.clone())).collect());
+        func.vars(vec![]);
+
+        func.build_return_var(NAMES[nr])?;
+
+        compile("arg", func.generated, abi)
+    }
+
+    #[test]
+    fn sysv_8_args() -> Result<(), Box<dyn Error>> {
+        for nr in 0..8 {
+            let code = return_arg(&Abi::linux(), nr, Type::u64(0))?;
+
+            let func: extern "sysv64" fn(u64, u64, u64, u64, u64, u64, u64, u64) -> u64 =
+                unsafe { std::mem::transmute(executable(&code)) };
+
+            assert_eq!(func(11, 22, 33, 44, 55, 66, 77, 88), (nr as u64 + 1) * 11);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn sysv_8_args_32bit() -> Result<(), Box<dyn Error>> {
+        for nr in 0..8 {
+            let code = return_arg(&Abi::linux(), nr, Type::u32(0))?;
+
+            let func: extern "sysv64" fn(u32, u32, u32, u32, u32, u32, u32, u32) -> u32 =
+                unsafe { std::mem::transmute(executable(&code)) };
+
+            assert_eq!(func(11, 22, 33, 44, 55, 66, 77, 88), (nr as u32 + 1) * 11);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn win64_8_args() -> Result<(), Box<dyn Error>> {
+        for nr in 0..8 {
+            let code = return_arg(&Abi::windows(), nr, Type::u64(0))?;
+
+            let func: extern "win64" fn(u64, u64, u64, u64, u64, u64, u64, u64) -> u64 =
+                unsafe { std::mem::transmute(executable(&code)) };
+
+            assert_eq!(func(11, 22, 33, 44, 55, 66, 77, 88), (nr as u64 + 1) * 11);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn sysv_u32_stack_arg() -> Result<(), Box<dyn Error>> {
+        // last(a, ..., g) = a == 0 ? g : last(0, ..., 0, u32::MAX) (g is passed on the stack)
+        let abi = Abi::linux();
+        let mut builder = Builder::new();
+        let mut func = IrFunctionBuilder::new("last", &mut builder, &abi);
+
+        func.args(NAMES[..7].iter().map(|name| (*name, Type::u32(0))).collect());
+        func.vars(vec![("zero", Type::u32(0)), ("result", Type::u32(0))]);
+        func.efuncs(vec![("last", vec![Type::u32(0); 7])]);
+
+        func.build_set("zero", Type::u32(0))?;
+        func.build_branch("a", CmpOp::Eq, "zero", "done")?;
+
+        func.build_call_ret("last", [vec![Type::u32(0); 6], vec![Type::u32(u32::MAX)]].concat(), "result")?;
+        func.build_return_var("result")?;
+
+        func.build_label("done");
+        func.build_return_var("g")?;
+
+        let code = compile_linked("last", func.generated, &mut func.builder, &abi)?;
+
+        let func: extern "sysv64" fn(u32, u32, u32, u32, u32, u32, u32) -> u32 =
+            unsafe { std::mem::transmute(executable(&code)) };
+
+        assert_eq!(func(0, 0, 0, 0, 0, 0, 7), 7);
+        assert_eq!(func(1, 0, 0, 0, 0, 0, 7), u32::MAX);
+
+        Ok(())
+    }
+
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Pair(u64, u64);
+
+    #[test]
+    fn win64_bytes_by_ref() -> Result<(), Box<dyn Error>> {
+        // pick(data, depth) = depth == 0 ? data : pick(depth == 1 ? data : [7; 16], 0)
+        let abi = Abi::windows();
+        let mut builder = Builder::new();
+        let mut func = IrFunctionBuilder::new("pick", &mut builder, &abi);
+
+        func.args(vec![("data", Type::Bytes(vec![0; 16])), ("depth", Type::u64(0))]);
+        func.set_ret(Type::Bytes(vec![0; 16]));
+        func.vars(vec![("zero", Type::u64(0)), ("one", Type::u64(0)), ("result", Type::Bytes(vec![0; 16]))]);
+        func.efuncs(vec![("pick", vec![Type::Bytes(vec![0; 16]), Type::u64(0)])]);
+
+        func.build_set("zero", Type::u64(0))?;
+        func.build_set("one", Type::u64(1))?;
+        func.build_branch("depth", CmpOp::Eq, "zero", "done")?;
+        func.build_branch("depth", CmpOp::Eq, "one", "pass")?;
+
+        func.build_call_ret("pick", vec![Type::Bytes(vec![7; 16]), Type::u64(0)], "result")?;
+        func.build_return_var("result")?;
+
+        func.build_label("pass");
+        func.build_call_ret("pick", vec![Type::InVar("data".into()), Type::u64(0)], "result")?;
+        func.build_return_var("result")?;
+
+        func.build_label("done");
+        func.build_return_var("data")?;
+
+        let code = compile_linked("pick", func.generated, &mut func.builder, &abi)?;
+
+        let func: extern "win64" fn(Pair, u64) -> Pair = unsafe { std::mem::transmute(executable(&code)) };
+
+        assert_eq!(func(Pair(1, 2), 0), Pair(1, 2));
+        assert_eq!(func(Pair(1, 2), 1), Pair(1, 2));
+        assert_eq!(func(Pair(1, 2), 2), Pair(0x0707070707070707, 0x0707070707070707));
+
+        Ok(())
+    }
+
+    #[test]
+    fn win64_bytes_by_value() -> Result<(), Box<dyn Error>> {
+        // the 4 byte aggregate is passed in ecx, the 16 byte one by reference in rdx
+        let abi = Abi::windows();
+        let mut builder = Builder::new();
+        let mut func = IrFunctionBuilder::new("first", &mut builder, &abi);
+
+        func.args(vec![("a", Type::Bytes(vec![0; 4])), ("b", Type::Bytes(vec![0; 16]))]);
+        func.set_ret(Type::Bytes(vec![0; 4]));
+        func.vars(vec![("copy", Type::Bytes(vec![0; 16]))]);
+
+        func.build_set("copy", Type::InVar("b".into()))?;
+        func.build_set("b", Type::Bytes(vec![0; 16]))?; // changes the copy of the caller
+        func.build_return_var("a")?;
+
+        let code = compile("first", func.generated, &abi)?;
+
+        let func: extern "win64" fn([u8; 4], Pair) -> [u8; 4] = unsafe { std::mem::transmute(executable(&code)) };
+
+        assert_eq!(func([1, 2, 3, 4], Pair(5, 6)), [1, 2, 3, 4]);
+
+        Ok(())
+    }
+}
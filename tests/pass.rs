@@ -0,0 +1,103 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use CodeGenLib::{
+        attrs::FuncAttrs, error::CodeGenLibError, ir::{AsmInstructionEnum::{self, *}, IrBuilder}, opt::{OptLevel, Pass, PassContext, PassManager},
+        target::{linux::LinuxAbi, Abi, Target}, BinFormat, Builder, IR::Register,
+    };
+
+    fn run(passes: &PassManager, code: Vec<AsmInstructionEnum>) -> Result<Vec<AsmInstructionEnum>, Box<dyn Error>> {
+        passes.run(code, &PassContext { name: "test", abi: &Abi::linux(), attrs: &FuncAttrs::default(), stack_args: 0, remarks: Default::default() })
+    }
+
+    #[test]
+    fn levels() -> Result<(), Box<dyn Error>> {
+        let code = vec![AddVal(Register::RAX, 1), Nop, AddVal(Register::RAX, -1), Ret];
+
+        assert_eq!(run(&PassManager::new(OptLevel::O0), code.to_owned())?, code);
+        assert_eq!(run(&PassManager::new(OptLevel::O1), code)?, vec![Inc(Register::RAX), Dec(Register::RAX), Ret]);
+
+        assert_eq!(PassManager::default().names(), PassManager::new(OptLevel::O1).names());
+
+        Ok(())
+    }
+
+    #[test]
+    fn fixpoint() -> Result<(), Box<dyn Error>> {
+        let slot = Abi::linux().stack(-8);
+
+        // the nop is removed after the peephole pass, which then forwards the store, whose slot is never read again
+        let code = vec![Store(Register::RAX, slot), Nop, Load(Register::RAX, slot), Ret];
+
+        assert_eq!(run(&PassManager::new(OptLevel::O1), code.to_owned())?, vec![Store(Register::RAX, slot), Load(Register::RAX, slot), Ret]);
+        assert_eq!(run(&PassManager::new(OptLevel::O2), code)?, vec![Ret]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn custom() -> Result<(), Box<dyn Error>> {
+        let mut passes = PassManager::new(OptLevel::O1);
+
+        // the result depends on where the pass runs relative to peephole and remove-nops
+        let nop_to_add = || Pass::new("nop-to-add", |code, _| {
+            Ok(code.into_iter().map(|instr| if instr == Nop { AddVal(Register::RAX, 1) } else { instr }).collect())
+        });
+
+        passes.add(nop_to_add());
+        assert_eq!(run(&passes, vec![Nop, Ret])?, vec![Ret]);
+
+        passes.remove("nop-to-add")?;
+        passes.insert_before("peephole", nop_to_add())?;
+        assert_eq!(run(&passes, vec![Nop, Ret])?, vec![Inc(Register::RAX), Ret]);
+
+        passes.remove("nop-to-add")?;
+        passes.insert_after("peephole", nop_to_add())?;
+        assert_eq!(passes.names(), vec!["dce", "strength-reduction", "peephole", "nop-to-add", "dse", "remove-nops", "tail-calls"]);
+        assert_eq!(run(&passes, vec![Nop, Ret])?, vec![AddVal(Register::RAX, 1), Ret]);
+
+        assert!(matches!(passes.remove("unknown"), Err(CodeGenLibError::PassNotExist(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn naked() -> Result<(), Box<dyn Error>> {
+        let attrs = FuncAttrs { naked: true, ..Default::default() };
+        let code = vec![Nop, Ret];
+
+        assert_eq!(PassManager::default().run(code.to_owned(), &PassContext { name: "test", abi: &Abi::linux(), attrs: &attrs, stack_args: 0, remarks: Default::default() })?, code);
+
+        Ok(())
+    }
+
+    #[test]
+    fn builders() -> Result<(), Box<dyn Error>> {
+        let mut builder = Builder::new();
+        builder.set_opt_level(OptLevel::O0);
+        builder.passes.add(Pass::new("fail", |_, ctx| Err(format!("{} failed", ctx.name).into())));
+
+        builder.define("func", true, vec![Nop, Ret])?;
+
+        assert_eq!(builder.write("tmp/pass.o", BinFormat::Elf).unwrap_err().to_string(), "func failed");
+
+        let mut passes = PassManager::new(OptLevel::Os);
+        passes.add(Pass::new("fail", |_, ctx| Err(format!("{} failed", ctx.name).into())));
+
+        let mut builder = IrBuilder::new(Target::host());
+        builder.set_passes(passes);
+        let func = builder.add("ret");
+        func.build_return_int(0)?;
+        func.set_public();
+
+        assert_eq!(builder.write("tmp/pass_ir.o").unwrap_err().to_string(), "ret failed");
+
+        builder.set_opt_level(OptLevel::O2);
+        builder.write("tmp/pass_ir.o")?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use iced_x86::MemoryOperand;
+    use CodeGenLib::{
+        ir::{AsmInstructionEnum::{self, *}, SourceLoc}, opt::peephole::{apply, carry_live, flags_live, RULES},
+        target::{linux::LinuxAbi, Abi}, IR::Register,
+    };
+
+    fn peephole(code: Vec<AsmInstructionEnum>) -> Vec<AsmInstructionEnum> {
+        apply(code, RULES)
+    }
+
+    #[test]
+    fn store_load() {
+        let abi = Abi::linux();
+        let slot = abi.stack(-8);
+
+        assert_eq!(peephole(vec![Store(Register::RAX, slot), Load(Register::RAX, slot), Ret]), vec![Store(Register::RAX, slot), Ret]);
+        assert_eq!(
+            peephole(vec![Store(Register::RAX, slot), Load(Register::RCX, slot), Ret]),
+            vec![Store(Register::RAX, slot), MovReg(Register::RCX, Register::RAX), Ret],
+        );
+
+        // locations don't break the window
+        let loc = Loc(SourceLoc { file: 0, line: 2, column: 1 });
+        assert_eq!(
+            peephole(vec![Store(Register::RAX, slot), loc.to_owned(), Load(Register::RAX, slot), Ret]),
+            vec![Store(Register::RAX, slot), loc, Ret],
+        );
+
+        // different slots, sizes or a label in between
+        let code = vec![Store(Register::RAX, slot), Load(Register::RAX, abi.stack(-16)), Ret];
+        assert_eq!(peephole(code.to_owned()), code);
+
+        let code = vec![Store(Register::RAX, slot), Load(Register::ECX, slot), Ret];
+        assert_eq!(peephole(code.to_owned()), code);
+
+        let code = vec![Store(Register::RAX, slot), Label("l".into()), Load(Register::RAX, slot), Ret];
+        assert_eq!(peephole(code.to_owned()), code);
+    }
+
+    #[test]
+    fn load_store() {
+        let abi = Abi::linux();
+        let slot = abi.stack(-8);
+
+        assert_eq!(peephole(vec![Load(Register::RBX, slot), Store(Register::RBX, slot), Ret]), vec![Load(Register::RBX, slot), Ret]);
+
+        let code = vec![Load(Register::RBX, slot), Store(Register::RCX, slot), Ret];
+        assert_eq!(peephole(code.to_owned()), code);
+
+        // the load changes the address of the store
+        let code = vec![Load(Register::RBX, abi.ptr(Register::RBX, 8)), Store(Register::RBX, abi.ptr(Register::RBX, 8)), Ret];
+        assert_eq!(peephole(code.to_owned()), code);
+
+        let indexed = MemoryOperand::new(Register::RAX, Register::RCX, 8, 0, 0, false, Register::None);
+        let code = vec![Load(Register::ECX, indexed), Store(Register::ECX, indexed), Ret];
+        assert_eq!(peephole(code.to_owned()), code);
+    }
+
+    #[test]
+    fn self_move() {
+        assert_eq!(peephole(vec![MovReg(Register::RAX, Register::RAX), Ret]), vec![Ret]);
+
+        // clears the upper half of rax
+        let code = vec![MovReg(Register::EAX, Register::EAX), Ret];
+        assert_eq!(peephole(code.to_owned()), code);
+    }
+
+    #[test]
+    fn move_back() {
+        assert_eq!(
+            peephole(vec![MovReg(Register::RAX, Register::RBX), MovReg(Register::RBX, Register::RAX), Ret]),
+            vec![MovReg(Register::RAX, Register::RBX), Ret],
+        );
+
+        let code = vec![MovReg(Register::RAX, Register::RBX), MovReg(Register::RCX, Register::RAX), Ret];
+        assert_eq!(peephole(code.to_owned()), code);
+    }
+
+    #[test]
+    fn identity() {
+        assert_eq!(peephole(vec![AddVal(Register::RAX, 0), SubVal(Register::RBX, 0), MulVal(Register::RCX, 1), Ret]), vec![Ret]);
+
+        // the comparison reads the flags of the addition
+        let code = vec![AddVal(Register::RAX, 0), Je("zero".into()), Ret, Label("zero".into()), Ret];
+        assert_eq!(peephole(code.to_owned()), code);
+
+        let code = vec![AddVal(Register::EAX, 0), Ret];
+        assert_eq!(peephole(code.to_owned()), code);
+    }
+
+    #[test]
+    fn xor_zero() {
+        assert_eq!(peephole(vec![MovVal(Register::RAX, 0), Ret]), vec![XorReg(Register::EAX, Register::EAX), Ret]);
+        assert_eq!(peephole(vec![MovVal(Register::R9D, 0), Ret]), vec![XorReg(Register::R9D, Register::R9D), Ret]);
+
+        // the mov is between the comparison and the jump
+        let code = vec![CmpVal(Register::RBX, 3), MovVal(Register::RAX, 0), Jl("less".into()), Ret, Label("less".into()), Ret];
+        assert_eq!(peephole(code.to_owned()), code);
+
+        let code = vec![MovVal(Register::AL, 0), Ret];
+        assert_eq!(peephole(code.to_owned()), code);
+    }
+
+    #[test]
+    fn inc_dec() {
+        assert_eq!(
+            peephole(vec![AddVal(Register::RAX, 1), SubVal(Register::RBX, 1), AddVal(Register::ECX, -1), Ret]),
+            vec![Inc(Register::RAX), Dec(Register::RBX), Dec(Register::ECX), Ret],
+        );
+
+        // inc doesn't set the carry flag which jb reads
+        let code = vec![AddVal(Register::RAX, 1), Jb("overflow".into()), Ret, Label("overflow".into()), Ret];
+        assert_eq!(peephole(code.to_owned()), code);
+
+        // je doesn't read the carry flag, but its target could
+        let code = vec![AddVal(Register::RAX, 1), Je("zero".into()), Ret, Label("zero".into()), Ret];
+        assert_eq!(peephole(code.to_owned()), code);
+
+        // the comparison overwrites the flags of the addition
+        assert_eq!(
+            peephole(vec![AddVal(Register::RAX, 1), CmpVal(Register::RAX, 5), Jb("less".into()), Ret, Label("less".into()), Ret])[0],
+            Inc(Register::RAX),
+        );
+    }
+
+    #[test]
+    fn liveness() {
+        assert!(flags_live(&[Nop, Je("l".into())]));
+        assert!(!flags_live(&[CmpVal(Register::RAX, 1), Je("l".into())]));
+        assert!(!flags_live(&[Call("f".into()), Je("l".into())]));
+        assert!(flags_live(&[Label("l".into())]));
+        assert!(!flags_live(&[]));
+
+        assert!(!carry_live(&[Inc(Register::RAX), Ret]));
+        assert!(carry_live(&[Inc(Register::RAX), Ja("l".into())]));
+    }
+}
+
+#[cfg(all(test, target_os = "linux", target_arch = "x86_64"))]
+mod jit_tests {
+    use std::error::Error;
+
+    use CodeGenLib::{ir::AsmInstructionEnum::*, target::{linux::LinuxAbi, Abi}, Builder, IR::Register};
+
+    use super::common::{compile_linked, executable};
+
+    #[test]
+    fn rewritten() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+        let slot = abi.stack(-8);
+
+        // most of the instructions get rewritten
+        let code = compile_linked("func", vec![
+            MovReg(Register::RAX, Register::RDI),
+            Store(Register::RAX, slot),
+            Load(Register::RCX, slot),
+            Store(Register::RCX, slot),
+            MovReg(Register::RDX, Register::RCX),
+            MovReg(Register::RCX, Register::RDX),
+            MovReg(Register::RDX, Register::RDX),
+            AddVal(Register::RDX, 0),
+            AddVal(Register::RDX, 1),
+            MovVal(Register::RAX, 0),
+            AddReg(Register::RAX, Register::RDX),
+            Ret,
+        ], &mut Builder::new(), &abi)?;
+
+        let func = unsafe { std::mem::transmute::<*const u8, extern "sysv64" fn(i64) -> i64>(executable(&code)) };
+
+        assert_eq!(func(41), 42);
+        assert_eq!(func(-1), 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use CodeGenLib::{
+        error::CodeGenLibError, ir::{Type, VInstr::*, VReg}, regalloc::{assign, Location}, target::{linux::LinuxAbi, windows::WindowsAbi, Abi},
+        BinFormat, Builder, IR::Register,
+    };
+
+    fn v(id: u32) -> VReg {
+        VReg::new(id, 8)
+    }
+
+    #[test]
+    fn fixed_registers() -> Result<(), CodeGenLibError> {
+        let abi = Abi::linux();
+
+        let code = vec![
+            Arg(v(0), 0),
+            Arg(v(1), 1),
+            Div(v(0), v(1)),
+            Shl(v(0), v(1)),
+            Ret(Some(v(0))),
+        ];
+
+        let locations = assign(&code, &abi, 0)?;
+
+        for reg in [v(0), v(1)] {
+            assert!(![Location::Reg(Register::RAX), Location::Reg(Register::RDX)].contains(&locations[&reg]));
+        }
+        assert_ne!(locations[&v(0)], Location::Reg(Register::RCX));
+
+        Ok(())
+    }
+
+    #[test]
+    fn call_clobbers() -> Result<(), CodeGenLibError> {
+        for abi in [Abi::linux(), Abi::windows()] {
+            let code = vec![
+                Arg(v(0), 0),
+                MovVal(v(1), 1),
+                Call("func".into(), vec![v(1)], Some(v(2))),
+                Add(v(2), v(0)),
+                Ret(Some(v(2))),
+            ];
+
+            let locations = assign(&code, &abi, 0)?;
+
+            // v0 lives across the call
+            match locations[&v(0)] {
+                Location::Reg(reg) => assert!(abi.callee_saved.contains(&reg), "{reg:?} is clobbered by the call"),
+                Location::Stack(_) => {},
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn abi_pools() -> Result<(), CodeGenLibError> {
+        // 6 values are live at once, windows has only 5 caller saved registers without the scratch registers
+        for (abi, callee_saved) in [(Abi::linux(), 0), (Abi::windows(), 1)] {
+            let mut code = vec![];
+
+            for id in 0..6 {
+                code.push(MovVal(v(id), id as i64));
+            }
+            for id in 1..6 {
+                code.push(Add(v(0), v(id)));
+            }
+            code.push(Ret(Some(v(0))));
+
+            let locations = assign(&code, &abi, 0)?;
+            let regs: Vec<Register> = locations.values().filter_map(|loc| match loc {
+                Location::Reg(reg) => Some(*reg),
+                _ => None,
+            }).collect();
+
+            assert_eq!(regs.len(), 6);
+            assert_eq!(regs.iter().filter(|reg| abi.callee_saved.contains(reg)).count(), callee_saved);
+            assert!(regs.iter().all(|reg| ![Register::R10, Register::R11].contains(reg)));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn spills() -> Result<(), CodeGenLibError> {
+        let abi = Abi::linux();
+
+        let mut code = vec![];
+
+        for id in 0..20 {
+            code.push(MovVal(v(id), id as i64));
+        }
+        for id in 1..20 {
+            code.push(Add(v(0), v(id)));
+        }
+        code.push(Ret(Some(v(0))));
+
+        let locations = assign(&code, &abi, 16)?;
+        let slots: Vec<i64> = locations.values().filter_map(|loc| match loc {
+            Location::Stack(displ) => Some(*displ),
+            _ => None,
+        }).collect();
+
+        assert_eq!(slots.len(), 20 - 12);
+        assert!(slots.iter().all(|slot| *slot <= -24), "the spill slots overlap the variables: {slots:?}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn define_virtual() -> Result<(), Box<dyn std::error::Error>> {
+        let mut builder = Builder::new();
+
+        builder.define_vars("func", vec![("x".into(), -8, Type::u64(0))]);
+        builder.define_virtual("func", true, vec![
+            Arg(v(0), 0),
+            Load(v(1), builder.abi.stack(-8)),
+            Add(v(0), v(1)),
+            Ret(Some(v(0))),
+        ])?;
+
+        // the allocated code passes the verifier
+        builder.write("tmp/regalloc.o", BinFormat::Elf)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn mismatch() {
+        let code = vec![MovVal(VReg::new(0, 8), 1), Mov(VReg::new(1, 4), VReg::new(0, 4))];
+
+        assert!(matches!(assign(&code, &Abi::linux(), 0), Err(CodeGenLibError::TypeMismatch(_))));
+
+        let code = vec![MovVal(v(0), 1), Arg(v(1), 0)];
+
+        assert!(matches!(assign(&code, &Abi::linux(), 0), Err(CodeGenLibError::UnsuportedArg(_))));
+    }
+}
+
+#[cfg(all(test, target_os = "linux", target_arch = "x86_64"))]
+mod jit_tests {
+    use std::error::Error;
+
+    use CodeGenLib::{
+        ir::{AsmInstructionEnum, VInstr::{self, *}, VReg}, regalloc::allocate, target::{linux::LinuxAbi, Abi}, Builder,
+    };
+
+    use super::common::{compile_linked, executable};
+
+    fn v(id: u32) -> VReg {
+        VReg::new(id, 8)
+    }
+
+    fn jit(name: &str, code: Vec<VInstr>) -> Result<extern "sysv64" fn(i64, i64) -> i64, Box<dyn Error>> {
+        let abi = Abi::linux();
+        let code = allocate(&code, &abi, 0)?;
+
+        let code = compile_linked(name, code, &mut Builder::new(), &abi)?;
+
+        Ok(unsafe { std::mem::transmute::<*const u8, extern "sysv64" fn(i64, i64) -> i64>(executable(&code)) })
+    }
+
+    #[test]
+    fn arithmetic() -> Result<(), Box<dyn Error>> {
+        // (((a + b) * 3 - b) / 2 + a % 7) << b >> 1
+        let func = jit("arith", vec![
+            Arg(v(0), 0),
+            Arg(v(1), 1),
+            Mov(v(2), v(0)),
+            Add(v(2), v(1)),
+            MulVal(v(2), 3),
+            Sub(v(2), v(1)),
+            MovVal(v(3), 2),
+            Div(v(2), v(3)),
+            MovVal(v(4), 7),
+            Mov(v(5), v(0)),
+            Rem(v(5), v(4)),
+            Add(v(2), v(5)),
+            Shl(v(2), v(1)),
+            MovVal(v(6), 1),
+            Sar(v(2), v(6)),
+            Ret(Some(v(2))),
+        ])?;
+
+        for (a, b) in [(10, 2), (-9, 3), (100, 0), (5, 1)] {
+            assert_eq!(func(a, b), (((a + b) * 3 - b) / 2 + a % 7) << b >> 1, "a = {a}, b = {b}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn spills() -> Result<(), Box<dyn Error>> {
+        // sums (a + id) for 20 registers which are all live at the same time
+        let mut code = vec![Arg(v(100), 0), Arg(v(101), 1)];
+
+        for id in 0..20 {
+            code.push(Mov(v(id), v(100)));
+            code.push(AddVal(v(id), id as i64));
+        }
+        for id in 1..20 {
+            code.push(Add(v(0), v(id)));
+        }
+        code.push(Add(v(0), v(101)));
+        code.push(Ret(Some(v(0))));
+
+        let func = jit("spills", code)?;
+
+        assert_eq!(func(1, 5), 20 + 190 + 5);
+        assert_eq!(func(-3, 0), -60 + 190);
+
+        Ok(())
+    }
+
+    #[test]
+    fn recursion() -> Result<(), Box<dyn Error>> {
+        // fact(n) = if n <= 1 { 1 } else { n * fact(n - 1) }
+        let func = jit("fact", vec![
+            Arg(v(0), 0),
+            MovVal(v(1), 1),
+            CmpVal(v(0), 2),
+            Asm(AsmInstructionEnum::Jl("done".into())),
+            Mov(v(2), v(0)),
+            SubVal(v(2), 1),
+            Call("fact".into(), vec![v(2), v(2)], Some(v(3))),
+            Mul(v(3), v(0)),
+            Mov(v(1), v(3)),
+            Asm(AsmInstructionEnum::Label("done".into())),
+            Ret(Some(v(1))),
+        ])?;
+
+        assert_eq!(func(1, 0), 1);
+        assert_eq!(func(5, 0), 120);
+        assert_eq!(func(10, 0), 3628800);
+
+        Ok(())
+    }
+}
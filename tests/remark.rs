@@ -0,0 +1,134 @@
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use CodeGenLib::{
+        attrs::FuncAttrs, ir::{AsmInstructionEnum::*, Type}, opt::{lower_frame_remarks, remark::Remark, OptLevel, PassContext, PassManager},
+        target::{linux::LinuxAbi, Abi}, BinFormat, Builder, IR::Register,
+    };
+
+    #[test]
+    fn passes() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+        let ctx = PassContext { name: "test", abi: &abi, attrs: &FuncAttrs::default(), stack_args: 0, remarks: Default::default() };
+
+        let code = vec![
+            Store(Register::RDI, abi.stack(-8)),
+            MovReg(Register::RAX, Register::RDI),
+            MulVal(Register::RAX, 8),
+            Nop,
+            Call("other".into()),
+            Ret,
+            MovVal(Register::RAX, 1),
+        ];
+
+        PassManager::new(OptLevel::O1).run(code, &ctx)?;
+
+        let remark = |pass: &str, message: &str| Remark::new(pass, "test", message.into());
+
+        assert_eq!(ctx.remarks.into_inner(), vec![
+            remark("dce", "removed 1 unreachable instructions"),
+            remark("strength-reduction", "reduced 1 multiplications and divisions by constants"),
+            remark("dse", "eliminated 1 dead stores"),
+            remark("remove-nops", "removed 1 nops"),
+            remark("tail-calls", "turned the call of other into a tail call"),
+        ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn divisions() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+        let ctx = PassContext { name: "test", abi: &abi, attrs: &FuncAttrs::default(), stack_args: 0, remarks: Default::default() };
+
+        // the division by 7 is replaced by a multiplication with its magic number
+        let code = vec![
+            MovReg(Register::RAX, Register::RDI),
+            UDivVal(Register::RAX, 7),
+            DivVal(Register::RAX, 3),
+            Ret,
+        ];
+
+        PassManager::new(OptLevel::O1).run(code, &ctx)?;
+
+        assert!(ctx.remarks.into_inner().contains(
+            &Remark::new("strength-reduction", "test", "reduced 2 multiplications and divisions by constants".into())
+        ));
+
+        Ok(())
+    }
+
+    fn write(level: OptLevel, stats: bool) -> Result<Builder, Box<dyn Error>> {
+        let abi = Abi::linux();
+
+        let mut builder = Builder::new();
+        builder.abi = abi.to_owned();
+        builder.set_opt_level(level);
+        builder.collect_stats = stats;
+
+        builder.define("main", true, vec![
+            Store(Register::RDI, abi.stack(-8)),
+            Store(Register::RDI, abi.stack(-16)),
+            Call("helper".into()),
+            Ret,
+        ])?;
+        builder.define_vars("main", vec![("a".into(), -8, Type::u64(0)), ("b".into(), -16, Type::u64(0))]);
+        builder.define("helper", false, vec![MovVal(Register::RAX, 1), Ret])?;
+
+        builder.write(&format!("tmp/remark_{level:?}.o"), BinFormat::Elf)?;
+
+        Ok(builder)
+    }
+
+    #[test]
+    fn builder() -> Result<(), Box<dyn Error>> {
+        let builder = write(OptLevel::O1, true)?;
+
+        assert_eq!(builder.remarks, vec![
+            Remark::new("inline", "main", "inlined helper into main".into()),
+            Remark::new("remove-unused", "helper", "removed the unused function".into()),
+            Remark::new("dse", "main", "eliminated 2 dead stores".into()),
+            Remark::new("lower-frame", "main", "omitted the frame pointer and placed the 16 byte frame into the red zone".into()),
+        ]);
+
+        // the frame and the stores are gone
+        assert_eq!(builder.stats.len(), 1);
+        assert_eq!((builder.stats[0].instrs_before, builder.stats[0].instrs_after), (9, 2));
+        assert!(builder.stats[0].bytes_after < builder.stats[0].bytes_before);
+
+        let builder = write(OptLevel::O0, true)?;
+
+        assert!(builder.remarks.is_empty());
+        assert!(builder.stats.iter().all(|stats| stats.instrs_before == stats.instrs_after && stats.bytes_before == stats.bytes_after));
+
+        assert!(write(OptLevel::O1, false)?.stats.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn frame() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+
+        let code = vec![
+            Call("other".into()),
+            CmpVal(Register::RAX, 0),
+            Je("zero".into()),
+            Ret,
+            Label("zero".into()),
+            Ret,
+        ];
+
+        let attrs = FuncAttrs { shared_epilogue: true, ..Default::default() };
+        let (_, remarks) = lower_frame_remarks(code, &abi, 0, &attrs, "test")?;
+
+        assert_eq!(remarks, vec![Remark::new("lower-frame", "test", "the 2 returns jump to a shared epilogue".into())]);
+
+        let (_, remarks) = lower_frame_remarks(vec![MovVal(Register::RAX, 1), Ret], &abi, 0, &FuncAttrs::default(), "test")?;
+
+        assert_eq!(remarks, vec![Remark::new("lower-frame", "test", "omitted the frame pointer of the leaf function".into())]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+mod common;
+
+#[cfg(all(test, target_os = "linux", target_arch = "x86_64"))]
+mod tests {
+    use std::error::Error;
+
+    use CodeGenLib::{ir::{IrFunctionBuilder, Type}, target::{linux::LinuxAbi, Abi}, Builder};
+
+    use super::common::{compile, compile_linked, executable};
+
+    #[repr(C)]
+    #[derive(Debug, PartialEq)]
+    struct Pair(u64, u64);
+
+    #[repr(C)]
+    #[derive(Debug, PartialEq)]
+    struct Triple(u64, u64, u64);
+
+    #[test]
+    fn sign_extend() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+        let mut builder = Builder::new();
+        let mut func = IrFunctionBuilder::new("set", &mut builder, &abi);
+
+        func.args(vec![]);
+        func.vars(vec![("x", Type::i32(0)), ("y", Type::i64(0))]);
+
+        func.build_set("x", Type::i32(-5))?;
+        func.build_set("y", Type::InVar("x".into()))?;
+        func.build_return_var("y")?;
+
+        let code = compile("set", func.generated, &abi)?;
+        let func: extern "sysv64" fn() -> i64 = unsafe { std::mem::transmute(executable(&code)) };
+
+        assert_eq!(func(), -5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn bytes_in_registers() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+        let mut builder = Builder::new();
+        let mut func = IrFunctionBuilder::new("set", &mut builder, &abi);
+
+        func.args(vec![]);
+        func.set_ret(Type::Bytes(vec![0; 16]));
+        func.vars(vec![("x", Type::Bytes(vec![0; 16]))]);
+
+        let mut content = 1u64.to_le_bytes().to_vec();
+        content.extend_from_slice(&2u64.to_le_bytes());
+
+        func.build_set("x", Type::Bytes(content))?;
+        func.build_return_var("x")?;
+
+        let code = compile("set", func.generated, &abi)?;
+        let func: extern "sysv64" fn() -> Pair = unsafe { std::mem::transmute(executable(&code)) };
+
+        assert_eq!(func(), Pair(1, 2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn bytes_sret() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+        let mut builder = Builder::new();
+        let mut func = IrFunctionBuilder::new("set", &mut builder, &abi);
+
+        func.args(vec![]);
+        func.set_ret(Type::Bytes(vec![0; 24]));
+        func.vars(vec![("x", Type::Bytes(vec![0; 24])), ("y", Type::Bytes(vec![0; 24]))]);
+
+        // 3, 4 and a odd tail (exercises the 4, 2 and 1 byte parts)
+        let mut content = 3u64.to_le_bytes().to_vec();
+        content.extend_from_slice(&4u64.to_le_bytes());
+        content.extend_from_slice(&[7, 0, 0, 0, 0, 0, 1]);
+
+        func.build_set("x", Type::Bytes(content))?;
+        func.build_set("y", Type::InVar("x".into()))?;
+        func.build_return_var("y")?;
+
+        let code = compile("set", func.generated, &abi)?;
+        let func: extern "sysv64" fn() -> Triple = unsafe { std::mem::transmute(executable(&code)) };
+
+        assert_eq!(func(), Triple(3, 4, 7 | (1 << 48)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn str_and_ptr() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+
+        // returns the variable `name` after setting it to `content`
+        let build = |name: &str, content: Type| -> Result<u64, Box<dyn Error>> {
+            let mut builder = Builder::new();
+            let mut func = IrFunctionBuilder::new("set", &mut builder, &abi);
+
+            func.args(vec![]);
+            func.vars(vec![("s", Type::Str(vec![])), ("p", Type::Ptr(Box::new(Type::u64(0))))]);
+
+            func.build_set(name, content)?;
+            func.build_return_var(name)?;
+
+            let code = compile_linked("set", func.generated, &mut func.builder, &abi)?;
+            let func: extern "sysv64" fn() -> u64 = unsafe { std::mem::transmute(executable(&code)) };
+
+            Ok(func())
+        };
+
+        let s = build("s", Type::Str(b"hi\0".to_vec()))?;
+        assert_eq!(unsafe { std::ffi::CStr::from_ptr(s as *const std::ffi::c_char) }, c"hi");
+
+        let p = build("p", Type::Ptr(Box::new(Type::u64(0x1122334455667788))))?;
+        assert_eq!(unsafe { *(p as *const u64) }, 0x1122334455667788);
+
+        let p = build("p", Type::Ptr(Box::new(Type::i32(-2))))?;
+        assert_eq!(unsafe { *(p as *const i32) }, -2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn mismatch() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+        let mut builder = Builder::new();
+        let mut func = IrFunctionBuilder::new("set", &mut builder, &abi);
+
+        func.args(vec![]);
+        func.vars(vec![("x", Type::u64(0)), ("b", Type::Bytes(vec![0; 8]))]);
+
+        assert!(func.build_set("b", Type::Bytes(vec![0; 9])).is_err());
+        assert!(func.build_set("x", Type::InVar("b".into())).is_err());
+        assert!(func.build_set("x", Type::Unlim(vec![])).is_err());
+
+        Ok(())
+    }
+}
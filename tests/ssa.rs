@@ -0,0 +1,189 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use CodeGenLib::{
+        error::CodeGenLibError, ir::Type, ssa::{isel::lower, BinOp, BlockId, CmpOp, DomTree, Function, Inst, Terminator}, BinFormat, Builder,
+    };
+
+    /// entry -> header <-> body, header -> exit
+    fn looped() -> Function {
+        let mut func = Function::new("loop");
+
+        let n = func.push(func.entry(), Inst::Arg(0), Type::i64(0));
+        let zero = func.push(func.entry(), Inst::Const(Type::i64(0)), Type::i64(0));
+
+        let header = func.add_block();
+        let body = func.add_block();
+        let exit = func.add_block();
+
+        func.terminate(func.entry(), Terminator::Br(header));
+        func.terminate(header, Terminator::CondBr(CmpOp::Lt, zero, n, body, exit));
+        func.terminate(body, Terminator::Br(header));
+        func.terminate(exit, Terminator::Ret(Some(n)));
+
+        func
+    }
+
+    #[test]
+    fn cfg() {
+        let func = looped();
+        let (entry, header, body, exit) = (BlockId(0), BlockId(1), BlockId(2), BlockId(3));
+
+        assert_eq!(func.successors(header), vec![body, exit]);
+        assert_eq!(func.predecessors()[header.0], vec![entry, body]);
+        assert_eq!(func.reverse_postorder(), vec![entry, header, exit, body]);
+
+        let dom = DomTree::new(&func);
+
+        assert_eq!(dom.idom(entry), None);
+        assert_eq!(dom.idom(header), Some(entry));
+        assert_eq!(dom.idom(body), Some(header));
+        assert_eq!(dom.idom(exit), Some(header));
+
+        assert!(dom.dominates(header, body));
+        assert!(dom.dominates(entry, exit));
+        assert!(!dom.dominates(body, exit));
+        assert_eq!(dom.children(header), vec![body, exit]);
+    }
+
+    #[test]
+    fn diamond() {
+        let mut func = Function::new("diamond");
+
+        let a = func.push(func.entry(), Inst::Arg(0), Type::i64(0));
+        let left = func.add_block();
+        let right = func.add_block();
+        let join = func.add_block();
+
+        func.terminate(func.entry(), Terminator::CondBr(CmpOp::Eq, a, a, left, right));
+        func.terminate(left, Terminator::Br(join));
+        func.terminate(right, Terminator::Br(join));
+        func.terminate(join, Terminator::Ret(None));
+
+        let dom = DomTree::new(&func);
+
+        assert_eq!(dom.idom(join), Some(func.entry()));
+        assert!(!dom.dominates(left, join));
+    }
+
+    #[test]
+    fn invalid() -> Result<(), Box<dyn Error>> {
+        let mut func = looped();
+        func.terminate(BlockId(2), Terminator::None);
+
+        assert!(matches!(lower(&func), Err(CodeGenLibError::InvalidSsa(_))));
+
+        let mut func = Function::new("mismatch");
+        let a = func.push(func.entry(), Inst::Const(Type::i64(1)), Type::i64(0));
+        let b = func.push(func.entry(), Inst::Const(Type::i32(1)), Type::i32(0));
+        func.push(func.entry(), Inst::Binary(BinOp::Add, a, b), Type::i64(0));
+        func.terminate(func.entry(), Terminator::Ret(None));
+
+        assert!(matches!(lower(&func), Err(CodeGenLibError::TypeMismatch(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn define_ssa() -> Result<(), Box<dyn Error>> {
+        let mut builder = Builder::new();
+
+        builder.define_ssa(&looped(), true)?;
+        builder.write("tmp/ssa.o", BinFormat::Elf)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, target_os = "linux", target_arch = "x86_64"))]
+mod jit_tests {
+    use std::error::Error;
+
+    use CodeGenLib::{
+        ir::Type, ssa::{BinOp, CmpOp, Function, Inst, Terminator}, target::{linux::LinuxAbi, Abi}, Builder,
+    };
+
+    use super::common::{compile_linked, executable};
+
+    fn jit(func: &Function) -> Result<extern "sysv64" fn(i64, i64) -> i64, Box<dyn Error>> {
+        let abi = Abi::linux();
+        let code = compile_linked(&func.name, func.compile(&abi)?, &mut Builder::new(), &abi)?;
+
+        Ok(unsafe { std::mem::transmute::<*const u8, extern "sysv64" fn(i64, i64) -> i64>(executable(&code)) })
+    }
+
+    fn int() -> Type {
+        Type::i64(0)
+    }
+
+    #[test]
+    fn max() -> Result<(), Box<dyn Error>> {
+        // the edge from the entry to the join block is critical
+        let mut func = Function::new("max");
+        let entry = func.entry();
+
+        let a = func.push(entry, Inst::Arg(0), int());
+        let b = func.push(entry, Inst::Arg(1), int());
+
+        let smaller = func.add_block();
+        let join = func.add_block();
+
+        func.terminate(entry, Terminator::CondBr(CmpOp::Ge, a, b, join, smaller));
+        func.terminate(smaller, Terminator::Br(join));
+
+        let max = func.push(join, Inst::Phi(vec![(entry, a), (smaller, b)]), int());
+        func.terminate(join, Terminator::Ret(Some(max)));
+
+        let max = jit(&func)?;
+
+        assert_eq!(max(3, 7), 7);
+        assert_eq!(max(7, 3), 7);
+        assert_eq!(max(-1, -1), -1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fibonacci() -> Result<(), Box<dyn Error>> {
+        // the phis of a and b need to be copied at the same time
+        let mut func = Function::new("fib");
+        let entry = func.entry();
+
+        let n = func.push(entry, Inst::Arg(0), int());
+        let zero = func.push(entry, Inst::Const(Type::i64(0)), int());
+        let one = func.push(entry, Inst::Const(Type::i64(1)), int());
+
+        let header = func.add_block();
+        let body = func.add_block();
+        let exit = func.add_block();
+
+        func.terminate(entry, Terminator::Br(header));
+
+        let i = func.push(header, Inst::Phi(vec![]), int());
+        let a = func.push(header, Inst::Phi(vec![]), int());
+        let b = func.push(header, Inst::Phi(vec![]), int());
+        func.terminate(header, Terminator::CondBr(CmpOp::Lt, i, n, body, exit));
+
+        let c = func.push(body, Inst::Binary(BinOp::Add, a, b), int());
+        let next = func.push(body, Inst::Binary(BinOp::Add, i, one), int());
+        func.terminate(body, Terminator::Br(header));
+
+        func.blocks[header.0].insts[0].1 = Inst::Phi(vec![(entry, zero), (body, next)]);
+        func.blocks[header.0].insts[1].1 = Inst::Phi(vec![(entry, zero), (body, b)]);
+        func.blocks[header.0].insts[2].1 = Inst::Phi(vec![(entry, one), (body, c)]);
+
+        func.terminate(exit, Terminator::Ret(Some(a)));
+
+        let fib = jit(&func)?;
+
+        assert_eq!(fib(0, 0), 0);
+        assert_eq!(fib(1, 0), 1);
+        assert_eq!(fib(10, 0), 55);
+        assert_eq!(fib(50, 0), 12586269025);
+
+        Ok(())
+    }
+}
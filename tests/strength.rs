@@ -0,0 +1,169 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use iced_x86::MemoryOperand;
+    use CodeGenLib::{ir::AsmInstructionEnum::*, opt::strength::{reduce, signed_magic, unsigned_magic}, IR::Register};
+
+    #[test]
+    fn multiplications() {
+        let lea = |scale| Lea(Register::RAX, MemoryOperand::new(Register::RAX, Register::RAX, scale, 0, 0, false, Register::None));
+
+        assert_eq!(reduce(vec![MulVal(Register::RAX, 8), Ret], false), vec![ShlVal(Register::RAX, 3), Ret]);
+        assert_eq!(reduce(vec![MulVal(Register::EAX, 8), Ret], false), vec![ShlVal(Register::EAX, 3), Ret]);
+        assert_eq!(reduce(vec![MulVal(Register::RAX, 10), Ret], false), vec![lea(4), ShlVal(Register::RAX, 1), Ret]);
+        assert_eq!(reduce(vec![MulVal(Register::RAX, 45), Ret], false), vec![lea(4), lea(8), Ret]);
+
+        // only a single lea when optimizing for size
+        assert_eq!(reduce(vec![MulVal(Register::RAX, 45), Ret], true), vec![MulVal(Register::RAX, 45), Ret]);
+
+        // lea doesn't clear the upper half of 32 bit registers
+        assert_eq!(reduce(vec![MulVal(Register::EAX, 3), Ret], false), vec![MulVal(Register::EAX, 3), Ret]);
+
+        // the shift sets other flags than imul
+        let code = vec![MulVal(Register::RAX, 8), Jb("overflow".into())];
+        assert_eq!(reduce(code.to_owned(), false), code);
+    }
+
+    #[test]
+    fn divisions() {
+        assert_eq!(reduce(vec![UDivVal(Register::RAX, 16)], false), vec![ShrVal(Register::RAX, 4)]);
+        assert_eq!(reduce(vec![URemVal(Register::RAX, 16)], false), vec![ShlVal(Register::RAX, 60), ShrVal(Register::RAX, 60)]);
+
+        let reduced = reduce(vec![DivVal(Register::RCX, 7)], false);
+        assert!(!reduced.iter().any(|instr| matches!(instr, DivVal(..))), "{reduced:?}");
+        assert!(reduced.contains(&Imul(Register::R11)));
+
+        // invalid divisions are left to the verifier
+        for instr in [DivVal(Register::RAX, 0), UDivVal(Register::EAX, -1), RemVal(Register::EAX, i64::MAX)] {
+            assert_eq!(reduce(vec![instr.to_owned()], false), vec![instr]);
+        }
+
+        // r11 is the temporary register of the reduced code
+        for instr in [DivVal(Register::R11, 8), RemVal(Register::R11D, 8), DivVal(Register::R11, 7), UDivVal(Register::R11D, 7)] {
+            assert_eq!(reduce(vec![instr.to_owned()], false), vec![instr]);
+        }
+    }
+
+    #[test]
+    fn magic_numbers() {
+        assert_eq!(unsigned_magic(3), (0xAAAA_AAAA_AAAA_AAAB, 1, false));
+        assert_eq!(unsigned_magic(7), (0x2492_4924_9249_2493, 2, true));
+        assert_eq!(signed_magic(3), (0x5555_5555_5555_5556, 0));
+        assert_eq!(signed_magic(7), (0x4924_9249_2492_4925, 1));
+
+        // the quotients match the division for the edge cases of the dividend
+        let dividends = [0, 1, 2, 6, 7, 8, 1 << 32, i64::MAX as u64 - 1, i64::MAX as u64, 1 << 63, u64::MAX - 1, u64::MAX];
+
+        for divisor in [3u64, 5, 6, 7, 10, 11, 25, 100, 641, 1_000_000_007, u32::MAX as u64, (1 << 63) + 1, u64::MAX] {
+            let (multiplier, shift, add) = unsigned_magic(divisor);
+
+            for dividend in dividends.iter().copied().chain([divisor - 1, divisor, divisor.wrapping_add(1)]) {
+                let high = ((dividend as u128 * multiplier as u128) >> 64) as u64;
+                let quotient = if add { (high + ((dividend - high) >> 1)) >> shift } else { high >> shift };
+
+                assert_eq!(quotient, dividend / divisor, "{dividend} / {divisor}");
+            }
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux", target_arch = "x86_64"))]
+mod jit_tests {
+    use std::error::Error;
+
+    use CodeGenLib::{ir::AsmInstructionEnum::{self, *}, opt::strength::reduce, resolve, IR::Register};
+
+    use super::common::executable;
+
+    /// Compiles `fn(x) -> x op value` (in rcx, so rax and rdx are clobbered in between)
+    fn compile(op: fn(Register, i64) -> AsmInstructionEnum, value: i64, wide: bool, reduced: bool) -> Result<extern "sysv64" fn(i64) -> i64, Box<dyn Error>> {
+        let (reg, arg) = if wide { (Register::RCX, Register::RDI) } else { (Register::ECX, Register::EDI) };
+
+        let mut code = vec![MovReg(reg, arg), op(reg, value), MovReg(Register::RAX, Register::RCX), Ret];
+
+        if reduced {
+            code = reduce(code, false);
+        }
+
+        let resolved = resolve(vec!["strength".into()], vec![], &code)?;
+
+        Ok(unsafe { std::mem::transmute::<*const u8, extern "sysv64" fn(i64) -> i64>(executable(&resolved.code)) })
+    }
+
+    fn dividends() -> Vec<i64> {
+        let mut dividends = vec![0, 1, -1, 2, -2, 7, -7, 100, -100, 12345, -12345, 1 << 31, -(1 << 31), i32::MAX as i64, u32::MAX as i64];
+        dividends.extend([1 << 40, -(1 << 40), i64::MAX, i64::MIN, i64::MIN + 1, 0x1234_5678_9ABC_DEF0]);
+        dividends.extend((-300..300).step_by(7));
+        dividends
+    }
+
+    const DIVISORS: &[i64] = &[1, -1, 2, -2, 3, -3, 5, 7, -7, 8, 10, -16, 25, 100, 641, 1 << 31, -(1 << 31), i32::MAX as i64, 1_000_000_007];
+
+    #[test]
+    fn divisions() -> Result<(), Box<dyn Error>> {
+        for &divisor in DIVISORS {
+            for reduced in [false, true] {
+                let div = compile(DivVal, divisor, true, reduced)?;
+                let rem = compile(RemVal, divisor, true, reduced)?;
+                let udiv = compile(UDivVal, divisor, true, reduced)?;
+                let urem = compile(URemVal, divisor, true, reduced)?;
+
+                for x in dividends() {
+                    // i64::MIN / -1 traps in idiv
+                    if reduced || divisor != -1 || x != i64::MIN {
+                        assert_eq!(div(x), x.wrapping_div(divisor), "{x} / {divisor} (reduced: {reduced})");
+                        assert_eq!(rem(x), x.wrapping_rem(divisor), "{x} % {divisor} (reduced: {reduced})");
+                    }
+
+                    assert_eq!(udiv(x), (x as u64 / divisor as u64) as i64, "{x} / {divisor} unsigned (reduced: {reduced})");
+                    assert_eq!(urem(x), (x as u64 % divisor as u64) as i64, "{x} % {divisor} unsigned (reduced: {reduced})");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn divisions32() -> Result<(), Box<dyn Error>> {
+        for &divisor in DIVISORS.iter().filter(|divisor| i32::try_from(**divisor).is_ok()) {
+            for reduced in [false, true] {
+                let div = compile(DivVal, divisor, false, reduced)?;
+                let rem = compile(RemVal, divisor, false, reduced)?;
+                let udiv = compile(UDivVal, divisor as u32 as i64, false, reduced)?;
+                let urem = compile(URemVal, divisor as u32 as i64, false, reduced)?;
+
+                for x in dividends().into_iter().map(|x| x as i32) {
+                    if reduced || divisor != -1 || x != i32::MIN {
+                        assert_eq!(div(x as i64), x.wrapping_div(divisor as i32) as u32 as i64, "{x} / {divisor} (reduced: {reduced})");
+                        assert_eq!(rem(x as i64), x.wrapping_rem(divisor as i32) as u32 as i64, "{x} % {divisor} (reduced: {reduced})");
+                    }
+
+                    let (x, divisor) = (x as u32, divisor as u32);
+                    assert_eq!(udiv(x as i64), (x / divisor) as i64, "{x} / {divisor} unsigned (reduced: {reduced})");
+                    assert_eq!(urem(x as i64), (x % divisor) as i64, "{x} % {divisor} unsigned (reduced: {reduced})");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn multiplications() -> Result<(), Box<dyn Error>> {
+        for value in [2, 3, 5, 8, 9, 10, 12, 15, 18, 25, 27, 40, 45, 81, 1 << 20, 1 << 30, 7, -4] {
+            for wide in [true, false] {
+                let mul = compile(MulVal, value, wide, true)?;
+
+                for x in dividends() {
+                    let expected = if wide { x.wrapping_mul(value) } else { (x as i32).wrapping_mul(value as i32) as u32 as i64 };
+
+                    assert_eq!(mul(x), expected, "{x} * {value} (wide: {wide})");
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+mod common;
+
+#[cfg(all(test, target_os = "linux", target_arch = "x86_64"))]
+mod tests {
+    use std::{error::Error, ffi::{c_char, c_int, c_void, CString}, io::ErrorKind, process::Command};
+
+    use CodeGenLib::{ir::{AsmInstructionEnum::*, IrBuilder, IrFunctionBuilder, Type}, target::{linux::LinuxAbi, Abi, Target}, Builder};
+
+    use super::common::{compile_linked, executable};
+
+    /// Builds a function which returns `value * 10` of the matching case (or -1 for the default case)
+    fn switch(typ: Type, cases: &[i64]) -> Result<(Vec<u8>, Vec<CodeGenLib::ir::AsmInstructionEnum>), Box<dyn Error>> {
+        let abi = Abi::linux();
+        let mut builder = Builder::new();
+        let mut func = IrFunctionBuilder::new("switch", &mut builder, &abi);
+
+        func.args(vec![("x", typ)]);
+        func.vars(vec![("y", Type::i64(0))]);
+
+        let names: Vec<String> = (0..cases.len()).map(|nr| format!("case{nr}")).collect();
+
+        func.build_switch("x", cases.iter().zip(names.iter()).map(|(value, name)| (*value, name.as_str())).collect(), "default")?;
+
+        for (value, name) in cases.iter().zip(names.iter()) {
+            func.build_label(name);
+            func.build_set("y", Type::i64(value.wrapping_mul(10)))?;
+            func.build_jmp("end");
+        }
+
+        func.build_label("default");
+        func.build_set("y", Type::i64(-1))?;
+
+        func.build_label("end");
+        func.build_return_var("y")?;
+
+        let ir = func.generated.to_owned();
+        let code = compile_linked("switch", func.generated, &mut func.builder, &abi)?;
+
+        Ok((code, ir))
+    }
+
+    #[test]
+    fn dense() -> Result<(), Box<dyn Error>> {
+        let cases = [3, 4, 5, 7, 8, 10];
+        let (code, ir) = switch(Type::i64(0), &cases)?;
+
+        assert!(ir.iter().any(|instr| matches!(instr, JmpReg(_))));
+
+        let func: extern "sysv64" fn(i64) -> i64 = unsafe { std::mem::transmute(executable(&code)) };
+
+        for value in -5..15 {
+            let expected = if cases.contains(&value) { value * 10 } else { -1 };
+
+            assert_eq!(func(value), expected);
+        }
+
+        assert_eq!(func(i64::MIN + 3), -1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn sparse() -> Result<(), Box<dyn Error>> {
+        let cases = [-1000, 3, 77, 9999, 1 << 40, -(1 << 40), 12];
+        let (code, ir) = switch(Type::i64(0), &cases)?;
+
+        assert!(!ir.iter().any(|instr| matches!(instr, JmpReg(_))));
+
+        let func: extern "sysv64" fn(i64) -> i64 = unsafe { std::mem::transmute(executable(&code)) };
+
+        for value in cases {
+            assert_eq!(func(value), value.wrapping_mul(10));
+        }
+
+        for value in [0, -999, 13, 1 << 41, i64::MAX, i64::MIN] {
+            assert_eq!(func(value), -1);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn dense_i32() -> Result<(), Box<dyn Error>> {
+        let cases = [-2, -1, 0, 1];
+        let (code, _) = switch(Type::i32(0), &cases)?;
+
+        let func: extern "sysv64" fn(i32) -> i64 = unsafe { std::mem::transmute(executable(&code)) };
+
+        for value in -4..4 {
+            let expected = if cases.contains(&(value as i64)) { value as i64 * 10 } else { -1 };
+
+            assert_eq!(func(value), expected);
+        }
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[link(name = "dl")]
+    extern "C" {
+        fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+        fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn shared_object() -> Result<(), Box<dyn Error>> {
+        let mut builder = IrBuilder::new(Target::host());
+
+        let func = builder.add("pick");
+        func.args(vec![("x", Type::i64(0))]);
+        func.vars(vec![]);
+        func.set_public();
+
+        let cases = ["zero", "one", "two", "three"];
+        func.build_switch("x", cases.iter().enumerate().map(|(value, name)| (value as i64, *name)).collect(), "default")?;
+
+        for (value, name) in cases.iter().enumerate() {
+            func.build_label(name);
+            func.build_return_int(value as i64 * 10)?;
+        }
+
+        func.build_label("default");
+        func.build_return_int(-1)?;
+
+        builder.write("tmp/switch_shared.o")?;
+
+        // the public function can be replaced, so its address goes through the plt
+        let status = match Command::new("cc").args(["-shared", "-o", "tmp/switch_shared.so", "tmp/switch_shared.o"]).status() {
+            Ok(status) => status,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                eprintln!("skipping shared_object: no cc to link the shared object");
+                return Ok(());
+            },
+            Err(err) => return Err(err.into()),
+        };
+        assert!(status.success());
+
+        let path = CString::new(std::fs::canonicalize("tmp/switch_shared.so")?.to_str().unwrap())?;
+        let name = CString::new("pick")?;
+
+        let pick: extern "sysv64" fn(i64) -> i64 = unsafe {
+            let lib = dlopen(path.as_ptr(), 2); // RTLD_NOW
+            assert!(!lib.is_null());
+
+            let pick = dlsym(lib, name.as_ptr());
+            assert!(!pick.is_null());
+
+            std::mem::transmute(pick)
+        };
+
+        for value in -2..6 {
+            assert_eq!(pick(value), if (0..4).contains(&value) { value * 10 } else { -1 });
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_case() {
+        let abi = Abi::linux();
+        let mut builder = Builder::new();
+        let mut func = IrFunctionBuilder::new("switch", &mut builder, &abi);
+
+        func.args(vec![("x", Type::u64(0))]);
+        func.vars(vec![]);
+
+        assert!(func.build_switch("x", vec![(1, "a"), (1, "b")], "default").is_err());
+    }
+}
@@ -0,0 +1,184 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use CodeGenLib::{
+        attrs::FuncAttrs, error::CodeGenLibError, ir::{AsmInstructionEnum::{self, *}, IrFunctionBuilder, Type},
+        opt::{lower_frame, tail::tail_calls, PassContext}, target::{linux::LinuxAbi, Abi}, Builder, IR::Register,
+    };
+
+    fn run(code: Vec<AsmInstructionEnum>, stack_args: usize) -> Vec<AsmInstructionEnum> {
+        tail_calls(code, &PassContext { name: "test", abi: &Abi::linux(), attrs: &FuncAttrs::default(), stack_args, remarks: Default::default() }).unwrap()
+    }
+
+    #[test]
+    fn calls() {
+        let abi = Abi::linux();
+
+        assert_eq!(run(vec![MovVal(Register::RDI, 1), Call("other".into()), Ret], 0), vec![MovVal(Register::RDI, 1), Jmp("other".into())]);
+
+        // the result is used, the callee is a label of the function or could use the frame
+        for code in [
+            vec![Call("other".into()), Store(Register::RAX, abi.stack(-8)), Ret],
+            vec![Label("local".into()), Call("local".into()), Ret],
+            vec![Lea(Register::RDI, abi.stack(-8)), Call("other".into()), Ret],
+        ] {
+            assert_eq!(run(code.to_owned(), 0), code);
+        }
+    }
+
+    #[test]
+    fn stack_args() {
+        let abi = Abi::linux();
+
+        // one stack argument and 8 bytes padding
+        let code = vec![SubVal(Register::RSP, 8), Push(Register::RAX), Call("other".into()), AddVal(Register::RSP, 16), Ret];
+
+        assert_eq!(run(code.to_owned(), 1), vec![
+            SubVal(Register::RSP, 8),
+            Push(Register::RAX),
+            Load(Register::R11, abi.ptr(Register::RSP, 0)),
+            Store(Register::R11, abi.stack(abi.stack_arg(0))),
+            AddVal(Register::RSP, 16),
+            Jmp("other".into()),
+        ]);
+
+        // it doesn't fit into the incoming argument area
+        assert_eq!(run(code.to_owned(), 0), code);
+
+        // two stack arguments
+        let code = vec![PushVal(2), PushVal(1), Call("other".into()), AddVal(Register::RSP, 16), Ret];
+
+        assert_eq!(run(code.to_owned(), 2).len(), 8);
+        assert_eq!(run(code.to_owned(), 1), code);
+
+        // the stack pointer doesn't match the pushed arguments
+        let code = vec![Push(Register::RAX), Call("other".into()), AddVal(Register::RSP, 8), Ret];
+        assert_eq!(run(code.to_owned(), 1), code);
+    }
+
+    #[test]
+    fn epilogue() -> Result<(), Box<dyn Error>> {
+        let attrs = FuncAttrs { keep_frame_pointer: true, ..Default::default() };
+
+        assert_eq!(lower_frame(vec![Jmp("other".into())], &Abi::linux(), 0, &attrs)?, vec![
+            Push(Register::RBP),
+            MovReg(Register::RBP, Register::RSP),
+            Pop(Register::RBP),
+            Jmp("other".into()),
+        ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn guaranteed() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+        let mut builder = Builder::new();
+
+        let impossible = |result: Result<(), Box<dyn Error>>| {
+            matches!(result.err().and_then(|err| err.downcast::<CodeGenLibError>().ok()).as_deref(), Some(CodeGenLibError::TailCallImpossible(_)))
+        };
+
+        let mut func = IrFunctionBuilder::new("test", &mut builder, &abi);
+        func.args(vec![("x", Type::u64(0))]);
+        func.vars(vec![("y", Type::u64(0))]);
+        func.efuncs(vec![("other", vec![Type::u64(0)]), ("many", vec![Type::u64(0); 7])]);
+
+        // the callee would get a pointer into the removed frame
+        assert!(impossible(func.build_tail_call("other", vec![Type::Ptr(Box::from(Type::InVar("y".into())))])));
+
+        // the seventh argument doesn't fit into the incoming argument area
+        assert!(impossible(func.build_tail_call("many", vec![Type::u64(1); 7])));
+
+        func.build_tail_call("other", vec![Type::InVar("x".into())])?;
+        assert_eq!(func.generated.last(), Some(&Jmp("other".into())));
+
+        // the return value is written into the memory of the caller
+        let mut func = IrFunctionBuilder::new("large", &mut builder, &abi);
+        func.set_ret(Type::Bytes(vec![0; 32]));
+        func.vars(vec![]);
+        func.efuncs(vec![("other", vec![Type::u64(0)])]);
+
+        assert!(impossible(func.build_tail_call("other", vec![Type::u64(1)])));
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, target_os = "linux", target_arch = "x86_64"))]
+mod jit_tests {
+    use std::error::Error;
+
+    use CodeGenLib::{ir::{IrFunctionBuilder, Type}, target::{linux::LinuxAbi, Abi}, Builder, IR::*};
+
+    use super::common::{compile_linked, executable};
+
+    #[test]
+    fn deep_recursion() -> Result<(), Box<dyn Error>> {
+        // sum(n, acc) = n == 0 ? acc : sum(n - 1, acc + n) doesn't fit onto the stack as real calls
+        let code = compile_linked("sum", vec![
+            CmpVal(Register::RDI, 0),
+            Je("done".into()),
+            AddReg(Register::RSI, Register::RDI),
+            Dec(Register::RDI),
+            Call("sum".into()),
+            Ret,
+            Label("done".into()),
+            MovReg(Register::RAX, Register::RSI),
+            Ret,
+        ], &mut Builder::new(), &Abi::linux())?;
+
+        let sum = unsafe { std::mem::transmute::<*const u8, extern "sysv64" fn(u64, u64) -> u64>(executable(&code)) };
+
+        assert_eq!(sum(10_000_000, 0), 10_000_000 * 10_000_001 / 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn stack_args() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+        let mut builder = Builder::new();
+        let mut func = IrFunctionBuilder::new("rotate", &mut builder, &abi);
+
+        let names = ["a", "b", "c", "d", "e", "f", "g"];
+
+        // rotate(n, a, ..., g) = n == 0 ? 8 * f + g : rotate(n - 1, g, a, ..., f) (f and g are passed on the stack)
+        func.args([("n", Type::i64(0))].into_iter().chain(names.iter().map(|name| (*name, Type::i64(0)))).collect());
+        func.vars(vec![("one", Type::i64(0)), ("next", Type::i64(0)), ("result", Type::i64(0))]);
+        func.efuncs(vec![("rotate", vec![Type::i64(0); 8])]);
+
+        func.build_switch("n", vec![(0, "done")], "again")?;
+
+        func.build_label("again");
+        func.build_set("one", Type::i64(-1))?;
+        func.build_add("n", "one", "next")?;
+
+        let rotated = ["g", "a", "b", "c", "d", "e", "f"];
+        func.build_tail_call("rotate", [Type::InVar("next".into())].into_iter().chain(rotated.iter().map(|name| Type::InVar(name.to_string()))).collect())?;
+
+        func.build_label("done");
+        func.build_add("f", "f", "result")?;
+        func.build_add("result", "result", "result")?;
+        func.build_add("result", "result", "result")?;
+        func.build_add("result", "g", "result")?;
+        func.build_return_var("result")?;
+
+        let code = compile_linked("rotate", func.generated, &mut func.builder, &abi)?;
+        let rotate = unsafe { std::mem::transmute::<*const u8, extern "sysv64" fn(i64, i64, i64, i64, i64, i64, i64, i64) -> i64>(executable(&code)) };
+
+        // every argument is in the place of f and g once
+        for n in 0..7i64 {
+            let (f, g) = ((5 - n).rem_euclid(7) + 1, (6 - n).rem_euclid(7) + 1);
+
+            assert_eq!(rotate(n, 1, 2, 3, 4, 5, 6, 7), 8 * f + g, "rotated {n} times");
+        }
+
+        assert_eq!(rotate(7 * 100_000, 1, 2, 3, 4, 5, 6, 7), 8 * 6 + 7);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,226 @@
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use gimli::{BaseAddresses, CfaRule, EhFrame, LittleEndian, RegisterRule, UnwindContext, UnwindSection, X86_64};
+    use object::{Object, ObjectSection};
+    use CodeGenLib::{
+        attrs::FuncAttrs, ir::{AsmInstructionEnum::{self, *}, Type}, opt::frame_size, resolve,
+        target::{linux::LinuxAbi, windows::WindowsAbi, Abi}, unwind::{unwind_info, unwind_ops, UnwindOp}, BinFormat, Builder, optimize, IR::Register,
+    };
+
+    fn func(builder: &mut Builder) -> Result<(), Box<dyn Error>> {
+        let abi = builder.abi.to_owned();
+
+        builder.define("func", true, vec![
+            MovVal(Register::RBX, 1),
+            Store(Register::RBX, abi.stack(-8)),
+            Ret,
+        ])?;
+        builder.define_vars("func", vec![("x".into(), -8, Type::u64(0))]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn ops() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+        let code = vec![MovVal(Register::RBX, 1), Ret];
+
+        let frame = frame_size(&code, None, &abi);
+        let ir = optimize(code, &abi, frame, &FuncAttrs { keep_frame_pointer: true, ..Default::default() })?;
+        let resolved = resolve(vec![], vec![], &ir)?;
+
+        let ops: Vec<UnwindOp> = unwind_ops(&ir, &resolved.offsets, resolved.code.len()).into_iter().map(|op| op.1).collect();
+
+        assert_eq!(ops, vec![
+            UnwindOp::PushFrame,
+            UnwindOp::SetFrame(0),
+            UnwindOp::Alloc(16),
+            UnwindOp::Save(Register::RBX, -8),
+            UnwindOp::Epilogue,
+        ]);
+
+        // an epilogue in the middle of the function
+        let code = vec![Push(Register::RBP), MovReg(Register::RBP, Register::RSP), Pop(Register::RBP), Ret, Pop(Register::RBP), Ret];
+        let ops: Vec<UnwindOp> = unwind_ops(&code, &[0, 1, 4, 5, 6, 7], 8).into_iter().map(|op| op.1).collect();
+
+        assert_eq!(ops, vec![UnwindOp::PushFrame, UnwindOp::SetFrame(0), UnwindOp::Epilogue, UnwindOp::EpilogueEnd, UnwindOp::Epilogue]);
+
+        // leaf functions without a frame pointer
+        assert!(unwind_ops(&[Ret], &[0], 1).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn leaf() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::linux();
+        let ops = |frame: i64| -> Result<Vec<UnwindOp>, Box<dyn Error>> {
+            let code = vec![MovVal(Register::RBX, 1), Store(Register::RBX, abi.stack(-frame)), Ret];
+            let ir = optimize(code, &abi, frame, &FuncAttrs::default())?;
+            let resolved = resolve(vec![], vec![], &ir)?;
+
+            Ok(unwind_ops(&ir, &resolved.offsets, resolved.code.len()).into_iter().map(|op| op.1).collect())
+        };
+
+        // rbx gets saved into the red zone (the displacement is relative to where rbp would be)
+        assert_eq!(ops(16)?, vec![UnwindOp::Save(Register::RBX, -24)]);
+
+        // the frame doesn't fit into the red zone
+        assert_eq!(ops(256)?, vec![UnwindOp::Alloc(272), UnwindOp::Save(Register::RBX, -264), UnwindOp::Epilogue]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn eh_frame() -> Result<(), Box<dyn Error>> {
+        let mut builder = Builder::new();
+        builder.abi = Abi::linux();
+        builder.passes.keep_frame_pointers = true;
+        func(&mut builder)?;
+
+        builder.write("tmp/unwind.o", BinFormat::Elf)?;
+
+        let data = std::fs::read("tmp/unwind.o")?;
+        let file = object::File::parse(&*data)?;
+        let section = file.section_by_name(".eh_frame").expect("no .eh_frame section");
+        let section = section.data()?;
+
+        let eh_frame = EhFrame::new(section, LittleEndian);
+        let bases = BaseAddresses::default().set_eh_frame(0);
+        let mut ctx = UnwindContext::new();
+
+        let mut entries = eh_frame.entries(&bases);
+        let mut rows = vec![];
+
+        while let Some(entry) = entries.next()? {
+            if let gimli::CieOrFde::Fde(partial) = entry {
+                let fde = partial.parse(EhFrame::cie_from_offset)?;
+                let mut table = fde.rows(&eh_frame, &bases, &mut ctx)?;
+
+                while let Some(row) = table.next_row()? {
+                    rows.push((row.cfa().to_owned(), row.register(X86_64::RBX)));
+                }
+            }
+        }
+
+        let cfa: Vec<CfaRule<usize>> = rows.iter().map(|row| row.0.to_owned()).collect();
+
+        assert_eq!(cfa, vec![
+            CfaRule::RegisterAndOffset { register: X86_64::RSP, offset: 8 },
+            CfaRule::RegisterAndOffset { register: X86_64::RSP, offset: 16 },
+            CfaRule::RegisterAndOffset { register: X86_64::RBP, offset: 16 },
+            CfaRule::RegisterAndOffset { register: X86_64::RBP, offset: 16 }, // rbx got saved
+            CfaRule::RegisterAndOffset { register: X86_64::RSP, offset: 8 },
+        ]);
+
+        assert_eq!(rows[3].1, RegisterRule::Offset(-40));
+
+        Ok(())
+    }
+
+    #[test]
+    fn pdata_xdata() -> Result<(), Box<dyn Error>> {
+        let xdata = |keep_frame_pointers: bool| -> Result<Vec<u8>, Box<dyn Error>> {
+            let mut builder = Builder::new();
+            builder.abi = Abi::windows();
+            builder.passes.keep_frame_pointers = keep_frame_pointers;
+            func(&mut builder)?;
+
+            builder.write("tmp/unwind.obj", BinFormat::Coff)?;
+
+            let data = std::fs::read("tmp/unwind.obj")?;
+            let file = object::File::parse(&*data)?;
+
+            let pdata = file.section_by_name(".pdata").expect("no .pdata section");
+            assert_eq!(pdata.size(), 12);
+            assert_eq!(pdata.relocations().count(), 3);
+
+            Ok(file.section_by_name(".xdata").expect("no .xdata section").data()?.to_vec())
+        };
+
+        assert_eq!(xdata(true)?, [
+            1, 17, 5, 0x25, // version, prolog size, code count, rbp with an offset of 32
+            17, 0x34, 1, 0, // mov [rsp + 8], rbx
+            13, 0x03, // lea rbp, [rsp + 32]
+            8, 0x32, // sub rsp, 32
+            1, 0x50, // push rbp
+            0, 0,
+        ]);
+
+        // the leaf function has no frame register
+        assert_eq!(xdata(false)?, [
+            1, 12, 3, 0,
+            12, 0x34, 0, 0, // mov [rsp], rbx
+            7, 0x32, // sub rsp, 32
+            0, 0,
+        ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn win64_saves() -> Result<(), Box<dyn Error>> {
+        let abi = Abi::windows();
+        let info = |frame: i64| -> Result<(Vec<AsmInstructionEnum>, Vec<u8>), Box<dyn Error>> {
+            let code = vec![MovVal(Register::RSI, 1), MovVal(Register::RDI, 2), Load(Register::XMM6, abi.stack(-frame)), Ret];
+            let ir = optimize(code, &abi, frame, &FuncAttrs { keep_frame_pointer: true, ..Default::default() })?;
+            let resolved = resolve(vec![], vec![], &ir)?;
+
+            let info = unwind_info(&unwind_ops(&ir, &resolved.offsets, resolved.code.len()));
+            Ok((ir, info))
+        };
+
+        // rsi, rdi and xmm6 are saved at rsp + 24, 16 and 0 (rbp is 48 bytes above rsp)
+        let (ir, xdata) = info(16)?;
+
+        assert_eq!(&ir[..7], [
+            Push(Register::RBP),
+            SubVal(Register::RSP, 48),
+            Lea(Register::RBP, abi.ptr(Register::RSP, 48)),
+            Store(Register::RSI, abi.stack(-24)),
+            Store(Register::RDI, abi.stack(-32)),
+            Store(Register::XMM6, abi.stack(-48)),
+            MovVal(Register::RSI, 1),
+        ]);
+
+        assert_eq!(xdata, [
+            1, 26, 9, 0x35,
+            26, 0x68, 0, 0, // xmm6 at rsp + 0 (scaled by 16)
+            21, 0x74, 2, 0, // rdi at rsp + 16 (scaled by 8)
+            17, 0x64, 3, 0, // rsi at rsp + 24
+            13, 0x03, // lea rbp, [rsp + 48]
+            8, 0x52, // sub rsp, 48
+            1, 0x50, // push rbp
+            0, 0,
+        ]);
+
+        // rbp is 240 bytes above rsp, the displacements move with it
+        let (ir, xdata) = info(1024)?;
+
+        assert_eq!(ir[2], Lea(Register::RBP, abi.ptr(Register::RSP, 240)));
+        assert_eq!(ir[3], Store(Register::RSI, abi.stack(-1032 + 816)));
+        assert_eq!(xdata[3], 0xF5);
+        // the registers are at the same offsets from rsp
+        assert_eq!([&xdata[5..8], &xdata[9..12], &xdata[13..16]], [[0x68, 0, 0], [0x74, 2, 0], [0x64, 3, 0]]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn disabled() -> Result<(), Box<dyn Error>> {
+        let mut builder = Builder::new();
+        builder.unwind = false;
+        func(&mut builder)?;
+
+        builder.write("tmp/no_unwind.o", BinFormat::Elf)?;
+
+        let data = std::fs::read("tmp/no_unwind.o")?;
+        let file = object::File::parse(&*data)?;
+
+        assert!(file.section_by_name(".eh_frame").is_none());
+
+        Ok(())
+    }
+}
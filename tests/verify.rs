@@ -0,0 +1,143 @@
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use CodeGenLib::{attrs::FuncAttrs, ir::{verify::verify_function, AsmInstructionEnum::*, Type}, target::{linux::LinuxAbi, Abi}, Builder, IR::Register};
+
+    #[test]
+    fn valid() -> Result<(), Box<dyn Error>> {
+        let code = vec![
+            Load(Register::RAX, Abi::linux().stack(-8)),
+            Call("callme".into()),
+            Ret,
+        ];
+
+        let violations = verify_function(
+            "test",
+            &code,
+            &["test".into()],
+            &[],
+            &["callme".into()],
+            Some(&vec![("x".into(), -8, Type::u64(0))]),
+            &FuncAttrs::default(),
+        );
+
+        assert_eq!(violations, vec![]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn violations() -> Result<(), Box<dyn Error>> {
+        let code = vec![
+            MovReg(Register::RAX, Register::EBX),
+            Load(Register::RAX, Abi::linux().stack(-16)),
+            Push(Register::RAX),
+            Call("callme".into()),
+            Ret,
+            Nop,
+        ];
+
+        let violations = verify_function(
+            "test",
+            &code,
+            &["test".into()],
+            &[],
+            &[],
+            Some(&vec![("x".into(), -8, Type::u64(0))]),
+            &FuncAttrs::default(),
+        );
+
+        let indexes: Vec<usize> = violations.iter().map(|violation| violation.index).collect();
+
+        // width, undeclared variable, undefined symbol, unreachable code, unbalanced push
+        assert_eq!(indexes, vec![0, 1, 3, 5, 4]);
+        assert!(violations.iter().all(|violation| violation.func == "test"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn labels() -> Result<(), Box<dyn Error>> {
+        let code = vec![
+            CmpVal(Register::RAX, 1),
+            Je("one".into()),
+            Jne("missing".into()),
+            Ret,
+            Label("one".into()),
+            Ret,
+            Label("one".into()),
+            Ret,
+        ];
+
+        let violations = verify_function("test", &code, &["test".into()], &[], &[], None, &FuncAttrs::default());
+
+        let indexes: Vec<usize> = violations.iter().map(|violation| violation.index).collect();
+
+        // undefined label, duplicate label
+        assert_eq!(indexes, vec![2, 6]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unreachable_runs() -> Result<(), Box<dyn Error>> {
+        let code = vec![
+            Ret,
+            Nop,
+            Nop,
+            Label("a".into()),
+            Ret,
+            PushVal(1 << 31),
+            AddVal(Register::RSP, 8),
+            Ret,
+        ];
+
+        let violations = verify_function("test", &code, &["test".into()], &[], &[], None, &FuncAttrs::default());
+
+        let indexes: Vec<usize> = violations.iter().map(|violation| violation.index).collect();
+
+        // immediate which isn't sign extendable, both unreachable runs
+        assert_eq!(indexes, vec![5, 1, 5]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_reports_errors() -> Result<(), Box<dyn Error>> {
+        let mut builder = Builder::new();
+
+        builder.define("test", true, vec![
+            Call("undefined".into()),
+            MovPtr(Register::RAX, "missing".into()),
+        ])?;
+
+        let err = builder.write("tmp/verify.o", CodeGenLib::BinFormat::host());
+
+        assert!(err.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn undeclared_externs() -> Result<(), Box<dyn Error>> {
+        let mut builder = Builder::new();
+
+        builder.define("test", true, vec![
+            Call("undeclared".into()),
+            Ret,
+        ])?;
+
+        // called functions need to be defined or declared by default
+        assert!(builder.write("tmp/verify_externs.o", CodeGenLib::BinFormat::host()).is_err());
+
+        builder.strict_externs = false;
+        builder.write("tmp/verify_externs.o", CodeGenLib::BinFormat::host())?;
+
+        builder.strict_externs = true;
+        builder.define_extern("undeclared");
+        builder.write("tmp/verify_externs.o", CodeGenLib::BinFormat::host())?;
+
+        Ok(())
+    }
+}